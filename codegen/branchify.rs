@@ -153,3 +153,58 @@ pub fn generate_branchified_method(
     w!(       ("}"));
     Ok(())
 }
+
+/// Prints a match expression which maps a sequence of bytes to the matching result.
+///
+/// Unlike `generate_branchified_method`, this reads nothing from a stream: it is for when the
+/// whole sequence is already at hand, as in a slice, and so there is no end marker.
+///
+/// :param branches: the branches to search through
+/// :param indent: the level of indentation (each level representing four leading spaces)
+/// :param next_call: the expression to get the next byte, evaluating to ``Option<&u8>``
+/// :param unknown: the expression for a sequence which does not match any of the branches
+pub fn generate_branchified_matcher(
+        writer: &mut Writer,
+        branches: Vec<ParseBranch>,
+        indent: usize,
+        next_call: &str,
+        unknown: &str) -> IoResult<()> {
+
+    fn r(writer: &mut Writer, branch: &ParseBranch, indent: usize, next_call: &str,
+            unknown: &str) -> IoResult<()> {
+        let indentstr = repeat(' ').take(indent * 4).collect::<String>();
+        macro_rules! w (
+            ($s:expr) => {
+                try!(write!(writer, "{}{}\n", indentstr, $s))
+            }
+        );
+        let patterns = branch.matches.iter()
+                                     .map(|&c| format!("Some(&b'{}')", c as char))
+                                     .collect::<Vec<String>>();
+        w!(format!("{} => match {} {{", patterns.connect(" | "), next_call));
+        for b in branch.children.iter() {
+            try!(r(writer, b, indent + 1, next_call, unknown));
+        }
+        match branch.result {
+            Some(ref result) => w!(format!("    None => {},", *result)),
+            None => w!(format!("    None => {},", unknown)),
+        }
+        w!(format!("    _ => {},", unknown));
+        w!("},");
+        Ok(())
+    }
+    let indentstr = repeat(' ').take(indent * 4).collect::<String>();
+    macro_rules! w (
+        ($s:expr) => {
+            try!(write!(writer, "{}{}\n", indentstr, $s))
+        }
+    );
+
+    w!(format!("match {} {{", next_call));
+    for b in branches.iter() {
+        try!(r(writer, b, indent + 1, next_call, unknown));
+    }
+    w!(format!("    _ => {},", unknown));
+    w!(       ("}"));
+    Ok(())
+}
@@ -0,0 +1,54 @@
+#![allow(deprecated)]
+
+use super::branchify::{branchify, generate_branchified_matcher};
+use super::get_writer;
+use std::old_io::IoResult;
+
+// These must be kept in the same order as the tables of the corresponding `headers_mod!`
+// invocations in src/http/headers/mod.rs; the index of a name is its row number there.
+
+static REQUEST_HEADERS: &'static [&'static str] = &[
+    "Cache-Control", "Connection", "Date", "Pragma", "Trailer", "Transfer-Encoding", "Upgrade",
+    "Via", "Warning",
+    "Accept", "Accept-Charset", "Accept-Encoding", "Accept-Language", "Authorization", "Expect",
+    "From", "Host", "If-Match", "If-Modified-Since", "If-None-Match", "If-Range",
+    "If-Unmodified-Since", "Max-Forwards", "Proxy-Authorization", "Range", "Referer", "TE",
+    "User-Agent",
+    "Allow", "Content-Encoding", "Content-Language", "Content-Length", "Content-Location",
+    "Content-MD5", "Content-Range", "Content-Type", "Expires", "Last-Modified",
+];
+
+static RESPONSE_HEADERS: &'static [&'static str] = &[
+    "Cache-Control", "Connection", "Date", "Pragma", "Trailer", "Transfer-Encoding", "Upgrade",
+    "Via", "Warning",
+    "Accept-Patch", "Accept-Ranges", "Age", "ETag", "Location", "Proxy-Authenticate",
+    "Retry-After", "Server", "Vary", "WWW-Authenticate",
+    "Allow", "Content-Encoding", "Content-Language", "Content-Length", "Content-Location",
+    "Content-MD5", "Content-Range", "Content-Type", "Expires", "Last-Modified",
+    "Access-Control-Allow-Origin",
+];
+
+pub fn generate(output_dir: Path) -> IoResult<()> {
+    let mut writer = get_writer(output_dir, "header_names.rs");
+    try!(writer.write_all(b"\
+// This automatically generated file is included in headers/mod.rs.
+"));
+    try!(generate_index_function(&mut *writer, "request_header_index", REQUEST_HEADERS));
+    generate_index_function(&mut *writer, "response_header_index", RESPONSE_HEADERS)
+}
+
+fn generate_index_function(writer: &mut Writer, name: &str, headers: &[&str]) -> IoResult<()> {
+    let results = (0..headers.len()).map(|i| format!("Some({})", i)).collect::<Vec<String>>();
+    let options = headers.iter().zip(results.iter())
+                                .map(|(&header, result)| (header, &result[..]))
+                                .collect::<Vec<(&str, &str)>>();
+    try!(write!(writer, "
+/// The index of the named header in its table, ignoring case.
+#[inline]
+pub fn {}(name: &[u8]) -> Option<usize> {{
+    let mut bytes = name.iter();
+", name));
+    try!(generate_branchified_matcher(writer, branchify(&options[..], false), 1, "bytes.next()",
+                                      "None"));
+    writer.write_all(b"}\n")
+}
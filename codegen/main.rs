@@ -1,20 +1,17 @@
 #![feature(collections, core, old_io, old_path, env, concat_idents)]
 use std::old_io::{File, Truncate, Write};
 use std::env;
-use std::thread::spawn;
 
 pub mod branchify;
 pub mod status;
-pub mod read_method;
+pub mod header_names;
 
 fn main() {
-    spawn(move || {
-        let output_dir = Path::new(env::var("OUT_DIR").unwrap());
-        read_method::generate(output_dir).unwrap();
-    });
-
     let output_dir = Path::new(env::var("OUT_DIR").unwrap());
     status::generate(output_dir).unwrap();
+
+    let output_dir = Path::new(env::var("OUT_DIR").unwrap());
+    header_names::generate(output_dir).unwrap();
 }
 
 pub fn get_writer(mut output_dir: Path, filename: &str) -> Box<Writer + 'static> {
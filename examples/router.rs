@@ -0,0 +1,50 @@
+#![feature(collections,old_io)]
+//! A server which dispatches requests with a `Router`: try "/", "/hello/you" and "/files/a/b.txt",
+//! and other methods on them. Each request is logged by a layer of middleware, and responses are
+//! compressed if the client accepts it.
+
+#![crate_name = "router"]
+
+extern crate time;
+extern crate http;
+
+use std::old_io::net::ip::{SocketAddr, Ipv4Addr};
+use std::old_io::Writer;
+
+use http::server::{Config, Server, Request, ResponseWriter};
+use http::server::middleware::{Stack, Next};
+use http::server::compression::Compression;
+use http::server::router::{Router, TrailingSlash};
+use http::headers::content_type::MediaType;
+
+fn text_plain() -> Option<MediaType> {
+    Some(MediaType::new(String::from_str("text"), String::from_str("plain"),
+                        vec!((String::from_str("charset"), String::from_str("UTF-8")))))
+}
+
+/// Log the method, path, status and duration of each request.
+fn log_requests(request: Request, w: &mut ResponseWriter, next: Next) {
+    let start = time::precise_time_ns();
+    let line = format!("{} {}", request.method, request.request_uri);
+    next(request, w);
+    println!("{} -> {:?} ({}µs)", line, w.status, (time::precise_time_ns() - start) / 1000);
+}
+
+fn main() {
+    let mut router = Router::new(Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1),
+                                                          port: 8001 }));
+    router.trailing_slash = TrailingSlash::Redirect;
+    router.get("/", |_, _, w| {
+        w.headers.content_type = text_plain();
+        w.write_all(b"Try /hello/<name> or /files/<path>\n").unwrap();
+    });
+    router.get("/hello/:name", |_, params, w| {
+        w.headers.content_type = text_plain();
+        write!(w, "Hello, {}!\n", params.get("name").unwrap()).unwrap();
+    });
+    router.get("/files/*path", |_, params, w| {
+        w.headers.content_type = text_plain();
+        write!(w, "You asked for the file {:?}\n", params.get("path").unwrap()).unwrap();
+    });
+    Stack::new(router).layer(log_requests).layer(Compression::new()).serve_forever();
+}
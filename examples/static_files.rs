@@ -0,0 +1,25 @@
+#![feature(collections,old_io,old_path,os)]
+//! A server which serves the files of a directory (the current directory, or the one given as the
+//! first argument) at http://127.0.0.1:8001/files/, with directory listings.
+
+#![crate_name = "static_files"]
+
+extern crate http;
+
+use std::os;
+use std::old_io::net::ip::{SocketAddr, Ipv4Addr};
+
+use http::server::{Config, Server};
+use http::server::static_files::StaticFiles;
+
+fn main() {
+    let root = match os::args().get(1) {
+        Some(root) => Path::new(&root[..]),
+        None => Path::new("."),
+    };
+    let mut files = StaticFiles::new(Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1),
+                                                              port: 8001 }),
+                                     "/files", root);
+    files.listings = true;
+    files.serve_forever();
+}
@@ -0,0 +1,55 @@
+#![feature(collections,old_io,std_misc)]
+//! A WebSocket server which sends every message back: connect to ws://127.0.0.1:8001/ with a
+//! browser or any other client. Anything other than a WebSocket handshake is refused.
+
+#![crate_name = "websocket_echo"]
+
+extern crate http;
+
+use std::old_io::net::ip::{SocketAddr, Ipv4Addr};
+use std::thread::Thread;
+
+use http::server::{Config, Server, Request, ResponseWriter};
+use http::websocket;
+use http::websocket::Message;
+
+#[derive(Clone)]
+struct EchoServer;
+
+impl Server for EchoServer {
+    fn get_config(&self) -> Config {
+        Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 })
+    }
+
+    fn handle_request(&self, r: Request, w: &mut ResponseWriter) {
+        let mut socket = match websocket::accept(&r, w, None) {
+            Ok(socket) => socket,
+            Err(err) => {
+                println!("refused a handshake: {:?}", err);
+                return;
+            },
+        };
+        Thread::spawn(move || {
+            loop {
+                let message = match socket.read_message() {
+                    Ok(message) => message,
+                    Err(_) => return,
+                };
+                match message {
+                    Message::Text(..) | Message::Binary(..) => {
+                        if socket.write_message(message).is_err() {
+                            return;
+                        }
+                    },
+                    // The close has been answered already.
+                    Message::Close(..) => return,
+                    Message::Ping(..) | Message::Pong(..) => (),
+                }
+            }
+        });
+    }
+}
+
+fn main() {
+    EchoServer.serve_forever();
+}
@@ -1,7 +1,7 @@
 /// Memory buffers for the benefit of `std::old_io::net` which has slow read/write.
 
 use std::old_io::{IoResult, Stream};
-use std::cmp::min;
+use std::cmp::{min, max};
 use std::slice;
 use std::fmt::radix;
 use std::ptr;
@@ -72,6 +72,59 @@ impl<T: Reader> BufferedStream<T> {
         }
     }
 
+    /// Put bytes which were received from the wrapped stream elsewhere in front of whatever is
+    /// buffered, so that they are read first.
+    pub fn unread(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let len = data.len() + self.read_max - self.read_pos;
+        let mut read_buffer = Vec::with_capacity(max(len, READ_BUF_SIZE));
+        read_buffer.push_all(data);
+        read_buffer.push_all(self.buffered());
+        unsafe { read_buffer.set_len(max(len, READ_BUF_SIZE)); }
+        self.read_buffer = read_buffer;
+        self.read_pos = 0;
+        self.read_max = len;
+    }
+
+    /// The bytes which have been read from the wrapped stream but not yet consumed.
+    #[inline]
+    pub fn buffered(&self) -> &[u8] {
+        &self.read_buffer[self.read_pos..self.read_max]
+    }
+
+    /// Mark the first `amount` bytes of `buffered()` as consumed.
+    #[inline]
+    pub fn consume(&mut self, amount: usize) {
+        assert!(self.read_pos + amount <= self.read_max);
+        self.read_pos += amount;
+    }
+
+    /// Read more from the wrapped stream onto the end of what is already buffered.
+    ///
+    /// If there is no room left at the end of the buffer, the unconsumed bytes are first moved to
+    /// the start of it. Returns the number of bytes read, which is zero if the buffer is already
+    /// full of unconsumed bytes.
+    pub fn fill_more(&mut self) -> IoResult<usize> {
+        if self.read_max == self.read_buffer.len() {
+            if self.read_pos == 0 {
+                return Ok(0);
+            }
+            let len = self.read_max - self.read_pos;
+            unsafe {
+                ptr::copy_memory(self.read_buffer.as_mut_ptr(),
+                    self.read_buffer.as_ptr().offset(self.read_pos as isize), len);
+            }
+            self.read_pos = 0;
+            self.read_max = len;
+        }
+        let read_max = self.read_max;
+        let amount = try!(self.wrapped.read(&mut self.read_buffer[read_max..]));
+        self.read_max += amount;
+        Ok(amount)
+    }
+
     /// Slightly faster implementation of read_byte than that which is provided by ReaderUtil
     /// (which just uses `read()`)
     #[inline]
@@ -92,9 +145,34 @@ impl<T: Writer> BufferedStream<T> {
     /// At the time of calling this, headers MUST have been written, including the
     /// ending CRLF, or else an invalid HTTP response may be written.
     pub fn finish_response(&mut self) -> IoResult<()> {
-        try!(self.flush());
+        try!(self.end_response());
+        self.flush()
+    }
+
+    /// Finish off writing a response as `finish_response` does, but leave whatever is still
+    /// buffered to go out with what is written next (or with `flush`), so that the responses to
+    /// pipelined requests can be sent together. This also switches away from the chunked coding.
+    pub fn end_response(&mut self) -> IoResult<()> {
         if self.writing_chunked_body {
-            try!(self.wrapped.write_all(b"0\r\n\r\n"));
+            try!(self.write_buffered());
+            self.writing_chunked_body = false;
+            try!(self.write_all(b"0\r\n\r\n"));
+        }
+        Ok(())
+    }
+
+    /// Write out what is in the write buffer (as a chunk, if writing a chunked body).
+    fn write_buffered(&mut self) -> IoResult<()> {
+        if self.write_len > 0 {
+            if self.writing_chunked_body {
+                let s = format!("{}\r\n", radix(self.write_len, 16));
+                try!(self.wrapped.write_all(s.as_bytes()));
+            }
+            try!(self.wrapped.write_all(&self.write_buffer[..self.write_len]));
+            if self.writing_chunked_body {
+                try!(self.wrapped.write_all(b"\r\n"));
+            }
+            self.write_len = 0;
         }
         Ok(())
     }
@@ -158,17 +236,7 @@ impl<T: Writer> Writer for BufferedStream<T> {
     }
 
     fn flush(&mut self) -> IoResult<()> {
-        if self.write_len > 0 {
-            if self.writing_chunked_body {
-                let s = format!("{}\r\n", radix(self.write_len, 16));
-                try!(self.wrapped.write_all(s.as_bytes()));
-            }
-            try!(self.wrapped.write_all(&self.write_buffer[..self.write_len]));
-            if self.writing_chunked_body {
-                try!(self.wrapped.write_all(b"\r\n"));
-            }
-            self.write_len = 0;
-        }
+        try!(self.write_buffered());
         self.wrapped.flush()
     }
 }
@@ -1,18 +1,72 @@
-use std::old_io::{Stream, IoResult, OtherIoError, IoError};
+use std::ascii::AsciiExt;
+use std::cmp::min;
+use std::mem;
+use std::old_io::{Stream, IoResult, OtherIoError, IoError, EndOfFile, standard_error};
+use std::slice;
+use flate2::reader::{GzDecoder, ZlibDecoder, DeflateDecoder};
 use client::request::RequestWriter;
-use rfc2616::{CR, LF, SP};
-use common::read_http_version;
+use coding::{is_zlib_header, decode_transfer_codings, DecodeError};
 use headers;
+use headers::transfer_encoding::TransferCoding;
+use headers::transfer_encoding::TransferCoding::Chunked;
+use method::Method::Head;
+use parser;
+use parser::{Parser, Event};
+use rfc2616::ContentCoding;
 use status::Status;
 
 use buffer::BufferedStream;
-use server::request::{RequestBuffer};
-use headers::HeaderLineErr::{EndOfFile, EndOfHeaders, MalformedHeaderSyntax,
-                             MalformedHeaderValue};
 
-pub struct ResponseReader<S> {
+/// The body of a response as it comes off the stream, with any chunked transfer-coding removed.
+struct RawBody<S> {
     stream: BufferedStream<S>,
 
+    /// The parser which read the head of the response and now decodes the body.
+    parser: Parser,
+
+    /// The number of bytes at the start of the stream's buffer which the parser has already passed
+    /// as body but which have not yet been read.
+    pending: usize,
+
+    /// Whether the whole body has been read.
+    complete: bool,
+
+    /// Bytes of the body which were read ahead (to tell what the content-coding really is) and are
+    /// to be read again first.
+    unread: Vec<u8>,
+
+    /// The transfer-codings other than chunked which are yet to be removed; the whole body is read
+    /// and decoded on the first read if there are any.
+    transfer_codings: Vec<TransferCoding>,
+
+    /// The most the body may be once the transfer-codings are removed.
+    max_decoded_len: usize,
+}
+
+/// The body of a response, decoded from its content-coding if it has one that is understood.
+enum Body<S> {
+    /// Not yet read from; the decoder for the coding is chosen on the first read, which reads a
+    /// little ahead to do so.
+    Undecided(RawBody<S>, ContentCoding),
+    Identity(RawBody<S>),
+    Gzip(GzDecoder<RawBody<S>>),
+    /// "deflate" as it should be: the zlib format.
+    Zlib(ZlibDecoder<RawBody<S>>),
+    /// "deflate" as some servers send it: raw DEFLATE, with no zlib wrapper.
+    Deflate(DeflateDecoder<RawBody<S>>),
+    /// The start of the body could not be read or decoded; the read which found that failed.
+    Failed,
+}
+
+pub struct ResponseReader<S> {
+    body: Body<S>,
+
+    /// Whether the body is being decoded from gzip or deflate; if so, the Content-Encoding and
+    /// Content-Length headers, which describe the encoded body, have been removed from `headers`.
+    ///
+    /// Set `request.decompress` to `false` to get the body as it was sent.
+    pub decoded: bool,
+
     /// The request which this is a response to
     pub request: RequestWriter<S>,
 
@@ -38,98 +92,394 @@ fn bad_response_err() -> IoError {
 impl<S: Stream> ResponseReader<S> {
     pub fn construct(mut stream: BufferedStream<S>, request: RequestWriter<S>)
             -> Result<ResponseReader<S>, (RequestWriter<S>, IoError)> {
-        // TODO: raise condition at the points where Err is returned
-        //let mut b = [0u8, ..4096];
-        //let len = stream.read(b);
-        //println!("{}", ::std::str::from_bytes(b[..len.unwrap()]));
-        let http_version = match read_http_version(&mut stream, &mut |b| b == SP) {
-            Ok(nums) => nums,
-            Err(_) => return Err((request, bad_response_err())),
+        let mut parser = Parser::response(&request.method);
+        let (http_version, status, mut headers) =
+            match read_head(&mut stream, &mut parser, &request) {
+                Ok(head) => head,
+                Err(err) => return Err((request, err)),
+            };
+
+        let code = status.code();
+        let may_have_body = request.method != Head && code >= 200 && code != 204 && code != 304;
+        let transfer_codings = match headers.transfer_encoding {
+            Some(ref codings) if may_have_body =>
+                codings.iter().filter(|coding| **coding != Chunked).cloned().collect(),
+            _ => Vec::new(),
+        };
+        let raw = RawBody {
+            stream: stream,
+            parser: parser,
+            pending: 0,
+            complete: false,
+            unread: Vec::new(),
+            transfer_codings: transfer_codings,
+            max_decoded_len: request.max_decoded_body_len,
+        };
+        let coding = match headers.content_encoding {
+            Some(ref coding) if request.decompress && may_have_body => content_coding(&coding[..]),
+            _ => None,
         };
+        let body = match coding {
+            Some(coding) => Body::Undecided(raw, coding),
+            None => Body::Identity(raw),
+        };
+        if coding.is_some() {
+            headers.content_encoding = None;
+            headers.content_length = None;
+        }
 
-        // Read the status code
-        let mut digits = 0u8;
-        let mut status_code = 0u16;
-        loop {
-            if digits == 4u8 {
-                // Status code must be three digits long
-                return Err((request, bad_response_err()));
-            }
-            match stream.read_byte() {
-                Ok(b) if b >= b'0' && b <= b'9' => {
-                    status_code = status_code * 10 + b as u16 - '0' as u16;
-                },
-                Ok(b) if b == SP => break,
-                _ => return Err((request, bad_response_err())),
-            }
-            digits += 1;
+        Ok(ResponseReader {
+            body: body,
+            decoded: coding.is_some(),
+            request: request,
+            version: http_version,
+            status: status,
+            headers: headers,
+        })
+    }
+
+    /// Take over the connection after `101 Switching Protocols`, for the protocol named in the
+    /// response's Upgrade header; anything the server has already sent in that protocol is still in
+    /// the stream's read buffer. Any other response is handed back as it was.
+    pub fn into_stream(self) -> Result<BufferedStream<S>, ResponseReader<S>> {
+        if self.status.code() != 101 {
+            return Err(self);
         }
+        match self.body {
+            Body::Identity(raw) => Ok(raw.stream),
+            body => Err(ResponseReader { body: body, ..self }),
+        }
+    }
+}
 
-        // Read the status reason
-        let mut reason = String::new();
-        loop {
-            match stream.read_byte() {
-                Ok(b) if b == CR => {
-                    if stream.read_byte() == Ok(LF) {
-                        break;
+/// Read the Status-Line and headers of a response.
+fn read_head<S: Stream>(stream: &mut BufferedStream<S>, parser: &mut Parser,
+                        request: &RequestWriter<S>)
+        -> IoResult<((usize, usize), Status, headers::response::HeaderCollection)> {
+    let mut http_version = (0, 0);
+    let mut status = None;
+    let mut headers = headers::response::HeaderCollection::new();
+
+    // The head is parsed where it lies in the read buffer, reading more onto the end of it as
+    // needed. Header names are distinct between a request and response, but that is taken care
+    // of when they are looked up (with a branchified matcher generated for each table).
+    loop {
+        let (consumed, head_complete, incomplete) = {
+            let (consumed, event) = match parser.parse(stream.buffered()) {
+                Ok(parsed) => parsed,
+                Err(_) => return Err(bad_response_err()),
+            };
+            match event {
+                Event::StatusLine(line) => {
+                    http_version = line.version;
+                    let reason = String::from_utf8_lossy(line.reason).into_owned();
+                    status = Some(Status::from_code_and_reason(line.code, reason));
+                },
+                Event::Header(field) => {
+                    let result = if parser::is_folded(field.value) {
+                        headers.append_raw_with(field.name, &parser::unfold(field.value)[..],
+                                                &*request.header_registry)
                     } else {
-                        // Response-Line has CR without LF. Not yet resilient; TODO.
-                        return Err((request, bad_response_err()));
+                        headers.append_raw_with(field.name, field.value, &*request.header_registry)
+                    };
+                    if result.is_err() {
+                        println!("Bad header encountered. TODO: handle this better.");
+                        // Now just ignore the header (it is still in headers.raw)
                     }
-                }
-                Ok(b) => {
-                    reason.push(b as char);
-                }
-                Err(_) => return Err((request, bad_response_err())),
+                },
+                _ => (),
+            }
+            (consumed, event == Event::HeadersComplete, event == Event::Incomplete)
+        };
+        stream.consume(consumed);
+        if head_complete {
+            let status = status.take().unwrap();
+            // An interim response (such as `100 Continue`) is followed by the real one; after
+            // `101 Switching Protocols` there is no more HTTP.
+            if status.code() / 100 == 1 && status.code() != 101 {
+                headers = headers::response::HeaderCollection::new();
+                continue;
             }
+            return Ok((http_version, status, headers));
         }
+        if incomplete {
+            match stream.fill_more() {
+                Ok(0) | Err(_) => return Err(bad_response_err()),
+                Ok(_) => (),
+            }
+        }
+    }
+}
 
-        // Now we sneakily slip back to server::RequestBuffer to avoid code duplication. This is
-        // temporary, honest!
-        //
-        // You see, read_header and read_header_line will be replaced, as will this. The code will
-        // not be shared between them as they will have ultra-smart parsers (probably using Ragel)
-        // to provide fast loading of standard headers, and the set of defined headers is distinct
-        // between a request and response.
-        let headers = {
-            let mut buffer = RequestBuffer::new(&mut stream);
-            let mut headers = headers::response::HeaderCollection::new();
-            loop {
-                let xxx = buffer.read_header::<headers::response::Header>();
-                match xxx {
-                //match buffer.read_header::<headers::response::Header>() {
-                    Err(EndOfFile) => {
-                        //panic!("server disconnected, no more response to receive :-(");
-                        return Err((request, bad_response_err()));
-                    },
-                    Err(EndOfHeaders) => break,
-                    Err(MalformedHeaderSyntax) => {
-                        return Err((request, bad_response_err()));
-                    },
-                    Err(MalformedHeaderValue) => {
-                        println!("Bad header encountered. TODO: handle this better.");
-                        // Now just ignore the header
-                    },
-                    Ok(header) => {
-                        headers.insert(header);
+/// The content-coding of a Content-Encoding value, if it is a single one which can be decoded.
+fn content_coding(content_encoding: &str) -> Option<ContentCoding> {
+    let coding = content_encoding.trim();
+    if coding.eq_ignore_ascii_case("x-gzip") {
+        return Some(ContentCoding::Gzip);
+    }
+    match coding.parse() {
+        Ok(ContentCoding::Gzip) => Some(ContentCoding::Gzip),
+        Ok(ContentCoding::Deflate) => Some(ContentCoding::Deflate),
+        _ => None,
+    }
+}
+
+/// Wrap the body in a decoder for the coding.
+fn decoder<S: Stream>(mut raw: RawBody<S>, coding: ContentCoding) -> IoResult<Body<S>> {
+    // Read ahead a little: an empty body has nothing to decode (and no gzip header), and the
+    // start of a "deflate" body tells whether it is really zlib.
+    let mut start = [0u8; 2];
+    let mut len = 0;
+    while len < start.len() {
+        match raw.read(&mut start[len..]) {
+            Ok(amount) => len += amount,
+            Err(ref err) if err.kind == EndOfFile => break,
+            Err(err) => return Err(err),
+        }
+    }
+    raw.unread = start[..len].to_vec();
+    if len == 0 {
+        return Ok(Body::Identity(raw));
+    }
+    Ok(match coding {
+        ContentCoding::Gzip => Body::Gzip(try!(GzDecoder::new(raw))),
+        ContentCoding::Deflate if len == 2 && is_zlib_header(start[0], start[1]) =>
+            Body::Zlib(ZlibDecoder::new(raw)),
+        ContentCoding::Deflate => Body::Deflate(DeflateDecoder::new(raw)),
+        _ => Body::Identity(raw),
+    })
+}
+
+/// Read the body, decoded from gzip or deflate where it was so encoded (unless
+/// `request.decompress` was turned off).
+impl<S: Stream> Reader for ResponseReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.body.read(buf)
+    }
+}
+
+impl<S: Stream> Reader for Body<S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if let Body::Undecided(..) = *self {
+            // If reading ahead fails, so does this read, and the body is left as `Failed`.
+            let body = match mem::replace(self, Body::Failed) {
+                Body::Undecided(raw, coding) => try!(decoder(raw, coding)),
+                _ => unreachable!(),
+            };
+            *self = body;
+        }
+        match *self {
+            Body::Identity(ref mut body) => body.read(buf),
+            Body::Gzip(ref mut body) => body.read(buf),
+            Body::Zlib(ref mut body) => body.read(buf),
+            Body::Deflate(ref mut body) => body.read(buf),
+            Body::Undecided(..) => unreachable!(),
+            Body::Failed => Err(bad_response_err()),
+        }
+    }
+}
+
+impl<S: Stream> Reader for RawBody<S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if !self.transfer_codings.is_empty() {
+            let codings = mem::replace(&mut self.transfer_codings, Vec::new());
+            let body = try!(self.read_to_end());
+            self.unread = match decode_transfer_codings(&codings[..], body, self.max_decoded_len) {
+                Ok(body) => body,
+                Err(DecodeError::Io(err)) => return Err(err),
+                Err(DecodeError::TooLarge) => return Err(IoError {
+                    kind: OtherIoError,
+                    desc: "response body too large once decoded",
+                    detail: None,
+                }),
+            };
+            if self.unread.is_empty() {
+                return Err(standard_error(EndOfFile));
+            }
+        }
+        if !self.unread.is_empty() {
+            let amount = min(self.unread.len(), buf.len());
+            slice::bytes::copy_memory(buf, &self.unread[..amount]);
+            self.unread = self.unread[amount..].to_vec();
+            return Ok(amount);
+        }
+        loop {
+            if self.pending > 0 {
+                let amount = min(self.pending, buf.len());
+                slice::bytes::copy_memory(buf, &self.stream.buffered()[..amount]);
+                self.stream.consume(amount);
+                self.pending -= amount;
+                return Ok(amount);
+            }
+            if self.complete {
+                return Err(standard_error(EndOfFile));
+            }
+
+            // Body bytes are left in the buffer until they are read (as `pending`), so they are
+            // not counted as consumed here.
+            let (consumed, body, incomplete) = match self.parser.parse(self.stream.buffered()) {
+                Ok((consumed, Event::Body(data))) => (consumed - data.len(), data.len(), false),
+                Ok((consumed, Event::MessageComplete)) => {
+                    self.complete = true;
+                    (consumed, 0, false)
+                },
+                Ok((consumed, Event::Incomplete)) => (consumed, 0, true),
+                // Trailers are not kept.
+                Ok((consumed, _)) => (consumed, 0, false),
+                Err(_) => return Err(bad_response_err()),
+            };
+            self.stream.consume(consumed);
+            self.pending = body;
+            if incomplete {
+                match self.stream.fill_more() {
+                    Ok(_) => (),
+                    Err(ref err) if err.kind == EndOfFile => {
+                        // Only a body which runs until the connection is closed may end here.
+                        return match self.parser.finish() {
+                            Ok(()) => {
+                                self.complete = true;
+                                Err(standard_error(EndOfFile))
+                            },
+                            Err(_) => Err(bad_response_err()),
+                        };
                     },
+                    Err(err) => return Err(err),
                 }
             }
-            headers
-        };
+        }
+    }
+}
 
-        Ok(ResponseReader {
+#[cfg(test)]
+mod test {
+    use std::old_io::Writer;
+    use std::usize;
+    use flate2::Compression;
+    use flate2::writer::{GzEncoder, ZlibEncoder, DeflateEncoder};
+    use buffer::BufferedStream;
+    use headers::transfer_encoding::TransferCoding::TransferExtension;
+    use memstream::MemReaderFakeStream;
+    use method::Method::Get;
+    use parser::{Parser, Event};
+    use rfc2616::ContentCoding::{Gzip, Deflate};
+    use super::{RawBody, Body, decoder, content_coding};
+
+    /// The body of a response, the head having been read.
+    fn raw_body(body: &[u8]) -> RawBody<MemReaderFakeStream> {
+        let mut response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len())
+            .into_bytes();
+        response.push_all(body);
+        let mut stream = BufferedStream::new(MemReaderFakeStream::new(response));
+        let mut parser = Parser::response(&Get);
+        loop {
+            let (consumed, event) = parser.parse(stream.buffered()).unwrap();
+            let (done, incomplete) = (event == Event::HeadersComplete, event == Event::Incomplete);
+            stream.consume(consumed);
+            if done {
+                break;
+            }
+            if incomplete {
+                stream.fill_more().unwrap();
+            }
+        }
+        RawBody {
             stream: stream,
-            request: request,
-            version: http_version,
-            status: Status::from_code_and_reason(status_code, reason),
-            headers: headers,
-        })
+            parser: parser,
+            pending: 0,
+            complete: false,
+            unread: Vec::new(),
+            transfer_codings: Vec::new(),
+            max_decoded_len: usize::MAX,
+        }
     }
-}
 
-impl<S: Stream> Reader for ResponseReader<S> {
-    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
-        self.stream.read(buf)
+    const TEXT: &'static [u8] = b"It was the best of times, it was the worst of times, it was the \
+                                  age of wisdom, it was the age of foolishness...";
+
+    #[test]
+    fn test_content_coding() {
+        assert_eq!(content_coding("gzip"), Some(Gzip));
+        assert_eq!(content_coding("X-GZIP"), Some(Gzip));
+        assert_eq!(content_coding(" deflate "), Some(Deflate));
+        assert_eq!(content_coding("br"), None);
+        assert_eq!(content_coding("gzip, identity"), None);
+    }
+
+    #[test]
+    fn test_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(TEXT).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut body = decoder(raw_body(&compressed[..]), Gzip).unwrap();
+        match body {
+            Body::Gzip(ref mut body) => assert_eq!(body.read_to_end().unwrap(), TEXT.to_vec()),
+            _ => panic!("not decoded as gzip"),
+        }
+    }
+
+    #[test]
+    fn test_deflate() {
+        // As it should be, in zlib format...
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(TEXT).unwrap();
+        let compressed = encoder.finish().unwrap();
+        match decoder(raw_body(&compressed[..]), Deflate).unwrap() {
+            Body::Zlib(mut body) => assert_eq!(body.read_to_end().unwrap(), TEXT.to_vec()),
+            _ => panic!("not decoded as zlib"),
+        }
+
+        // ... and as it sometimes is, raw.
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(TEXT).unwrap();
+        let compressed = encoder.finish().unwrap();
+        match decoder(raw_body(&compressed[..]), Deflate).unwrap() {
+            Body::Deflate(mut body) => assert_eq!(body.read_to_end().unwrap(), TEXT.to_vec()),
+            _ => panic!("not decoded as raw deflate"),
+        }
+    }
+
+    #[test]
+    fn test_undecided() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(TEXT).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut body = Body::Undecided(raw_body(&compressed[..]), Gzip);
+        assert_eq!(body.read_to_end().unwrap(), TEXT.to_vec());
+
+        // Something which is not gzip fails when it is read, not before.
+        let mut body = Body::Undecided(raw_body(TEXT), Gzip);
+        assert!(body.read_to_end().is_err());
+        match body {
+            Body::Failed => (),
+            _ => panic!("not failed"),
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        match decoder(raw_body(b""), Gzip).unwrap() {
+            Body::Identity(mut body) => assert_eq!(body.read_to_end().unwrap(), Vec::new()),
+            _ => panic!("an empty body was decoded"),
+        }
+    }
+
+    #[test]
+    fn test_transfer_codings() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(TEXT).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut body = raw_body(&compressed[..]);
+        body.transfer_codings = vec!(TransferExtension(String::from_str("gzip"), Vec::new()));
+        assert_eq!(body.read_to_end().unwrap(), TEXT.to_vec());
+
+        // A body which decodes to more than the limit is not decoded in full.
+        let mut body = raw_body(&compressed[..]);
+        body.transfer_codings = vec!(TransferExtension(String::from_str("gzip"), Vec::new()));
+        body.max_decoded_len = TEXT.len() - 1;
+        assert!(body.read_to_end().is_err());
+
+        let mut body = raw_body(TEXT);
+        body.transfer_codings = vec!(TransferExtension(String::from_str("compress"), Vec::new()));
+        assert!(body.read_to_end().is_err());
     }
 }
@@ -29,6 +29,12 @@ impl Connecter for NetworkStream {
             Ok(NormalStream(stream))
         }
     }
+
+    fn set_read_timeout(&mut self, timeout_ms: Option<u64>) {
+        match *self {
+            NormalStream(ref mut ns) => ns.set_read_timeout(timeout_ms),
+        }
+    }
 }
 
 impl Reader for NetworkStream {
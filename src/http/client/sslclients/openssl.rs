@@ -32,6 +32,13 @@ impl Connecter for NetworkStream {
             Ok(NormalStream(stream))
         }
     }
+
+    fn set_read_timeout(&mut self, timeout_ms: Option<u64>) {
+        match *self {
+            NormalStream(ref mut ns) => ns.set_read_timeout(timeout_ms),
+            SslProtectedStream(ref mut ns) => ns.get_mut().set_read_timeout(timeout_ms),
+        }
+    }
 }
 
 impl Reader for NetworkStream {
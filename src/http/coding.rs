@@ -0,0 +1,296 @@
+//! gzip and deflate, as used both as content-codings and as transfer-codings.
+//!
+//! A transfer-coding is a property of a message rather than of the representation it carries: it
+//! is applied by the sender and removed by the recipient, on top of the chunked coding which frames
+//! the body. Codings are listed in the order they were applied, so they are removed in reverse;
+//! chunked, when it is there at all, is always the last (and is left to the parser and the
+//! `BufferedStream`).
+
+use std::error::FromError;
+use std::mem;
+use std::old_io::{IoResult, IoError, OtherIoError, EndOfFile, MemReader, Writer};
+use std::sync::{Arc, Mutex};
+use flate2::Compression;
+use flate2::reader::{GzDecoder, ZlibDecoder, DeflateDecoder};
+use flate2::writer::{GzEncoder, ZlibEncoder};
+
+use headers::transfer_encoding::TransferCoding;
+use headers::transfer_encoding::TransferCoding::{Chunked, TransferExtension};
+use rfc2616::ContentCoding;
+
+/// The gzip or deflate coding which a transfer-coding stands for (Chunked and unsupported codings
+/// have none).
+pub fn transfer_coding_as_content_coding(coding: &TransferCoding) -> Option<ContentCoding> {
+    match *coding {
+        Chunked => None,
+        TransferExtension(ref token, _) => match &token[..] {
+            "gzip" | "x-gzip" => Some(ContentCoding::Gzip),
+            "deflate" => Some(ContentCoding::Deflate),
+            _ => None,
+        },
+    }
+}
+
+/// Whether every one of the transfer-codings can be applied and removed; a server answers a
+/// request with any other `501 Not Implemented`.
+pub fn transfer_codings_supported(codings: &[TransferCoding]) -> bool {
+    codings.iter().all(|coding| {
+        *coding == Chunked || transfer_coding_as_content_coding(coding).is_some()
+    })
+}
+
+fn unsupported_err() -> IoError {
+    IoError {
+        kind: OtherIoError,
+        desc: "unsupported transfer-coding",
+        detail: None,
+    }
+}
+
+/// Whether two bytes are the start of a zlib stream (RFC 1950): the deflate method, and a check
+/// value which makes them a multiple of 31.
+pub fn is_zlib_header(cmf: u8, flg: u8) -> bool {
+    cmf & 0x0f == 8 && ((cmf as u16) << 8 | flg as u16) % 31 == 0
+}
+
+/// Why data could not be decoded.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The decoded data would be longer than the limit it was decoded with.
+    TooLarge,
+    /// The data is not valid for its coding, or the coding is not supported.
+    Io(IoError),
+}
+
+impl FromError<IoError> for DecodeError {
+    fn from_error(err: IoError) -> DecodeError {
+        DecodeError::Io(err)
+    }
+}
+
+/// Read everything from a decoder, a piece at a time, giving up as soon as there is more than
+/// `max_len`: a little compressed data can inflate to far more than is wanted in memory.
+fn read_limited<R: Reader>(mut reader: R, max_len: usize) -> Result<Vec<u8>, DecodeError> {
+    let mut out = Vec::new();
+    let mut buf = [0u8; 0x2000];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(amount) => {
+                if amount > max_len - out.len() {
+                    return Err(DecodeError::TooLarge);
+                }
+                out.push_all(&buf[..amount]);
+            },
+            Err(ref err) if err.kind == EndOfFile => return Ok(out),
+            Err(err) => return Err(DecodeError::Io(err)),
+        }
+    }
+}
+
+/// Decode data of the given coding in full, to at most `max_len` bytes. "deflate" is taken to be
+/// the zlib format, as it should be, or raw DEFLATE if it does not start like zlib, as some
+/// senders have it.
+pub fn decode(coding: ContentCoding, data: &[u8], max_len: usize)
+        -> Result<Vec<u8>, DecodeError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+    let reader = MemReader::new(data.to_vec());
+    match coding {
+        ContentCoding::Gzip => read_limited(try!(GzDecoder::new(reader)), max_len),
+        ContentCoding::Deflate if data.len() >= 2 && is_zlib_header(data[0], data[1]) =>
+            read_limited(ZlibDecoder::new(reader), max_len),
+        ContentCoding::Deflate => read_limited(DeflateDecoder::new(reader), max_len),
+        ContentCoding::Identity => read_limited(reader, max_len),
+        ContentCoding::Compress => Err(DecodeError::Io(unsupported_err())),
+    }
+}
+
+/// Remove the transfer-codings (other than chunked, which is already gone) from a whole body,
+/// which may be at most `max_len` bytes once each is removed.
+pub fn decode_transfer_codings(codings: &[TransferCoding], body: Vec<u8>, max_len: usize)
+        -> Result<Vec<u8>, DecodeError> {
+    let mut body = body;
+    for coding in codings.iter().rev() {
+        if *coding == Chunked {
+            continue;
+        }
+        body = match transfer_coding_as_content_coding(coding) {
+            Some(coding) => try!(decode(coding, &body[..], max_len)),
+            None => return Err(DecodeError::Io(unsupported_err())),
+        };
+    }
+    Ok(body)
+}
+
+/// A `Writer` which an encoder writes into, so that its output can be taken as it comes.
+#[derive(Clone)]
+struct Sink(Arc<Mutex<Vec<u8>>>);
+
+impl Sink {
+    fn take(&self) -> Vec<u8> {
+        mem::replace(&mut *self.0.lock().unwrap(), Vec::new())
+    }
+}
+
+impl Writer for Sink {
+    fn write_all(&mut self, buf: &[u8]) -> IoResult<()> {
+        self.0.lock().unwrap().push_all(buf);
+        Ok(())
+    }
+}
+
+enum EncoderKind {
+    Gzip(GzEncoder<Sink>),
+    Deflate(ZlibEncoder<Sink>),
+}
+
+/// An incremental gzip or deflate (zlib) encoder.
+pub struct Encoder {
+    kind: EncoderKind,
+    sink: Sink,
+}
+
+impl Encoder {
+    /// An encoder for the coding, if it is gzip or deflate.
+    pub fn new(coding: ContentCoding) -> Option<Encoder> {
+        let sink = Sink(Arc::new(Mutex::new(Vec::new())));
+        let kind = match coding {
+            ContentCoding::Gzip => EncoderKind::Gzip(GzEncoder::new(sink.clone(),
+                                                                    Compression::Default)),
+            ContentCoding::Deflate => EncoderKind::Deflate(ZlibEncoder::new(sink.clone(),
+                                                                            Compression::Default)),
+            _ => return None,
+        };
+        Some(Encoder {
+            kind: kind,
+            sink: sink,
+        })
+    }
+
+    /// Encode more data, appending what output there is so far to `out`.
+    pub fn encode(&mut self, data: &[u8], out: &mut Vec<u8>) -> IoResult<()> {
+        match self.kind {
+            EncoderKind::Gzip(ref mut encoder) => try!(encoder.write_all(data)),
+            EncoderKind::Deflate(ref mut encoder) => try!(encoder.write_all(data)),
+        }
+        out.push_all(&self.sink.take()[..]);
+        Ok(())
+    }
+
+    /// Finish the encoding, appending the rest of the output to `out`.
+    pub fn finish(self, out: &mut Vec<u8>) -> IoResult<()> {
+        match self.kind {
+            EncoderKind::Gzip(encoder) => { try!(encoder.finish()); },
+            EncoderKind::Deflate(encoder) => { try!(encoder.finish()); },
+        }
+        out.push_all(&self.sink.take()[..]);
+        Ok(())
+    }
+}
+
+/// Applies a list of transfer-codings (other than chunked) to a body as it is written.
+pub struct TransferEncoder {
+    /// The encoders, in the order they are applied.
+    encoders: Vec<Encoder>,
+}
+
+impl TransferEncoder {
+    /// An encoder for the codings, or `None` if any is not supported.
+    pub fn new(codings: &[TransferCoding]) -> Option<TransferEncoder> {
+        let mut encoders = Vec::new();
+        for coding in codings.iter().filter(|coding| **coding != Chunked) {
+            match transfer_coding_as_content_coding(coding).and_then(Encoder::new) {
+                Some(encoder) => encoders.push(encoder),
+                None => return None,
+            }
+        }
+        Some(TransferEncoder {
+            encoders: encoders,
+        })
+    }
+
+    /// Whether there are no codings to apply.
+    pub fn is_empty(&self) -> bool {
+        self.encoders.is_empty()
+    }
+
+    /// Encode more of the body, appending what output there is so far to `out`.
+    pub fn encode(&mut self, data: &[u8], out: &mut Vec<u8>) -> IoResult<()> {
+        let mut data = data.to_vec();
+        for encoder in self.encoders.iter_mut() {
+            let mut encoded = Vec::new();
+            try!(encoder.encode(&data[..], &mut encoded));
+            data = encoded;
+        }
+        out.push_all(&data[..]);
+        Ok(())
+    }
+
+    /// Finish the body, appending the rest of the output to `out`.
+    pub fn finish(mut self, out: &mut Vec<u8>) -> IoResult<()> {
+        // Each encoder's last output still has to go through the encoders after it.
+        while !self.encoders.is_empty() {
+            let encoder = self.encoders.remove(0);
+            let mut rest = Vec::new();
+            try!(encoder.finish(&mut rest));
+            try!(self.encode(&rest[..], out));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use headers::transfer_encoding::TransferCoding;
+    use headers::transfer_encoding::TransferCoding::{Chunked, TransferExtension};
+    use std::usize;
+    use rfc2616::ContentCoding::{Gzip, Deflate};
+    use super::{Encoder, TransferEncoder, DecodeError, decode, decode_transfer_codings,
+                transfer_codings_supported};
+
+    fn extension(token: &str) -> TransferCoding {
+        TransferExtension(String::from_str(token), Vec::new())
+    }
+
+    const TEXT: &'static [u8] = b"It is a truth universally acknowledged, that a single man in \
+                                  possession of a good fortune, must be in want of a wife.";
+
+    #[test]
+    fn test_encoder() {
+        for &coding in [Gzip, Deflate].iter() {
+            let mut encoder = Encoder::new(coding).unwrap();
+            let mut encoded = Vec::new();
+            for piece in TEXT.chunks(7) {
+                encoder.encode(piece, &mut encoded).unwrap();
+            }
+            encoder.finish(&mut encoded).unwrap();
+            assert_eq!(decode(coding, &encoded[..], usize::MAX).unwrap(), TEXT.to_vec());
+            assert_eq!(decode(coding, &encoded[..], TEXT.len()).unwrap(), TEXT.to_vec());
+            match decode(coding, &encoded[..], TEXT.len() - 1) {
+                Err(DecodeError::TooLarge) => (),
+                result => panic!("{:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_transfer_codings() {
+        let codings = vec!(extension("gzip"), extension("deflate"), Chunked);
+        assert!(transfer_codings_supported(&codings[..]));
+        assert!(!transfer_codings_supported(&[extension("compress"), Chunked]));
+        assert!(TransferEncoder::new(&[extension("compress")]).is_none());
+        assert!(TransferEncoder::new(&[Chunked]).unwrap().is_empty());
+
+        let mut encoder = TransferEncoder::new(&codings[..]).unwrap();
+        let mut encoded = Vec::new();
+        for piece in TEXT.chunks(10) {
+            encoder.encode(piece, &mut encoded).unwrap();
+        }
+        encoder.finish(&mut encoded).unwrap();
+        assert_eq!(decode_transfer_codings(&codings[..], encoded, usize::MAX).unwrap(),
+                   TEXT.to_vec());
+        assert!(decode_transfer_codings(&[extension("compress")], TEXT.to_vec(),
+                                        usize::MAX).is_err());
+    }
+}
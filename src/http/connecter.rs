@@ -10,4 +10,9 @@ use std::old_io::net::ip::SocketAddr;
 /// than in terms of `TcpStream` only. This is handy for testing and for SSL.
 pub trait Connecter {
     fn connect(addr: SocketAddr, host: &str, use_ssl: bool) -> IoResult<Self>;
+
+    /// Make reads give up with a `TimedOut` error after so many milliseconds, or (with `None`)
+    /// wait for as long as it takes again. Streams which cannot time out need not do anything.
+    fn set_read_timeout(&mut self, _timeout_ms: Option<u64>) {
+    }
 }
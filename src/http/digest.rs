@@ -0,0 +1,552 @@
+//! Digest access authentication, as defined in RFC 7616.
+//!
+//! On the client side, `DigestClient` answers a challenge, keeping count of the nonce uses;
+//! `RequestWriter` uses it to retry a request which met with 401 Unauthorized.
+//!
+//! On the server side, `DigestAuthenticator` issues challenges and verifies the credentials that
+//! come back against a lookup of the user's password.
+
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::fmt;
+use std::num::from_str_radix;
+use crypto::digest::Digest;
+use rand;
+use rustc_serialize::base64::{ToBase64, FromBase64, URL_SAFE};
+use time;
+
+use method::Method;
+use headers::authorization::{Credentials, Challenge, AuthParams};
+use server::request::Request;
+
+use self::Algorithm::{Md5, Md5Sess, Sha256, Sha256Sess};
+pub use self::DigestVerification::{Authenticated, Stale, Unauthenticated};
+
+/// How long a nonce issued by a `DigestAuthenticator` remains valid, in seconds.
+const NONCE_LIFETIME: i64 = 300;
+
+/// How many nonces a `DigestAuthenticator` keeps counts for before it forgets the expired ones
+/// (which it also does once every `NONCE_LIFETIME`).
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// The hash algorithms supported for Digest authentication.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Algorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl Algorithm {
+    /// Get an algorithm from its name in the ``algorithm`` auth-param, regardless of case.
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        if name.eq_ignore_ascii_case("MD5") {
+            Some(Md5)
+        } else if name.eq_ignore_ascii_case("MD5-sess") {
+            Some(Md5Sess)
+        } else if name.eq_ignore_ascii_case("SHA-256") {
+            Some(Sha256)
+        } else if name.eq_ignore_ascii_case("SHA-256-sess") {
+            Some(Sha256Sess)
+        } else {
+            None
+        }
+    }
+
+    /// Whether this is a session variant, where A1 includes the nonce and cnonce.
+    pub fn is_session(&self) -> bool {
+        *self == Md5Sess || *self == Sha256Sess
+    }
+
+    /// Hash the data, producing lowercase hexadecimal as RFC 7616 requires.
+    pub fn hash(&self, data: &str) -> String {
+        match *self {
+            Md5 | Md5Sess => {
+                let mut hasher = ::crypto::md5::Md5::new();
+                hasher.input_str(data);
+                hasher.result_str()
+            },
+            Sha256 | Sha256Sess => {
+                let mut hasher = ::crypto::sha2::Sha256::new();
+                hasher.input_str(data);
+                hasher.result_str()
+            },
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Md5 => "MD5",
+            Md5Sess => "MD5-sess",
+            Sha256 => "SHA-256",
+            Sha256Sess => "SHA-256-sess",
+        })
+    }
+}
+
+/// Calculate the ``response`` auth-param.
+///
+/// ``qop`` and the values accompanying it (``nc`` and ``cnonce``) are optional only for
+/// compatibility with RFC 2069; if ``qop`` is ``None``, the others are ignored.
+pub fn response_digest(algorithm: Algorithm, username: &str, realm: &str, password: &str,
+                       method: &Method, uri: &str, nonce: &str,
+                       qop: Option<(&str, u32, &str)>) -> String {
+    let mut ha1 = algorithm.hash(&format!("{}:{}:{}", username, realm, password)[..]);
+    if algorithm.is_session() {
+        let cnonce = match qop {
+            Some((_, _, cnonce)) => cnonce,
+            None => "",
+        };
+        ha1 = algorithm.hash(&format!("{}:{}:{}", ha1, nonce, cnonce)[..]);
+    }
+    let ha2 = algorithm.hash(&format!("{}:{}", method, uri)[..]);
+    match qop {
+        Some((qop, nc, cnonce)) => algorithm.hash(
+            &format!("{}:{}:{:08x}:{}:{}:{}", ha1, nonce, nc, cnonce, qop, ha2)[..]),
+        None => algorithm.hash(&format!("{}:{}:{}", ha1, nonce, ha2)[..]),
+    }
+}
+
+/// The parts of a Digest challenge (from WWW-Authenticate or Proxy-Authenticate).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DigestChallenge {
+    pub realm: String,
+    pub nonce: String,
+    pub opaque: Option<String>,
+    pub algorithm: Algorithm,
+    /// The quality of protection options offered; only ``auth`` is supported.
+    pub qop: Vec<String>,
+    /// Whether the previous request was rejected only because its nonce had expired.
+    pub stale: bool,
+}
+
+impl DigestChallenge {
+    /// Interpret a challenge as a Digest challenge.
+    ///
+    /// This returns ``None`` if it is not a Digest challenge, if it lacks the realm or nonce, or if
+    /// it uses an algorithm which is not supported.
+    pub fn from_challenge(challenge: &Challenge) -> Option<DigestChallenge> {
+        if !challenge.is_scheme("Digest") {
+            return None;
+        }
+        let (realm, nonce) = match (challenge.param("realm"), challenge.param("nonce")) {
+            (Some(realm), Some(nonce)) => (String::from_str(realm), String::from_str(nonce)),
+            _ => return None,
+        };
+        let algorithm = match challenge.param("algorithm") {
+            Some(name) => match Algorithm::from_name(name) {
+                Some(algorithm) => algorithm,
+                None => return None,
+            },
+            None => Md5,
+        };
+        Some(DigestChallenge {
+            realm: realm,
+            nonce: nonce,
+            opaque: challenge.param("opaque").map(|o| String::from_str(o)),
+            algorithm: algorithm,
+            qop: match challenge.param("qop") {
+                Some(qop) => qop.split(',').map(|q| String::from_str(q.trim()))
+                                .filter(|q| !q.is_empty()).collect(),
+                None => Vec::new(),
+            },
+            stale: match challenge.param("stale") {
+                Some(stale) => stale.eq_ignore_ascii_case("true"),
+                None => false,
+            },
+        })
+    }
+
+    /// Produce the challenge for sending in a WWW-Authenticate header.
+    pub fn to_challenge(&self) -> Challenge {
+        let mut params = vec!((String::from_str("realm"), self.realm.clone()));
+        if !self.qop.is_empty() {
+            params.push((String::from_str("qop"), self.qop.connect(", ")));
+        }
+        params.push((String::from_str("algorithm"), self.algorithm.to_string()));
+        params.push((String::from_str("nonce"), self.nonce.clone()));
+        match self.opaque {
+            Some(ref opaque) => params.push((String::from_str("opaque"), opaque.clone())),
+            None => (),
+        }
+        if self.stale {
+            params.push((String::from_str("stale"), String::from_str("true")));
+        }
+        Challenge {
+            scheme: String::from_str("Digest"),
+            data: AuthParams(params),
+        }
+    }
+
+    fn supports_auth_qop(&self) -> bool {
+        self.qop.iter().any(|q| q.eq_ignore_ascii_case("auth"))
+    }
+}
+
+/// Client-side Digest authentication state for a single challenge.
+///
+/// Keep it around to authenticate further requests with the same nonce; each use increments the
+/// nonce count as the server expects.
+#[derive(Clone, Debug)]
+pub struct DigestClient {
+    pub challenge: DigestChallenge,
+    username: String,
+    password: String,
+    nonce_count: u32,
+}
+
+impl DigestClient {
+    pub fn new(challenge: DigestChallenge, username: String, password: String) -> DigestClient {
+        DigestClient {
+            challenge: challenge,
+            username: username,
+            password: password,
+            nonce_count: 0,
+        }
+    }
+
+    /// The number of times the nonce has been used so far.
+    pub fn nonce_count(&self) -> u32 {
+        self.nonce_count
+    }
+
+    /// Produce credentials for a request with the given method and request-target.
+    pub fn credentials(&mut self, method: &Method, uri: &str) -> Credentials {
+        let c = &self.challenge;
+        let mut params = vec!((String::from_str("username"), self.username.clone()),
+                              (String::from_str("realm"), c.realm.clone()),
+                              (String::from_str("uri"), String::from_str(uri)),
+                              (String::from_str("algorithm"), c.algorithm.to_string()),
+                              (String::from_str("nonce"), c.nonce.clone()));
+        let response = if c.supports_auth_qop() {
+            self.nonce_count += 1;
+            let cnonce = new_cnonce();
+            let response = response_digest(c.algorithm, &self.username[..], &c.realm[..],
+                                           &self.password[..], method, uri, &c.nonce[..],
+                                           Some(("auth", self.nonce_count, &cnonce[..])));
+            params.push((String::from_str("nc"), format!("{:08x}", self.nonce_count)));
+            params.push((String::from_str("cnonce"), cnonce));
+            params.push((String::from_str("qop"), String::from_str("auth")));
+            response
+        } else {
+            response_digest(c.algorithm, &self.username[..], &c.realm[..], &self.password[..],
+                            method, uri, &c.nonce[..], None)
+        };
+        params.push((String::from_str("response"), response));
+        match c.opaque {
+            Some(ref opaque) => params.push((String::from_str("opaque"), opaque.clone())),
+            None => (),
+        }
+        Credentials {
+            scheme: String::from_str("Digest"),
+            data: AuthParams(params),
+        }
+    }
+}
+
+fn new_cnonce() -> String {
+    let bytes: [u8; 16] = rand::random();
+    bytes.to_base64(URL_SAFE)
+}
+
+/// The outcome of `DigestAuthenticator.verify`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DigestVerification {
+    /// The credentials were correct; this is the username.
+    Authenticated(String),
+    /// The credentials would have been correct, but the nonce has expired. Send a new challenge
+    /// with ``stale`` set, and the client can retry without asking the user again.
+    Stale,
+    /// Missing or incorrect credentials, a nonce which was not issued by the authenticator, or an
+    /// attempt to replay a nonce count.
+    Unauthenticated,
+}
+
+/// Server-side Digest authentication for one realm.
+///
+/// Nonces are issued with a timestamp and a MAC, so they need not be stored; the highest nonce
+/// count seen is kept for each live nonce so that replayed requests can be rejected.
+///
+/// Typical use in a handler, with the authenticator kept in the `Server`:
+///
+/// ```ignore
+/// match self.authenticator.verify(&request, |username| self.users.get(username).cloned()) {
+///     Authenticated(username) => { /* carry on */ },
+///     verification => {
+///         response.status = Unauthorized;
+///         response.headers.www_authenticate =
+///             Some(vec!(self.authenticator.challenge(verification == Stale)));
+///     },
+/// }
+/// ```
+pub struct DigestAuthenticator {
+    pub realm: String,
+    pub algorithm: Algorithm,
+    secret: String,
+    opaque: String,
+    nonce_counts: Mutex<NonceCounts>,
+}
+
+/// The greatest nonce count seen for each live nonce, and when expired nonces were last forgotten.
+struct NonceCounts {
+    counts: HashMap<String, u32>,
+    pruned: i64,
+}
+
+impl DigestAuthenticator {
+    pub fn new(realm: &str, algorithm: Algorithm) -> DigestAuthenticator {
+        let secret: [u8; 32] = rand::random();
+        let opaque: [u8; 16] = rand::random();
+        DigestAuthenticator {
+            realm: String::from_str(realm),
+            algorithm: algorithm,
+            secret: secret.to_base64(URL_SAFE),
+            opaque: opaque.to_base64(URL_SAFE),
+            nonce_counts: Mutex::new(NonceCounts {
+                counts: HashMap::new(),
+                pruned: time::get_time().sec,
+            }),
+        }
+    }
+
+    /// Issue a new challenge, with a fresh nonce.
+    pub fn challenge(&self, stale: bool) -> Challenge {
+        let now = time::get_time().sec;
+        let salt: [u8; 12] = rand::random();
+        let nonce = self.nonce_for(now, &salt.to_base64(URL_SAFE)[..]);
+        {
+            let mut nonce_counts = self.nonce_counts.lock().unwrap();
+            if nonce_counts.counts.len() >= PRUNE_THRESHOLD ||
+                    now - nonce_counts.pruned >= NONCE_LIFETIME {
+                // Forget about expired nonces.
+                let live = ::std::mem::replace(&mut nonce_counts.counts, HashMap::new());
+                for (nonce, nc) in live.into_iter() {
+                    if self.nonce_age(&nonce[..], now).map_or(false, |age| age <= NONCE_LIFETIME) {
+                        nonce_counts.counts.insert(nonce, nc);
+                    }
+                }
+                nonce_counts.pruned = now;
+            }
+            // The nonce is all but certainly new, but if not, its count must not start again.
+            if !nonce_counts.counts.contains_key(&nonce) {
+                nonce_counts.counts.insert(nonce.clone(), 0);
+            }
+        }
+        DigestChallenge {
+            realm: self.realm.clone(),
+            nonce: nonce,
+            opaque: Some(self.opaque.clone()),
+            algorithm: self.algorithm,
+            qop: vec!(String::from_str("auth")),
+            stale: stale,
+        }.to_challenge()
+    }
+
+    /// A nonce is the time it was issued and some random salt (so that clients challenged in the
+    /// same second get different nonces), followed by a MAC of the two.
+    fn nonce_for(&self, timestamp: i64, salt: &str) -> String {
+        let mac = self.algorithm.hash(&format!("{}:{}:{}", timestamp, salt, self.secret)[..]);
+        format!("{}:{}:{}", timestamp, salt, mac).as_bytes().to_base64(URL_SAFE)
+    }
+
+    /// The age of a nonce, in seconds, or ``None`` if it was not issued by this authenticator
+    /// (whether or not it has expired is up to the caller).
+    fn nonce_age(&self, nonce: &str, now: i64) -> Option<i64> {
+        let decoded = match nonce.from_base64() {
+            Ok(bytes) => match String::from_utf8(bytes) {
+                Ok(s) => s,
+                Err(_) => return None,
+            },
+            Err(_) => return None,
+        };
+        let parts: Vec<&str> = decoded.split(':').collect();
+        if parts.len() != 3 {
+            return None;
+        }
+        let timestamp: i64 = match parts[0].parse().ok() {
+            Some(timestamp) => timestamp,
+            None => return None,
+        };
+        if !constant_time_eq(self.nonce_for(timestamp, parts[1]).as_bytes(), nonce.as_bytes()) {
+            None
+        } else {
+            Some(now - timestamp)
+        }
+    }
+
+    /// Verify the Authorization header of a request.
+    ///
+    /// ``lookup`` is given the username and should return that user's password, or ``None`` if
+    /// there is no such user.
+    pub fn verify<F>(&self, request: &Request, lookup: F) -> DigestVerification
+            where F: FnOnce(&str) -> Option<String> {
+        let credentials = match request.headers.authorization {
+            Some(ref credentials) if credentials.is_scheme("Digest") => credentials,
+            _ => return Unauthenticated,
+        };
+        let (username, nonce, uri, response) = match (credentials.param("username"),
+                                                       credentials.param("nonce"),
+                                                       credentials.param("uri"),
+                                                       credentials.param("response")) {
+            (Some(username), Some(nonce), Some(uri), Some(response)) =>
+                (username, nonce, uri, response),
+            _ => return Unauthenticated,
+        };
+        if credentials.param("realm") != Some(&self.realm[..]) ||
+                credentials.param("opaque") != Some(&self.opaque[..]) {
+            return Unauthenticated;
+        }
+        match credentials.param("algorithm") {
+            Some(name) if Algorithm::from_name(name) != Some(self.algorithm) =>
+                return Unauthenticated,
+            _ => (),
+        }
+        // A nonce which was never issued is refused outright; only a genuine one can be stale.
+        let age = match self.nonce_age(nonce, time::get_time().sec) {
+            Some(age) => age,
+            None => return Unauthenticated,
+        };
+        // The uri must be that of this request, lest credentials be replayed for another resource.
+        if uri != &request.request_uri.to_string()[..] {
+            return Unauthenticated;
+        }
+        // Only qop=auth is issued, so it is insisted upon.
+        let (nc, cnonce) = match (credentials.param("qop"), credentials.param("nc"),
+                                  credentials.param("cnonce")) {
+            (Some(qop), Some(nc), Some(cnonce)) if qop.eq_ignore_ascii_case("auth") =>
+                match from_str_radix::<u32>(nc, 16).ok() {
+                    Some(nc) => (nc, cnonce),
+                    None => return Unauthenticated,
+                },
+            _ => return Unauthenticated,
+        };
+        let password = match lookup(username) {
+            Some(password) => password,
+            None => return Unauthenticated,
+        };
+        let expected = response_digest(self.algorithm, username, &self.realm[..], &password[..],
+                                       &request.method, uri, nonce, Some(("auth", nc, cnonce)));
+        if !constant_time_eq(expected.as_bytes(), response.to_ascii_lowercase().as_bytes()) {
+            return Unauthenticated;
+        }
+        if age > NONCE_LIFETIME {
+            return Stale;
+        }
+        let mut nonce_counts = self.nonce_counts.lock().unwrap();
+        match nonce_counts.counts.get_mut(nonce) {
+            Some(last_nc) if nc > *last_nc => *last_nc = nc,
+            Some(_) => return Unauthenticated,  // A replay
+            // A valid nonce which has been forgotten; treat it as stale rather than failing.
+            None => return Stale,
+        }
+        Authenticated(String::from_str(username))
+    }
+}
+
+/// Compare two byte strings without leaking where they differ through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (*x ^ *y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::{response_digest, Md5, Sha256, DigestChallenge, DigestClient, DigestAuthenticator,
+                NONCE_LIFETIME};
+    use time;
+    use method::Method::Get;
+    use headers::authorization::Challenge;
+    use headers::test_utils::from_stream_with_str;
+
+    // The example from RFC 7616, section 3.9.1
+    static USERNAME: &'static str = "Mufasa";
+    static PASSWORD: &'static str = "Circle of Life";
+    static REALM: &'static str = "http-auth@example.org";
+    static NONCE: &'static str = "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v";
+    static CNONCE: &'static str = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+    static URI: &'static str = "/dir/index.html";
+
+    #[test]
+    fn test_response_digest() {
+        assert_eq!(&response_digest(Md5, USERNAME, REALM, PASSWORD, &Get, URI, NONCE,
+                                    Some(("auth", 1, CNONCE)))[..],
+                   "8ca523f5e9506fed4657c9700eebdbec");
+        assert_eq!(&response_digest(Sha256, USERNAME, REALM, PASSWORD, &Get, URI, NONCE,
+                                    Some(("auth", 1, CNONCE)))[..],
+                   "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1");
+    }
+
+    #[test]
+    fn test_digest_challenge() {
+        let challenges: Vec<Challenge> = from_stream_with_str("Digest realm=\"http-auth@example.org\", \
+            qop=\"auth, auth-int\", algorithm=SHA-256, \
+            nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+            opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\", \
+            Basic realm=\"x\"").unwrap();
+        let challenge = DigestChallenge::from_challenge(&challenges[0]).unwrap();
+        assert_eq!(&challenge.realm[..], REALM);
+        assert_eq!(&challenge.nonce[..], NONCE);
+        assert_eq!(challenge.algorithm, Sha256);
+        assert_eq!(challenge.qop, vec!(String::from_str("auth"), String::from_str("auth-int")));
+        assert!(!challenge.stale);
+        assert_eq!(DigestChallenge::from_challenge(&challenges[1]), None);
+        assert_eq!(DigestChallenge::from_challenge(&challenge.to_challenge()), Some(challenge));
+    }
+
+    #[test]
+    fn test_nonce_age() {
+        let authenticator = DigestAuthenticator::new(REALM, Md5);
+        let nonce = authenticator.nonce_for(1000, "salt");
+        assert_eq!(authenticator.nonce_age(&nonce[..], 1010), Some(10));
+        // An expired nonce is still genuine, so that it can be reported as stale.
+        assert_eq!(authenticator.nonce_age(&nonce[..], 1001 + NONCE_LIFETIME),
+                   Some(1 + NONCE_LIFETIME));
+        // One which was not issued by this authenticator is not.
+        let other = DigestAuthenticator::new(REALM, Md5).nonce_for(1000, "salt");
+        assert_eq!(authenticator.nonce_age(&other[..], 1010), None);
+        assert_eq!(authenticator.nonce_age(NONCE, 1010), None);
+    }
+
+    #[test]
+    fn test_challenge_nonces() {
+        let authenticator = DigestAuthenticator::new(REALM, Md5);
+        let nonce = |challenge: Challenge| {
+            DigestChallenge::from_challenge(&challenge).unwrap().nonce
+        };
+        // Challenges issued together still have nonces of their own.
+        let (first, second) = (nonce(authenticator.challenge(false)),
+                               nonce(authenticator.challenge(false)));
+        assert!(first != second);
+        assert!(authenticator.nonce_age(&first[..], time::get_time().sec).is_some());
+        let nonce_counts = authenticator.nonce_counts.lock().unwrap();
+        assert_eq!(nonce_counts.counts.get(&first), Some(&0));
+        assert_eq!(nonce_counts.counts.get(&second), Some(&0));
+    }
+
+    #[test]
+    fn test_digest_client_nonce_count() {
+        let challenge = DigestChallenge {
+            realm: String::from_str(REALM),
+            nonce: String::from_str(NONCE),
+            opaque: None,
+            algorithm: Md5,
+            qop: vec!(String::from_str("auth")),
+            stale: false,
+        };
+        let mut client = DigestClient::new(challenge, String::from_str(USERNAME),
+                                           String::from_str(PASSWORD));
+        let first = client.credentials(&Get, URI);
+        assert_eq!(first.param("nc"), Some("00000001"));
+        assert_eq!(first.param("username"), Some(USERNAME));
+        let second = client.credentials(&Get, URI);
+        assert_eq!(second.param("nc"), Some("00000002"));
+        assert!(first.param("cnonce") != second.param("cnonce"));
+        assert_eq!(client.nonce_count(), 2);
+    }
+}
@@ -0,0 +1,181 @@
+//! Forms in the `application/x-www-form-urlencoded` format, as used by query strings and the
+//! bodies of HTML form submissions.
+
+use std::slice::Iter;
+use url::percent_encoding::percent_decode;
+
+/// The default limits on a form; see `FormLimits`.
+pub const DEFAULT_MAX_FIELDS: usize = 1000;
+pub const DEFAULT_MAX_LEN: usize = 0x100000;
+
+/// The most that will be parsed of a form, to bound the work done for a request.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FormLimits {
+    /// The most fields accepted.
+    pub max_fields: usize,
+    /// The longest encoded form accepted, in bytes.
+    pub max_len: usize,
+}
+
+impl FormLimits {
+    pub fn new() -> FormLimits {
+        FormLimits {
+            max_fields: DEFAULT_MAX_FIELDS,
+            max_len: DEFAULT_MAX_LEN,
+        }
+    }
+}
+
+/// Why a form could not be parsed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FormError {
+    /// There are more fields than `FormLimits.max_fields`.
+    TooManyFields,
+    /// The encoded form is longer than `FormLimits.max_len`.
+    TooLong,
+    /// A name or value is not UTF-8 once decoded.
+    InvalidUtf8,
+}
+
+/// The fields of a form, in order; a name may occur any number of times.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+}
+
+impl Form {
+    pub fn new() -> Form {
+        Form {
+            fields: Vec::new(),
+        }
+    }
+
+    /// Parse a form from its encoded form, such as a query string (without the "?").
+    ///
+    /// "+" stands for a space and percent-encoding is undone. Empty fields ("a=1&&b=2") are
+    /// skipped, and a field with no "=" has an empty value.
+    pub fn parse(encoded: &[u8], limits: &FormLimits) -> Result<Form, FormError> {
+        if encoded.len() > limits.max_len {
+            return Err(FormError::TooLong);
+        }
+        let mut form = Form::new();
+        for field in encoded.split(|&b| b == b'&').filter(|field| !field.is_empty()) {
+            if form.len() == limits.max_fields {
+                return Err(FormError::TooManyFields);
+            }
+            let (name, value) = match field.iter().position(|&b| b == b'=') {
+                Some(i) => (&field[..i], &field[i + 1..]),
+                None => (field, &field[field.len()..]),
+            };
+            form.append(try!(decode(name)), try!(decode(value)));
+        }
+        Ok(form)
+    }
+
+    /// The number of fields, counting each repetition of a name separately.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Add a field after all existing ones.
+    pub fn append(&mut self, name: String, value: String) {
+        self.fields.push((name, value));
+    }
+
+    /// Add all the fields of another form after the existing ones.
+    pub fn extend(&mut self, other: Form) {
+        self.fields.extend(other.fields.into_iter());
+    }
+
+    /// The value of the first field with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|&&(ref n, _)| &n[..] == name)
+            .map(|&(_, ref v)| &v[..])
+    }
+
+    /// The values of all fields with the given name, in order.
+    pub fn get_all<'a>(&'a self, name: &str) -> Vec<&'a str> {
+        self.fields.iter()
+            .filter(|&&(ref n, _)| &n[..] == name)
+            .map(|&(_, ref v)| &v[..])
+            .collect()
+    }
+
+    /// Iterate over the (name, value) pairs in order.
+    pub fn iter<'a>(&'a self) -> Iter<'a, (String, String)> {
+        self.fields.iter()
+    }
+
+    /// Encode the form, as for a query string or a request body.
+    pub fn to_urlencoded(&self) -> String {
+        let mut s = String::new();
+        for (i, &(ref name, ref value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                s.push('&');
+            }
+            encode_into(&mut s, &name[..]);
+            s.push('=');
+            encode_into(&mut s, &value[..]);
+        }
+        s
+    }
+}
+
+fn decode(encoded: &[u8]) -> Result<String, FormError> {
+    let unplussed: Vec<u8> = encoded.iter().map(|&b| if b == b'+' { b' ' } else { b }).collect();
+    String::from_utf8(percent_decode(&unplussed[..])).map_err(|_| FormError::InvalidUtf8)
+}
+
+/// Append `s` encoded as in HTML's application/x-www-form-urlencoded serializer.
+fn encode_into(out: &mut String, s: &str) {
+    for &b in s.as_bytes().iter() {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'*' | b'-' | b'.' | b'_' => out.push(b as char),
+            b' ' => out.push('+'),
+            _ => out.push_str(&format!("%{:02X}", b)[..]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Form, FormLimits, FormError};
+
+    #[test]
+    fn test_parse() {
+        let form = Form::parse(b"q=rust+http&tag=a&tag=b%26c&&empty=&flag&caf%C3%A9=%E2%9C%93",
+                               &FormLimits::new()).unwrap();
+        assert_eq!(form.len(), 6);
+        assert_eq!(form.get("q"), Some("rust http"));
+        assert_eq!(form.get_all("tag"), vec!("a", "b&c"));
+        assert_eq!(form.get("empty"), Some(""));
+        assert_eq!(form.get("flag"), Some(""));
+        assert_eq!(form.get("café"), Some("✓"));
+        assert_eq!(form.get("missing"), None);
+        assert_eq!(Form::parse(b"a=%FF", &FormLimits::new()), Err(FormError::InvalidUtf8));
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = FormLimits { max_fields: 2, max_len: 10 };
+        assert!(Form::parse(b"a=1&b=2", &limits).is_ok());
+        assert_eq!(Form::parse(b"a=1&b=2&c=3", &limits), Err(FormError::TooLong));
+        let limits = FormLimits { max_fields: 2, max_len: 100 };
+        assert_eq!(Form::parse(b"a=1&b=2&c=3", &limits), Err(FormError::TooManyFields));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut form = Form::new();
+        form.append(String::from_str("name"), String::from_str("Zoë & co"));
+        form.append(String::from_str("n"), String::from_str("1+1=2"));
+        let encoded = form.to_urlencoded();
+        assert_eq!(encoded, String::from_str("name=Zo%C3%AB+%26+co&n=1%2B1%3D2"));
+        assert_eq!(Form::parse(encoded.as_bytes(), &FormLimits::new()), Ok(form));
+    }
+}
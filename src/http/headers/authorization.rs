@@ -0,0 +1,454 @@
+//! The Authorization and Proxy-Authorization request headers and the WWW-Authenticate and
+//! Proxy-Authenticate response headers, defined in RFC 7235.
+//!
+//!     credentials = auth-scheme [ 1*SP ( token68 / #auth-param ) ]
+//!     challenge   = auth-scheme [ 1*SP ( token68 / #auth-param ) ]
+//!     token68     = 1*( ALPHA / DIGIT / "-" / "." / "_" / "~" / "+" / "/" ) *"="
+//!     auth-param  = token BWS "=" BWS ( token / quoted-string )
+
+use std::ascii::AsciiExt;
+use std::str;
+use rustc_serialize::base64::{ToBase64, FromBase64, STANDARD};
+use rfc2616::{is_alpha, is_digit, SP, HT};
+use headers::serialization_utils::{maybe_quoted_string, quoted_string};
+use headers::ConsumeCommaLWSResult::{CommaConsumed, EndOfValue, ErrCommaNotFound};
+
+pub use self::AuthData::{Token68, AuthParams};
+
+/// The data following the auth-scheme in credentials or a challenge.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AuthData {
+    /// A single token68 value, as used by the Basic and Bearer schemes for credentials.
+    Token68(String),
+    /// A (possibly empty) list of auth-params, in the order they were given.
+    AuthParams(Vec<(String, String)>),
+}
+
+/// A value for the Authorization and Proxy-Authorization headers.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Credentials {
+    pub scheme: String,
+    pub data: AuthData,
+}
+
+/// A single challenge from the WWW-Authenticate and Proxy-Authenticate headers.
+///
+/// Those headers may contain several challenges, and so are typed as ``Vec<Challenge>``.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Challenge {
+    pub scheme: String,
+    pub data: AuthData,
+}
+
+impl Credentials {
+    /// Construct credentials for the Basic scheme (RFC 7617).
+    pub fn basic(user_id: &str, password: &str) -> Credentials {
+        let user_pass = format!("{}:{}", user_id, password);
+        Credentials {
+            scheme: String::from_str("Basic"),
+            data: Token68(user_pass.as_bytes().to_base64(STANDARD)),
+        }
+    }
+
+    /// Construct credentials for the Bearer scheme (RFC 6750).
+    pub fn bearer(token: &str) -> Credentials {
+        Credentials {
+            scheme: String::from_str("Bearer"),
+            data: Token68(String::from_str(token)),
+        }
+    }
+
+    /// Whether these credentials are for the given auth-scheme (compared case-insensitively).
+    pub fn is_scheme(&self, scheme: &str) -> bool {
+        self.scheme.eq_ignore_ascii_case(scheme)
+    }
+
+    /// Decode Basic credentials into a (user-id, password) pair.
+    ///
+    /// This returns ``None`` if these are not Basic credentials or if they are malformed.
+    pub fn basic_user_pass(&self) -> Option<(String, String)> {
+        if !self.is_scheme("Basic") {
+            return None;
+        }
+        let decoded = match self.data {
+            Token68(ref t) => match t.from_base64() {
+                Ok(bytes) => bytes,
+                Err(_) => return None,
+            },
+            AuthParams(_) => return None,
+        };
+        let decoded = match str::from_utf8(&decoded[..]) {
+            Ok(s) => s,
+            Err(_) => return None,
+        };
+        let mut parts = decoded.splitn(1, ':');
+        match (parts.next(), parts.next()) {
+            (Some(user_id), Some(password)) =>
+                Some((String::from_str(user_id), String::from_str(password))),
+            _ => None,
+        }
+    }
+
+    /// Get the token from Bearer credentials, or ``None`` if these are not Bearer credentials.
+    pub fn bearer_token(&self) -> Option<&str> {
+        match self.data {
+            Token68(ref t) if self.is_scheme("Bearer") => Some(&t[..]),
+            _ => None,
+        }
+    }
+
+    /// Get the value of an auth-param by name (compared case-insensitively).
+    pub fn param(&self, name: &str) -> Option<&str> {
+        find_param(&self.data, name)
+    }
+}
+
+impl Challenge {
+    /// Construct a challenge for the Basic scheme with the given realm.
+    pub fn basic(realm: &str) -> Challenge {
+        Challenge {
+            scheme: String::from_str("Basic"),
+            data: AuthParams(vec!((String::from_str("realm"), String::from_str(realm)))),
+        }
+    }
+
+    /// Construct a challenge for the Bearer scheme, with a realm if one is given.
+    pub fn bearer(realm: Option<&str>) -> Challenge {
+        Challenge {
+            scheme: String::from_str("Bearer"),
+            data: AuthParams(match realm {
+                Some(realm) => vec!((String::from_str("realm"), String::from_str(realm))),
+                None => Vec::new(),
+            }),
+        }
+    }
+
+    /// Whether this challenge is for the given auth-scheme (compared case-insensitively).
+    pub fn is_scheme(&self, scheme: &str) -> bool {
+        self.scheme.eq_ignore_ascii_case(scheme)
+    }
+
+    /// Get the value of an auth-param by name (compared case-insensitively).
+    pub fn param(&self, name: &str) -> Option<&str> {
+        find_param(&self.data, name)
+    }
+
+    /// The realm of the challenge, if it has one.
+    pub fn realm(&self) -> Option<&str> {
+        self.param("realm")
+    }
+}
+
+fn find_param<'a>(data: &'a AuthData, name: &str) -> Option<&'a str> {
+    match *data {
+        AuthParams(ref params) => params.iter()
+                                        .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+                                        .map(|&(_, ref v)| &v[..]),
+        Token68(_) => None,
+    }
+}
+
+/// token68 characters, excluding the trailing "=" padding
+#[inline]
+fn is_token68_item(b: u8) -> bool {
+    is_alpha(b) || is_digit(b) ||
+    b == b'-' || b == b'.' || b == b'_' || b == b'~' || b == b'+' || b == b'/'
+}
+
+/// The auth-params which are conventionally (or, for realm, mandatorily) sent as quoted-strings
+/// even when they would be valid tokens.
+fn always_quoted(name: &str) -> bool {
+    ["realm", "domain", "nonce", "opaque", "cnonce", "username", "uri", "response"].iter()
+        .any(|q| name.eq_ignore_ascii_case(*q))
+}
+
+/// Push the scheme and its data onto a string in the form it is written in a header.
+fn push_scheme_and_data(mut s: String, scheme: &String, data: &AuthData) -> String {
+    s.push_str(&scheme[..]);
+    match *data {
+        Token68(ref t) => {
+            s.push(' ');
+            s.push_str(&t[..]);
+        },
+        AuthParams(ref params) => {
+            for (i, &(ref k, ref v)) in params.iter().enumerate() {
+                s.push_str(if i == 0 { " " } else { ", " });
+                s.push_str(&k[..]);
+                s.push('=');
+                if always_quoted(&k[..]) {
+                    s.push_str(&quoted_string(v)[..]);
+                } else {
+                    s.push_str(&maybe_quoted_string(v)[..]);
+                }
+            }
+        },
+    }
+    s
+}
+
+/// An item in the auth data: a token68 (which may also turn out to be the auth-scheme of the next
+/// challenge) or an auth-param.
+enum AuthItem {
+    Token68Item(String),
+    ParamItem(String, String),
+}
+
+/// Read a token68 or an auth-param from the current position.
+///
+/// Should it be a token68, the next byte (if any) will not have been consumed.
+fn read_auth_item<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>) -> Option<AuthItem> {
+    let mut name = String::new();
+    loop {
+        match reader.next() {
+            Some(b) if is_token68_item(b) => name.push(b as char),
+            Some(b) => {
+                assert_eq!(reader.next_byte, None);
+                reader.next_byte = Some(b);
+                break;
+            },
+            None => break,
+        }
+    }
+    if name.len() == 0 {
+        return None;
+    }
+    reader.consume_optional_lws();
+    match reader.next() {
+        Some(b'=') => (),
+        Some(b) => {
+            // The end of a token68 (or the auth-scheme of the next challenge)
+            reader.next_byte = Some(b);
+            return Some(AuthItem::Token68Item(name));
+        },
+        None => return Some(AuthItem::Token68Item(name)),
+    }
+    // One "=" could be either a parameter or the token68 padding; "==" can only be padding.
+    match reader.next() {
+        Some(b'=') => {
+            name.push_str("==");
+            loop {
+                match reader.next() {
+                    Some(b'=') => name.push('='),
+                    Some(b) => {
+                        reader.next_byte = Some(b);
+                        break;
+                    },
+                    None => break,
+                }
+            }
+            Some(AuthItem::Token68Item(name))
+        },
+        Some(b',') => {
+            reader.next_byte = Some(b',');
+            name.push('=');
+            Some(AuthItem::Token68Item(name))
+        },
+        None => {
+            name.push('=');
+            Some(AuthItem::Token68Item(name))
+        },
+        Some(b) => {
+            reader.next_byte = Some(b);
+            reader.consume_optional_lws();
+            if name.as_bytes().contains(&b'/') {
+                // Not a valid token, so it can't be an auth-param name
+                return None;
+            }
+            match reader.read_token_or_quoted_string() {
+                Some(value) => Some(AuthItem::ParamItem(name, value)),
+                None => None,
+            }
+        },
+    }
+}
+
+/// Read the auth-scheme and the data following it. Where this is one of a list of challenges, the
+/// auth-scheme may already have been read while reading the preceding challenge.
+///
+/// The second value returned is the auth-scheme of the next challenge, if one was found.
+fn read_scheme_and_data<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>,
+                                   scheme: Option<String>, allow_more: bool)
+                                   -> Option<(String, AuthData, Option<String>)> {
+    let scheme = match scheme {
+        Some(scheme) => scheme,
+        None => match reader.read_token() {
+            Some(scheme) => scheme,
+            None => return None,
+        },
+    };
+    match reader.next() {
+        Some(b) if b == SP || b == HT => reader.consume_optional_lws(),
+        Some(b',') if allow_more => {
+            reader.next_byte = Some(b',');
+            return Some((scheme, AuthParams(Vec::new()), None));
+        },
+        Some(_) => return None,
+        None => return Some((scheme, AuthParams(Vec::new()), None)),
+    }
+    let mut params = match read_auth_item(reader) {
+        None => return Some((scheme, AuthParams(Vec::new()), None)),
+        Some(AuthItem::Token68Item(t)) => return Some((scheme, Token68(t), None)),
+        Some(AuthItem::ParamItem(k, v)) => vec!((k, v)),
+    };
+    loop {
+        match reader.consume_comma_lws() {
+            CommaConsumed => (),
+            EndOfValue => return Some((scheme, AuthParams(params), None)),
+            ErrCommaNotFound => return None,
+        }
+        match read_auth_item(reader) {
+            Some(AuthItem::ParamItem(k, v)) => params.push((k, v)),
+            Some(AuthItem::Token68Item(next_scheme)) => {
+                if !allow_more || next_scheme.ends_with("=") || next_scheme.contains("/") {
+                    return None;
+                }
+                return Some((scheme, AuthParams(params), Some(next_scheme)));
+            },
+            // An empty list element; tolerated as RFC 7230, section 7 asks
+            None => continue,
+        }
+    }
+}
+
+impl super::HeaderConvertible for Credentials {
+    fn from_stream<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>)
+            -> Option<Credentials> {
+        match read_scheme_and_data(reader, None, false) {
+            Some((scheme, data, None)) => reader.some_if_consumed(Credentials {
+                scheme: scheme,
+                data: data,
+            }),
+            _ => None,
+        }
+    }
+
+    fn http_value(&self) -> String {
+        push_scheme_and_data(String::new(), &self.scheme, &self.data)
+    }
+}
+
+impl super::HeaderConvertible for Challenge {
+    fn from_stream<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>)
+            -> Option<Challenge> {
+        match read_scheme_and_data(reader, None, false) {
+            Some((scheme, data, None)) => reader.some_if_consumed(Challenge {
+                scheme: scheme,
+                data: data,
+            }),
+            _ => None,
+        }
+    }
+
+    fn http_value(&self) -> String {
+        push_scheme_and_data(String::new(), &self.scheme, &self.data)
+    }
+}
+
+/// Challenges can't use ``CommaListHeaderConvertible``: the comma separating two challenges is
+/// indistinguishable from that separating two auth-params until the token after it has been read.
+impl super::HeaderConvertible for Vec<Challenge> {
+    fn from_stream<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>)
+            -> Option<Vec<Challenge>> {
+        let mut result = Vec::new();
+        let mut next_scheme = None;
+        loop {
+            let (scheme, data, following) = match read_scheme_and_data(reader, next_scheme, true) {
+                Some(v) => v,
+                None => return None,
+            };
+            result.push(Challenge { scheme: scheme, data: data });
+            next_scheme = following;
+            if next_scheme.is_some() {
+                continue;
+            }
+            match reader.consume_comma_lws() {
+                CommaConsumed => continue,
+                EndOfValue => break,
+                ErrCommaNotFound => return None,
+            }
+        }
+        Some(result)
+    }
+
+    fn http_value(&self) -> String {
+        let mut out = String::new();
+        for (i, challenge) in self.iter().enumerate() {
+            if i != 0 {
+                out.push_str(", ");
+            }
+            out = push_scheme_and_data(out, &challenge.scheme, &challenge.data);
+        }
+        out
+    }
+
+    fn combine(&mut self, other: Vec<Challenge>) -> Result<(), Vec<Challenge>> {
+        self.extend(other.into_iter());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_credentials() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==",
+                              Credentials::basic("Aladdin", "open sesame"));
+    assert_conversion_correct("Bearer mF_9.B5f-4.1JqM", Credentials::bearer("mF_9.B5f-4.1JqM"));
+    assert_conversion_correct("Newauth realm=\"apps\", type=1",
+                              Credentials {
+                                  scheme: String::from_str("Newauth"),
+                                  data: AuthParams(vec!((String::from_str("realm"), String::from_str("apps")),
+                                                        (String::from_str("type"), String::from_str("1")))),
+                              });
+    assert_conversion_correct("Negotiate", Credentials {
+                                  scheme: String::from_str("Negotiate"),
+                                  data: AuthParams(Vec::new()),
+                              });
+    assert_interpretation_correct("bearer  abc/def=", Credentials {
+                                      scheme: String::from_str("bearer"),
+                                      data: Token68(String::from_str("abc/def=")),
+                                  });
+    assert_interpretation_correct("Newauth realm = apps ,type=\"1\"", Credentials {
+                                      scheme: String::from_str("Newauth"),
+                                      data: AuthParams(vec!((String::from_str("realm"), String::from_str("apps")),
+                                                            (String::from_str("type"), String::from_str("1")))),
+                                  });
+
+    assert_invalid::<Credentials>("");
+    assert_invalid::<Credentials>("Basic abc def");
+    assert_invalid::<Credentials>("Basic abc==, realm=x");
+    assert_invalid::<Credentials>("Newauth realm=x, Basic");
+}
+
+#[test]
+fn test_challenges() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("Basic realm=\"simple\"", vec!(Challenge::basic("simple")));
+    assert_conversion_correct("Newauth realm=\"apps\", type=1, title=\"Login to apps\", Basic realm=\"simple\"",
+                              vec!(Challenge {
+                                       scheme: String::from_str("Newauth"),
+                                       data: AuthParams(vec!((String::from_str("realm"), String::from_str("apps")),
+                                                             (String::from_str("type"), String::from_str("1")),
+                                                             (String::from_str("title"), String::from_str("Login to apps")))),
+                                   },
+                                   Challenge::basic("simple")));
+    assert_conversion_correct("Bearer, Basic realm=\"x\"",
+                              vec!(Challenge::bearer(None), Challenge::basic("x")));
+    assert_interpretation_correct("Basic realm=simple ,  Bearer", vec!(Challenge::basic("simple"),
+                                                                       Challenge::bearer(None)));
+
+    assert_invalid::<Vec<Challenge>>("");
+    assert_invalid::<Vec<Challenge>>("Basic realm=\"x\" Bearer");
+}
+
+#[test]
+fn test_basic_helpers() {
+    let credentials = Credentials::basic("Aladdin", "open sesame");
+    assert_eq!(credentials.basic_user_pass(),
+               Some((String::from_str("Aladdin"), String::from_str("open sesame"))));
+    assert_eq!(credentials.bearer_token(), None);
+    assert_eq!(Credentials::bearer("abc").bearer_token(), Some("abc"));
+    assert_eq!(Credentials::bearer("abc").basic_user_pass(), None);
+    assert_eq!(Challenge::basic("simple").realm(), Some("simple"));
+}
@@ -0,0 +1,108 @@
+//! The Content-Disposition header, defined in RFC 6266 and, for the parts of multipart/form-data,
+//! RFC 7578.
+
+use headers::serialization_utils::{push_parameters, WriterUtil};
+use std::ascii::AsciiExt;
+use std::old_io::IoResult;
+use std::fmt;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ContentDisposition {
+    /// The disposition type, such as "form-data", "attachment" or "inline".
+    pub disposition: String,
+    pub parameters: Vec<(String, String)>,
+}
+
+impl ContentDisposition {
+    pub fn new(disposition: String, parameters: Vec<(String, String)>) -> ContentDisposition {
+        ContentDisposition {
+            disposition: disposition,
+            parameters: parameters,
+        }
+    }
+
+    /// The value of the named parameter; names are case-insensitive.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.parameters.iter()
+            .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+    }
+
+    /// The name of the form field, for a part of multipart/form-data.
+    pub fn name(&self) -> Option<&str> {
+        self.param("name")
+    }
+
+    /// The name of the file which the content came from or should be saved as.
+    pub fn filename(&self) -> Option<&str> {
+        self.param("filename")
+    }
+}
+
+impl fmt::Display for ContentDisposition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&push_parameters(self.disposition.clone(), &self.parameters[..])[..])
+    }
+}
+
+/// Browsers send file names as raw UTF-8 in quoted strings, which are read a byte to a char;
+/// put such bytes back together.
+fn reinterpret_as_utf8(s: String) -> String {
+    if s.is_ascii() {
+        return s;
+    }
+    let bytes: Vec<u8> = s.chars().map(|c| c as u8).collect();
+    match String::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(_) => s,
+    }
+}
+
+impl super::HeaderConvertible for ContentDisposition {
+    fn from_stream<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>)
+            -> Option<ContentDisposition> {
+        let disposition = match reader.read_token() {
+            Some(v) => v,
+            None => return None,
+        };
+        match reader.read_parameters() {
+            Some(parameters) => {
+                let parameters = parameters.into_iter()
+                    .map(|(k, v)| (k, reinterpret_as_utf8(v)))
+                    .collect();
+                reader.some_if_consumed(ContentDisposition {
+                    disposition: disposition,
+                    parameters: parameters,
+                })
+            },
+            None => None,
+        }
+    }
+
+    fn to_stream<W: Writer>(&self, writer: &mut W) -> IoResult<()> {
+        try!(writer.write_token(&self.disposition));
+        writer.write_parameters(&self.parameters[..])
+    }
+
+    fn http_value(&self) -> String {
+        format!("{}", self)
+    }
+}
+
+#[test]
+fn test_content_disposition() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("attachment", ContentDisposition::new(String::from_str("attachment"),
+                                                                    Vec::new()));
+    assert_conversion_correct("form-data;name=field",
+                              ContentDisposition::new(String::from_str("form-data"),
+                                  vec!((String::from_str("name"), String::from_str("field")))));
+    assert_interpretation_correct("form-data; name=\"upload\"; filename=\"café.txt\"",
+                                  ContentDisposition::new(String::from_str("form-data"),
+                                      vec!((String::from_str("name"), String::from_str("upload")),
+                                           (String::from_str("filename"),
+                                            String::from_str("café.txt")))));
+    assert_invalid::<ContentDisposition>("");
+    assert_invalid::<ContentDisposition>("form-data; name");
+}
@@ -1,4 +1,6 @@
 use headers::serialization_utils::{push_quoted_string, quoted_string, WriterUtil};
+use headers::HeaderValueByteIterator;
+use headers::ConsumeCommaLWSResult::{CommaConsumed, EndOfValue, ErrCommaNotFound};
 use std::old_io::IoResult;
 use std::fmt;
 
@@ -22,6 +24,20 @@ pub fn strong_etag(opaque_tag: String) -> EntityTag {
     }
 }
 
+impl EntityTag {
+    /// The strong comparison (RFC 7232 §2.3.2): both tags are strong and their opaque tags are
+    /// the same. This is the comparison for If-Match and If-Range.
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.opaque_tag == other.opaque_tag
+    }
+
+    /// The weak comparison (RFC 7232 §2.3.2): the opaque tags are the same, whether either tag is
+    /// weak or not. This is the comparison for If-None-Match.
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.opaque_tag == other.opaque_tag
+    }
+}
+
 impl fmt::Display for EntityTag {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.weak {
@@ -32,30 +48,41 @@ impl fmt::Display for EntityTag {
     }
 }
 
-impl super::HeaderConvertible for EntityTag {
-    fn from_stream<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>) -> Option<EntityTag> {
-        let weak = match reader.next() {
-            Some(b) if b == b'W' || b == b'w' => {
-                if reader.next() != Some(b'/') || reader.next() != Some(b'"') {
-                    return None;
-                }
-                true
-            },
-            Some(b) if b == b'"' => {
-                false
-            },
-            _ => {
+/// Read an entity tag whose first byte has already been taken from the reader, leaving whatever
+/// follows it (such as the comma before another tag) unread.
+fn read_entity_tag<R: Reader>(first: Option<u8>, reader: &mut HeaderValueByteIterator<R>)
+        -> Option<EntityTag> {
+    let weak = match first {
+        Some(b) if b == b'W' || b == b'w' => {
+            if reader.next() != Some(b'/') || reader.next() != Some(b'"') {
                 return None;
             }
-        };
-        let opaque_tag = match reader.read_quoted_string(true) {
-            Some(tag) => tag,
-            None => return None,
-        };
-        reader.some_if_consumed(EntityTag {
-            weak: weak,
-            opaque_tag: opaque_tag,
-        })
+            true
+        },
+        Some(b) if b == b'"' => {
+            false
+        },
+        _ => {
+            return None;
+        }
+    };
+    let opaque_tag = match reader.read_quoted_string(true) {
+        Some(tag) => tag,
+        None => return None,
+    };
+    Some(EntityTag {
+        weak: weak,
+        opaque_tag: opaque_tag,
+    })
+}
+
+impl super::HeaderConvertible for EntityTag {
+    fn from_stream<R: Reader>(reader: &mut HeaderValueByteIterator<R>) -> Option<EntityTag> {
+        let first = reader.next();
+        match read_entity_tag(first, reader) {
+            Some(etag) => reader.some_if_consumed(etag),
+            None => None,
+        }
     }
 
     fn to_stream<W: Writer>(&self, writer: &mut W) -> IoResult<()> {
@@ -70,6 +97,65 @@ impl super::HeaderConvertible for EntityTag {
     }
 }
 
+/// The value of the If-Match and If-None-Match headers: `*` or a list of entity tags.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum EntityTagMatch {
+    /// `*`, which matches any current representation.
+    Any,
+    Tags(Vec<EntityTag>),
+}
+
+impl EntityTagMatch {
+    /// Whether the tag matches, by the strong comparison (for If-Match).
+    pub fn matches_strong(&self, etag: &EntityTag) -> bool {
+        match *self {
+            EntityTagMatch::Any => true,
+            EntityTagMatch::Tags(ref tags) => tags.iter().any(|tag| tag.strong_eq(etag)),
+        }
+    }
+
+    /// Whether the tag matches, by the weak comparison (for If-None-Match).
+    pub fn matches_weak(&self, etag: &EntityTag) -> bool {
+        match *self {
+            EntityTagMatch::Any => true,
+            EntityTagMatch::Tags(ref tags) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        }
+    }
+}
+
+impl super::HeaderConvertible for EntityTagMatch {
+    fn from_stream<R: Reader>(reader: &mut HeaderValueByteIterator<R>)
+            -> Option<EntityTagMatch> {
+        let mut next = reader.next();
+        if next == Some(b'*') {
+            return reader.some_if_consumed(EntityTagMatch::Any);
+        }
+        let mut tags = Vec::new();
+        loop {
+            match read_entity_tag(next, reader) {
+                Some(etag) => tags.push(etag),
+                None => return None,
+            }
+            match reader.consume_comma_lws() {
+                CommaConsumed => next = reader.next(),
+                EndOfValue => break,
+                ErrCommaNotFound => return None,
+            }
+        }
+        Some(EntityTagMatch::Tags(tags))
+    }
+
+    fn http_value(&self) -> String {
+        match *self {
+            EntityTagMatch::Any => String::from_str("*"),
+            EntityTagMatch::Tags(ref tags) => {
+                let tags: Vec<String> = tags.iter().map(|tag| format!("{}", tag)).collect();
+                tags.connect(", ")
+            },
+        }
+    }
+}
+
 #[test]
 fn test_etag() {
     use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
@@ -91,4 +177,38 @@ fn test_etag() {
     assert_invalid::<EntityTag>("fO0");
     assert_invalid::<EntityTag>("\"\\\"");
     assert_invalid::<EntityTag>("\"\"\"\"");
+    assert_invalid::<EntityTag>("\"a\", \"b\"");
+}
+
+#[test]
+fn test_comparison() {
+    let (strong, weak) = (strong_etag(String::from_str("1")), weak_etag(String::from_str("1")));
+    let other = strong_etag(String::from_str("2"));
+    assert!(strong.strong_eq(&strong));
+    assert!(!strong.strong_eq(&weak));
+    assert!(!weak.strong_eq(&weak));
+    assert!(!strong.strong_eq(&other));
+    assert!(strong.weak_eq(&weak));
+    assert!(weak.weak_eq(&weak));
+    assert!(!weak.weak_eq(&other));
+}
+
+#[test]
+fn test_entity_tag_match() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    assert_conversion_correct("*", EntityTagMatch::Any);
+    assert_conversion_correct("\"a\", W/\"b\"",
+                              EntityTagMatch::Tags(vec!(strong_etag(String::from_str("a")),
+                                                        weak_etag(String::from_str("b")))));
+    assert_interpretation_correct("\"a\",W/\"b\"",
+                                  EntityTagMatch::Tags(vec!(strong_etag(String::from_str("a")),
+                                                            weak_etag(String::from_str("b")))));
+    assert_invalid::<EntityTagMatch>("a, b");
+    assert_invalid::<EntityTagMatch>("*, \"a\"");
+
+    let tags = EntityTagMatch::Tags(vec!(weak_etag(String::from_str("a"))));
+    assert!(tags.matches_weak(&strong_etag(String::from_str("a"))));
+    assert!(!tags.matches_strong(&strong_etag(String::from_str("a"))));
+    assert!(EntityTagMatch::Any.matches_strong(&strong_etag(String::from_str("z"))));
 }
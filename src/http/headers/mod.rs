@@ -28,6 +28,13 @@ pub enum HeaderLineErr {
 
 pub mod test_utils;
 pub mod serialization_utils;
+pub mod raw;
+pub mod typed;
+
+/// Generated functions for finding a header name in the `request` and `response` tables.
+mod header_names {
+    include!(concat!(env!("OUT_DIR"), "/header_names.rs"));
+}
 
 /* TODO: ensure we've got all standard HTTP headers, not just those in RFC 2616.
 
@@ -62,14 +69,17 @@ be the more canonical source.
 //pub mod accept_encoding;
 //pub mod accept_language;
 pub mod accept_ranges;
+pub mod authorization;
 //pub mod cache_control;
 pub mod connection;
+pub mod content_disposition;
 //pub mod content_encoding;
 //pub mod content_range;
 pub mod content_type;
 pub mod etag;
 pub mod host;
 pub mod transfer_encoding;
+pub mod upgrade;
 
 pub type DeltaSeconds = u64;
 
@@ -102,9 +112,8 @@ pub trait HeaderEnum {
         -> Option<Self>;
 }
 
-/// Shifted out of being a default method to fix an ICE (not yet reported, TODO)
-pub fn header_enum_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R)
-        -> (Result<E, HeaderLineErr>, Option<u8>) {
+/// Read a header name and the colon after it, leaving the reader at the start of the value.
+fn read_header_name<R: Reader>(reader: &mut R) -> Result<String, HeaderLineErr> {
     enum State { Start, ReadingName, NameFinished, GotCR }
 
     let mut state = State::Start;
@@ -118,14 +127,23 @@ pub fn header_enum_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R)
             // TODO: check up on the rules for a line like "Name : value". Full LWS?
             (State::Start, Ok(b)) if b == CR => State::GotCR,
             (State::Start, Ok(b)) | (State::GotCR, Ok(b)) if b == LF => {
-                return (Err(EndOfHeaders), None);
+                return Err(EndOfHeaders);
             },
             (_, Ok(b)) if b == SP => State::NameFinished,
-            (_, Ok(b)) if b == COLON => break,
-            (_, Ok(_)) => return (Err(MalformedHeaderSyntax), None),
-            (_, Err(_)) => return (Err(EndOfFile), None),
+            (_, Ok(b)) if b == COLON => return Ok(header_name),
+            (_, Ok(_)) => return Err(MalformedHeaderSyntax),
+            (_, Err(_)) => return Err(EndOfFile),
         }
     }
+}
+
+/// Shifted out of being a default method to fix an ICE (not yet reported, TODO)
+pub fn header_enum_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R)
+        -> (Result<E, HeaderLineErr>, Option<u8>) {
+    let header_name = match read_header_name(reader) {
+        Ok(name) => name,
+        Err(err) => return (Err(err), None),
+    };
     let mut iter = HeaderValueByteIterator::new(reader);
     let header = HeaderEnum::value_from_stream(normalise_header_name(&header_name[..]), &mut iter);
     // Ensure that the entire header line is consumed (don't want to mess up next header!)
@@ -140,6 +158,25 @@ pub fn header_enum_from_stream<R: Reader, E: HeaderEnum>(reader: &mut R)
     }
 }
 
+/// Read a header line without interpreting it: the name as it was sent, and the value.
+///
+/// Folded lines are joined (the CR LF is dropped) and leading and trailing white space is removed;
+/// otherwise the value is as it was received. The second value is the extra byte read, as for
+/// `header_enum_from_stream`.
+pub fn raw_header_from_stream<R: Reader>(reader: &mut R)
+        -> (Result<(String, Vec<u8>), HeaderLineErr>, Option<u8>) {
+    let header_name = match read_header_name(reader) {
+        Ok(name) => name,
+        Err(err) => return (Err(err), None),
+    };
+    let mut iter = HeaderValueByteIterator::new(reader);
+    let mut value: Vec<u8> = iter.by_ref().collect();
+    while value.last().map_or(false, |&b| b == SP || b == HT) {
+        value.pop();
+    }
+    (Ok((header_name, value)), iter.next_byte)
+}
+
 #[derive(PartialEq, Eq)]
 enum HeaderValueByteIteratorState {
     Normal,  // Anything other than the rest.
@@ -574,6 +611,22 @@ pub trait HeaderConvertible: PartialEq + Clone + Debug {
      * For types which implement ``Str``, a body of ``String::from_str(self)`` will often be sufficient.
      */
     fn http_value(&self) -> String;
+
+    /**
+     * Merge in the value of a repeated header field, as if the two had been sent as one field with
+     * their values joined by a comma (RFC 7230 §3.2.2).
+     *
+     * Headers whose values are comma-separated lists combine by appending. Anything else cannot be
+     * combined: a repetition of the same value is tolerated, but a different value is handed back
+     * as an error, for the current value should not be silently replaced.
+     */
+    fn combine(&mut self, other: Self) -> Result<(), Self> {
+        if *self == other {
+            Ok(())
+        } else {
+            Err(other)
+        }
+    }
 }
 
 /// A header with multiple comma-separated values. Implement this and a HeaderConvertible
@@ -618,6 +671,11 @@ impl<T: CommaListHeaderConvertible> HeaderConvertible for Vec<T> {
         }
         out
     }
+
+    fn combine(&mut self, other: Vec<T>) -> Result<(), Vec<T>> {
+        self.extend(other.into_iter());
+        Ok(())
+    }
 }
 
 // Now let's have some common implementation types.
@@ -634,6 +692,18 @@ impl HeaderConvertible for String {
     fn http_value(&self) -> String {
         self.clone()
     }
+
+    /// Arbitrary string headers are assumed to be lists; headers which are not lists should not be
+    /// repeated in the first place.
+    fn combine(&mut self, other: String) -> Result<(), String> {
+        if self.is_empty() {
+            *self = other;
+        } else if !other.is_empty() {
+            self.push_str(", ");
+            self.push_str(&other[..]);
+        }
+        Ok(())
+    }
 }
 
 impl HeaderConvertible for usize {
@@ -872,6 +942,48 @@ mod test {
     fn test_to_stream() {
         assert_eq!(to_stream_into_str(&sample_tm()), String::from_str("Sun, 06 Nov 1994 08:49:37 GMT"));
     }
+
+    #[test]
+    fn test_append_raw_repeated_headers() {
+        use headers::request::HeaderCollection;
+        use headers::connection::Connection::{Close, Token};
+        use headers::raw::HeaderName;
+
+        let mut headers = HeaderCollection::new();
+        assert_eq!(headers.append_raw("Via", b"1.0 fred"), Ok(()));
+        assert_eq!(headers.append_raw("X-Trace", b"a"), Ok(()));
+        assert_eq!(headers.append_raw("via", b"1.1 nowhere.com"), Ok(()));
+        assert_eq!(headers.append_raw("Connection", b"close"), Ok(()));
+        assert_eq!(headers.append_raw("connection", b"Foo"), Ok(()));
+        assert_eq!(headers.append_raw("x-trace", b"b"), Ok(()));
+        assert_eq!(headers.append_raw("Content-Length", b"4"), Ok(()));
+        assert_eq!(headers.append_raw("Content-Length", b"4"), Ok(()));
+        assert_eq!(headers.append_raw("Content-Length", b"5"), Err(()));
+
+        assert_eq!(headers.via, Some(String::from_str("1.0 fred, 1.1 nowhere.com")));
+        assert_eq!(headers.connection, Some(vec!(Close, Token(String::from_str("Foo")))));
+        assert_eq!(headers.content_length, Some(4));
+        assert_eq!(headers.get_all("X-TRACE"), vec!(String::from_str("a"), String::from_str("b")));
+        assert_eq!(headers.get_all("via"), vec!(String::from_str("1.0 fred, 1.1 nowhere.com")));
+        assert_eq!(headers.get_all("Max-Forwards"), Vec::<String>::new());
+
+        assert_eq!(headers.raw.len(), 9);
+        assert_eq!(headers.raw.get_all("X-Trace"), vec!(&b"a"[..], &b"b"[..]));
+        let names: Vec<&str> = headers.raw.iter().take(3).map(|&(ref n, _)| &n[..]).collect();
+        assert_eq!(names, vec!("Via", "X-Trace", "via"));
+        // A name spelt in the usual way is not copied.
+        assert!(match headers.raw.iter().next() {
+            Some(&(HeaderName::Standard("Via"), _)) => true,
+            _ => false,
+        });
+
+        let written: Vec<String> = headers.iter().filter_map(|h| match h {
+            ::headers::request::Header::ExtensionHeader(name, value) =>
+                Some(format!("{}: {}", name, value)),
+            _ => None,
+        }).collect();
+        assert_eq!(written, vec!(String::from_str("X-Trace: a"), String::from_str("x-trace: b")));
+    }
 }
 
 macro_rules! headers_mod {
@@ -881,6 +993,7 @@ macro_rules! headers_mod {
         //$($attrs:attr)*
         pub mod $mod_name:ident;
         num_headers: $num_headers:expr;
+        index_function: $index_function:ident;
         $(
             $num_id:pat,
             $output_name:expr,
@@ -896,15 +1009,41 @@ macro_rules! headers_mod {
 
             #[allow(unused_imports)]
             use std::old_io::{BufReader, IoResult};
-            use std::ascii::OwnedAsciiExt;
+            use std::any::{Any, TypeId};
+            use std::ascii::AsciiExt;
+            use std::collections::HashMap;
+            use std::slice::Iter;
             use time;
-            use std::collections::hash_map::{HashMap, Iter};
             use headers;
+            use headers::raw::{RawHeaders, ReceivedHeaders, HeaderName};
+            use headers::typed::{TypedHeader, HeaderRegistry, ParsedValue, value_from_bytes,
+                                 type_mismatch};
             use headers::{HeaderEnum, HeaderConvertible, HeaderValueByteIterator};
+            use headers::header_names::$index_function as header_index;
 
             use self::Header::{$($caps_ident),+};
             use self::Header::ExtensionHeader;
 
+            /// Marker types for the strongly-typed headers, for use with `HeaderCollection.get`
+            /// and `HeaderCollection.set`.
+            pub mod typed {
+                #[allow(unused_imports)]
+                use time;
+                #[allow(unused_imports)]
+                use headers;
+                use headers::typed::TypedHeader;
+
+                $(
+                    #[allow(missing_copy_implementations)]
+                    pub struct $caps_ident;
+
+                    impl TypedHeader for $caps_ident {
+                        type Value = $htype;
+                        fn header_name() -> &'static str { $output_name }
+                    }
+                )*
+            }
+
             pub enum Header {
                 $($caps_ident($htype),)*
                 ExtensionHeader(String, String),
@@ -913,22 +1052,181 @@ macro_rules! headers_mod {
             #[derive(Clone)]
             pub struct HeaderCollection {
                 $(pub $lower_ident: Option<$htype>,)*
-                pub extensions: HashMap<String, String>,
+                /// Headers which are not strongly typed, in order, repetitions included.
+                pub extensions: RawHeaders,
+                /// Every header field as it was received, in order and with its original name and
+                /// bytes.
+                ///
+                /// This is only filled by `append_raw`: it does not follow changes made with
+                /// `insert`, `append` or `set`, and is not consulted when writing the headers.
+                pub raw: ReceivedHeaders,
+                /// The values of the extension headers in the registry, parsed as they were
+                /// received; keyed by the lowercase name, and dropped when the header is changed.
+                parsed: HashMap<String, Box<ParsedValue>>,
             }
 
             impl HeaderCollection {
                 pub fn new() -> HeaderCollection {
                     HeaderCollection {
                         $($lower_ident: None,)*
-                        extensions: HashMap::new(),
+                        extensions: RawHeaders::new(),
+                        raw: ReceivedHeaders::new(),
+                        parsed: HashMap::new(),
                     }
                 }
 
                 /// Consume a header, putting it into this structure.
+                ///
+                /// This replaces any existing value of the header; see also `append`.
                 pub fn insert(&mut self, header: Header) {
                     match header {
                         $($caps_ident(value) => self.$lower_ident = Some(value),)*
-                        ExtensionHeader(key, value) => { self.extensions.insert(key, value); },
+                        ExtensionHeader(key, value) => {
+                            self.parsed.remove(&key.to_ascii_lowercase());
+                            self.extensions.insert(key, value);
+                        },
+                    }
+                }
+
+                /// Consume a header, merging it with any existing value of the same header.
+                ///
+                /// List headers gain the new items; extension headers are kept as a separate field.
+                /// If the header already has a value which the new one cannot be combined with
+                /// (e.g. two different values of Content-Length), the existing value is kept and
+                /// this returns an error.
+                pub fn append(&mut self, header: Header) -> Result<(), ()> {
+                    match header {
+                        $($caps_ident(value) => match self.$lower_ident {
+                            Some(ref mut existing) => existing.combine(value).map_err(|_| ()),
+                            None => {
+                                self.$lower_ident = Some(value);
+                                Ok(())
+                            },
+                        },)*
+                        ExtensionHeader(key, value) => {
+                            self.parsed.remove(&key.to_ascii_lowercase());
+                            self.extensions.append(key, value);
+                            Ok(())
+                        },
+                    }
+                }
+
+                /// Record a header field as received and merge it into the collection.
+                ///
+                /// The field is kept in `raw` whatever happens; the result is an error if the value
+                /// is not valid for a strongly-typed header or cannot be combined with its existing
+                /// value (see `append`).
+                pub fn append_raw(&mut self, name: &str, value: &[u8]) -> Result<(), ()> {
+                    self.append_raw_with(name, value, &HeaderRegistry::new())
+                }
+
+                /// As `append_raw`, but a header in the registry must also have a valid value.
+                ///
+                /// The name is looked up where it lies; it is only copied for an extension header
+                /// or a strongly-typed one which is not spelt in the usual way.
+                pub fn append_raw_with(&mut self, name: &str, value: &[u8],
+                                       registry: &HeaderRegistry) -> Result<(), ()> {
+                    let index = header_index(name.as_bytes());
+                    match index.map(standard_name) {
+                        Some(standard) if standard == name =>
+                            self.raw.append_standard(standard, value.to_vec()),
+                        _ => self.raw.append(String::from_str(name), value.to_vec()),
+                    }
+                    let mut reader = BufReader::new(value);
+                    let mut value_iter = HeaderValueByteIterator::new(&mut reader);
+                    let index = match index {
+                        Some(index) => index,
+                        None => {
+                            // The registry is only for extension headers.
+                            let key = name.to_ascii_lowercase();
+                            let mut parsed = self.parsed.remove(&key);
+                            let valid = registry.parse(name, value, &mut parsed);
+                            if let Some(parsed) = parsed {
+                                self.parsed.insert(key, parsed);
+                            }
+                            if valid == Some(false) {
+                                return Err(());
+                            }
+                            self.extensions.append(String::from_str(name),
+                                                   value_iter.collect_to_string());
+                            return Ok(());
+                        },
+                    };
+                    match Header::from_index(index, &mut value_iter) {
+                        Some(h) => self.append(h),
+                        None => Err(()),
+                    }
+                }
+
+                /// The value of the header `H`, whether it is one of the strongly-typed headers or
+                /// an extension header (in which case any repeated fields are combined).
+                ///
+                /// This is ``None`` if the header is absent or its value is not valid for its type.
+                /// The value of an extension header in the registry was parsed when it was
+                /// received, and is not parsed again.
+                pub fn get<H: TypedHeader>(&self) -> Option<H::Value> {
+                    let name = H::header_name();
+                    match header_index(name.as_bytes()) {
+                        $(Some($num_id) => return match (&self.$lower_ident as &Any)
+                                .downcast_ref::<Option<H::Value>>() {
+                            Some(value) => value.clone(),
+                            None => type_mismatch(name),
+                        },)*
+                        _ => (),
+                    }
+                    match self.parsed.get(&name.to_ascii_lowercase()) {
+                        Some(parsed) => match parsed.as_any().downcast_ref::<H::Value>() {
+                            Some(value) => return Some(value.clone()),
+                            None => (),
+                        },
+                        None => (),
+                    }
+                    let mut result: Option<H::Value> = None;
+                    for value in self.extensions.get_all(name).into_iter() {
+                        let value = match value_from_bytes::<H::Value>(value.as_bytes()) {
+                            Some(v) => v,
+                            None => return None,
+                        };
+                        result = match result {
+                            None => Some(value),
+                            Some(mut existing) => match existing.combine(value) {
+                                Ok(()) => Some(existing),
+                                Err(_) => return None,
+                            },
+                        };
+                    }
+                    result
+                }
+
+                /// Set the value of the header `H`, replacing any existing value.
+                ///
+                /// Panics if `H` has the name of a strongly-typed header but another `Value` type.
+                pub fn set<H: TypedHeader>(&mut self, value: H::Value) {
+                    let name = H::header_name();
+                    match header_index(name.as_bytes()) {
+                        $(Some($num_id) => match (&mut self.$lower_ident as &mut Any)
+                                .downcast_mut::<Option<H::Value>>() {
+                            Some(field) => {
+                                *field = Some(value);
+                                return;
+                            },
+                            None => type_mismatch(name),
+                        },)*
+                        _ => (),
+                    }
+                    self.parsed.remove(&name.to_ascii_lowercase());
+                    self.extensions.insert(String::from_str(name), value.http_value());
+                }
+
+                /// The values of the named header, as they would be written.
+                ///
+                /// A strongly-typed header has at most one value, for repeated fields have been
+                /// combined; extension headers have one value per field, in order.
+                pub fn get_all(&self, name: &str) -> Vec<String> {
+                    match header_index(name.as_bytes()) {
+                        $(Some($num_id) => self.$lower_ident.iter().map(|v| v.http_value()).collect(),)*
+                        _ => self.extensions.get_all(name).into_iter()
+                                 .map(|v| String::from_str(v)).collect(),
                     }
                 }
 
@@ -968,7 +1266,7 @@ macro_rules! headers_mod {
             pub struct HeaderCollectionIterator<'a> {
                 pos: usize,
                 coll: &'a HeaderCollection,
-                ext_iter: Option<Iter<'a, String, String>>
+                ext_iter: Option<Iter<'a, (HeaderName, String)>>
             }
 
             impl<'a> Iterator for HeaderCollectionIterator<'a> {
@@ -987,8 +1285,8 @@ macro_rules! headers_mod {
                                 continue
                             },
                             _ => match self.ext_iter.as_mut().unwrap().next() {
-                                Some((k, v)) =>
-                                    return Some(ExtensionHeader(k.clone(), v.clone())),
+                                Some(&(ref k, ref v)) =>
+                                    return Some(ExtensionHeader(String::from_str(&k[..]), v.clone())),
                                 None => return None,
                             },
                         }
@@ -1035,15 +1333,65 @@ macro_rules! headers_mod {
 
                 fn value_from_stream<R: Reader>(name: String, value: &mut HeaderValueByteIterator<R>)
                         -> Option<Header> {
-                    match &name.clone().into_ascii_lowercase()[..] {
-                        $($input_name => match HeaderConvertible::from_stream(value) {
+                    match header_index(name.as_bytes()) {
+                        Some(index) => Header::from_index(index, value),
+                        None => Some(ExtensionHeader(name, value.collect_to_string())),
+                    }
+                }
+            }
+
+            impl Header {
+                /// Read the value of the strongly-typed header with the given index in the table.
+                fn from_index<R: Reader>(index: usize, value: &mut HeaderValueByteIterator<R>)
+                        -> Option<Header> {
+                    match index {
+                        $($num_id => match HeaderConvertible::from_stream(value) {
                             Some(v) => Some($caps_ident(v)),
                             None => None,
                         },)*
-                        _ => Some(ExtensionHeader(name, value.collect_to_string())),
+                        _ => unreachable!(),
                     }
                 }
             }
+
+            /// The type of the value of the strongly-typed header with the given index.
+            pub fn type_id(index: usize) -> TypeId {
+                match index {
+                    $($num_id => TypeId::of::<$htype>(),)*
+                    _ => unreachable!(),
+                }
+            }
+
+            /// The usual spelling of the name of the strongly-typed header with the given index.
+            fn standard_name(index: usize) -> &'static str {
+                match index {
+                    $($num_id => $output_name,)*
+                    _ => unreachable!(),
+                }
+            }
+
+            #[cfg(test)]
+            mod test {
+                use std::ascii::AsciiExt;
+                use headers::header_names::$index_function;
+
+                /// The generated name table must be in step with this one.
+                #[test]
+                fn test_header_index() {
+                    $(
+                        assert!(match $index_function($output_name.as_bytes()) {
+                            Some($num_id) => true,
+                            _ => false,
+                        }, "wrong index for {}", $output_name);
+                        assert_eq!($index_function($output_name.to_ascii_uppercase().as_bytes()),
+                                   $index_function($output_name.to_ascii_lowercase().as_bytes()));
+                    )*
+                    assert_eq!($index_function(b"X-Foo"), None);
+                    assert_eq!($index_function(b"Content-Lengt"), None);
+                    assert_eq!($index_function(b"Content-Lengths"), None);
+                    assert_eq!($index_function(b""), None);
+                }
+            }
         }
     }
 }
@@ -1053,6 +1401,7 @@ headers_mod! {
     pub mod request;
 
     num_headers: 38;
+    index_function: request_header_index;
 
     // RFC 2616, Section 4.5: General Header Fields
      0, "Cache-Control",     "cache-control",     CacheControl,     cache_control,     String,
@@ -1061,7 +1410,7 @@ headers_mod! {
      3, "Pragma",            "pragma",            Pragma,           pragma,            String,
      4, "Trailer",           "trailer",           Trailer,          trailer,           String,
      5, "Transfer-Encoding", "transfer-encoding", TransferEncoding, transfer_encoding, Vec<headers::transfer_encoding::TransferCoding>,
-     6, "Upgrade",           "upgrade",           Upgrade,          upgrade,           String,
+     6, "Upgrade",           "upgrade",           Upgrade,          upgrade,           Vec<headers::upgrade::Protocol>,
      7, "Via",               "via",               Via,              via,               String,
      8, "Warning",           "warning",           Warning,          warning,           String,
 
@@ -1070,17 +1419,17 @@ headers_mod! {
     10, "Accept-Charset",      "accept-charset",      AcceptCharset,      accept_charset,      String,
     11, "Accept-Encoding",     "accept-encoding",     AcceptEncoding,     accept_encoding,     String,
     12, "Accept-Language",     "accept-language",     AcceptLanguage,     accept_language,     String,
-    13, "Authorization",       "authorization",       Authorization,      authorization,       String,
+    13, "Authorization",       "authorization",       Authorization,      authorization,       headers::authorization::Credentials,
     14, "Expect",              "expect",              Expect,             expect,              String,
     15, "From",                "from",                From,               from,                String,
     16, "Host",                "host",                Host,               host,                headers::host::Host,
-    17, "If-Match",            "if-match",            IfMatch,            if_match,            String,
+    17, "If-Match",            "if-match",            IfMatch,            if_match,            headers::etag::EntityTagMatch,
     18, "If-Modified-Since",   "if-modified-since",   IfModifiedSince,    if_modified_since,   time::Tm,
-    19, "If-None-Match",       "if-none-match",       IfNoneMatch,        if_none_match,       String,
+    19, "If-None-Match",       "if-none-match",       IfNoneMatch,        if_none_match,       headers::etag::EntityTagMatch,
     20, "If-Range",            "if-range",            IfRange,            if_range,            String,
     21, "If-Unmodified-Since", "if-unmodified-since", IfUnmodifiedSince,  if_unmodified_since, time::Tm,
     22, "Max-Forwards",        "max-forwards",        MaxForwards,        max_forwards,        usize,
-    23, "Proxy-Authorization", "proxy-authorization", ProxyAuthorization, proxy_authorization, String,
+    23, "Proxy-Authorization", "proxy-authorization", ProxyAuthorization, proxy_authorization, headers::authorization::Credentials,
     24, "Range",               "range",               Range,              range,               String,
     25, "Referer",             "referer",             Referer,            referer,             String,
     26, "TE",                  "te",                  Te,                 te,                  String,
@@ -1104,6 +1453,7 @@ headers_mod! {
     pub mod response;
 
     num_headers: 30;
+    index_function: response_header_index;
 
     // RFC 2616, Section 4.5: General Header Fields
      0, "Cache-Control",     "cache-control",     CacheControl,     cache_control,     String,
@@ -1112,7 +1462,7 @@ headers_mod! {
      3, "Pragma",            "pragma",            Pragma,           pragma,            String,
      4, "Trailer",           "trailer",           Trailer,          trailer,           String,
      5, "Transfer-Encoding", "transfer-encoding", TransferEncoding, transfer_encoding, Vec<headers::transfer_encoding::TransferCoding>,
-     6, "Upgrade",           "upgrade",           Upgrade,          upgrade,           String,
+     6, "Upgrade",           "upgrade",           Upgrade,          upgrade,           Vec<headers::upgrade::Protocol>,
      7, "Via",               "via",               Via,              via,               String,
      8, "Warning",           "warning",           Warning,          warning,           String,
 
@@ -1122,11 +1472,11 @@ headers_mod! {
     11, "Age",                "age",                Age,               age,                String,
     12, "ETag",               "etag",               ETag,              etag,               headers::etag::EntityTag,
     13, "Location",           "location",           Location,          location,           ::url::Url,
-    14, "Proxy-Authenticate", "proxy-authenticate", ProxyAuthenticate, proxy_authenticate, String,
+    14, "Proxy-Authenticate", "proxy-authenticate", ProxyAuthenticate, proxy_authenticate, Vec<headers::authorization::Challenge>,
     15, "Retry-After",        "retry-after",        RetryAfter,        retry_after,        String,
     16, "Server",             "server",             Server,            server,             String,
     17, "Vary",               "vary",               Vary,              vary,               String,
-    18, "WWW-Authenticate",   "www-authenticate",   WwwAuthenticate,   www_authenticate,   String,
+    18, "WWW-Authenticate",   "www-authenticate",   WwwAuthenticate,   www_authenticate,   Vec<headers::authorization::Challenge>,
 
     // RFC 2616, Section 7.1: Entity Header Fields
     19, "Allow",            "allow",            Allow,           allow,            Vec<::method::Method>,
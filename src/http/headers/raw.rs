@@ -0,0 +1,244 @@
+//! Header storage that keeps everything: repeated fields, their order and their original casing.
+//!
+//! Header names are compared case-insensitively, as RFC 7230 §3.2 requires, but are stored exactly
+//! as they were given so they can be passed on unchanged (which proxies and message signature
+//! schemes need).
+
+use std::ascii::AsciiExt;
+use std::ops::Deref;
+use std::slice::Iter;
+
+use self::HeaderName::{Standard, Other};
+
+/// The name of a header field, exactly as it was given.
+///
+/// A received field whose name is spelt just as the standard name of a strongly-typed header
+/// refers to that name rather than copying it.
+#[derive(Clone, Debug)]
+pub enum HeaderName {
+    Standard(&'static str),
+    Other(String),
+}
+
+impl Deref for HeaderName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match *self {
+            Standard(name) => name,
+            Other(ref name) => &name[..],
+        }
+    }
+}
+
+impl PartialEq for HeaderName {
+    fn eq(&self, other: &HeaderName) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for HeaderName {}
+
+/// An ordered multimap of header names to values.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RawHeaders {
+    entries: Vec<(HeaderName, String)>,
+}
+
+impl RawHeaders {
+    pub fn new() -> RawHeaders {
+        RawHeaders {
+            entries: Vec::new(),
+        }
+    }
+
+    /// The number of header fields, counting each repetition of a name separately.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add a header field after all existing ones, keeping any others of the same name.
+    pub fn append(&mut self, name: String, value: String) {
+        self.entries.push((Other(name), value));
+    }
+
+    /// As `append`, for a name which need not be copied.
+    pub fn append_standard(&mut self, name: &'static str, value: String) {
+        self.entries.push((Standard(name), value));
+    }
+
+    /// Set a header field, replacing every existing field of the same name.
+    ///
+    /// The new field takes the place of the first one it replaces, or goes at the end if there was
+    /// none.
+    pub fn insert(&mut self, name: String, value: String) {
+        match self.entries.iter().position(|&(ref n, _)| n.eq_ignore_ascii_case(&name[..])) {
+            Some(i) => {
+                self.entries[i] = (Other(name), value);
+                let name = self.entries[i].0.clone();
+                let mut j = i + 1;
+                while j < self.entries.len() {
+                    if self.entries[j].0.eq_ignore_ascii_case(&*name) {
+                        self.entries.remove(j);
+                    } else {
+                        j += 1;
+                    }
+                }
+            },
+            None => self.entries.push((Other(name), value)),
+        }
+    }
+
+    /// Remove every field with the given name, returning whether there were any.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|&(ref n, _)| !n.eq_ignore_ascii_case(name));
+        self.entries.len() != len
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.iter().any(|&(ref n, _)| n.eq_ignore_ascii_case(name))
+    }
+
+    /// The value of the first field with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+    }
+
+    /// The values of all fields with the given name, in order.
+    pub fn get_all<'a>(&'a self, name: &str) -> Vec<&'a str> {
+        self.entries.iter()
+            .filter(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+            .collect()
+    }
+
+    /// Iterate over the (name, value) pairs in order.
+    pub fn iter<'a>(&'a self) -> Iter<'a, (HeaderName, String)> {
+        self.entries.iter()
+    }
+}
+
+/// Header fields as they were received: names as given and values as the bytes which came in.
+///
+/// Fields can only be added, as this is a record of what arrived rather than of the headers as
+/// they stand; a received value need not even be text.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ReceivedHeaders {
+    entries: Vec<(HeaderName, Vec<u8>)>,
+}
+
+impl ReceivedHeaders {
+    pub fn new() -> ReceivedHeaders {
+        ReceivedHeaders {
+            entries: Vec::new(),
+        }
+    }
+
+    /// The number of header fields, counting each repetition of a name separately.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add a header field after all existing ones.
+    pub fn append(&mut self, name: String, value: Vec<u8>) {
+        self.entries.push((Other(name), value));
+    }
+
+    /// As `append`, for a name which need not be copied.
+    pub fn append_standard(&mut self, name: &'static str, value: Vec<u8>) {
+        self.entries.push((Standard(name), value));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.iter().any(|&(ref n, _)| n.eq_ignore_ascii_case(name))
+    }
+
+    /// The value of the first field with the given name.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.entries.iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+    }
+
+    /// The values of all fields with the given name, in order.
+    pub fn get_all<'a>(&'a self, name: &str) -> Vec<&'a [u8]> {
+        self.entries.iter()
+            .filter(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+            .collect()
+    }
+
+    /// Iterate over the (name, value) pairs in order.
+    pub fn iter<'a>(&'a self) -> Iter<'a, (HeaderName, Vec<u8>)> {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RawHeaders, ReceivedHeaders};
+    use super::HeaderName::{Standard, Other};
+
+    fn sample() -> RawHeaders {
+        let mut h = RawHeaders::new();
+        h.append(String::from_str("Set-Cookie"), String::from_str("a=1"));
+        h.append(String::from_str("X-Thing"), String::from_str("x"));
+        h.append(String::from_str("set-cookie"), String::from_str("b=2"));
+        h
+    }
+
+    #[test]
+    fn test_append_keeps_order_and_case() {
+        let h = sample();
+        assert_eq!(h.len(), 3);
+        assert_eq!(h.get("SET-COOKIE"), Some("a=1"));
+        assert_eq!(h.get_all("Set-Cookie"), vec!("a=1", "b=2"));
+        let names: Vec<&str> = h.iter().map(|&(ref n, _)| &n[..]).collect();
+        assert_eq!(names, vec!("Set-Cookie", "X-Thing", "set-cookie"));
+    }
+
+    #[test]
+    fn test_header_name() {
+        let mut h = RawHeaders::new();
+        h.append_standard("Content-Length", String::from_str("4"));
+        assert_eq!(h.get("content-length"), Some("4"));
+        assert_eq!(Standard("Via"), Other(String::from_str("Via")));
+        assert!(Standard("Via") != Other(String::from_str("via")));
+    }
+
+    #[test]
+    fn test_insert_replaces_all() {
+        let mut h = sample();
+        h.insert(String::from_str("Set-Cookie"), String::from_str("c=3"));
+        assert_eq!(h.get_all("set-cookie"), vec!("c=3"));
+        assert_eq!(h.iter().next(), Some(&(Other(String::from_str("Set-Cookie")),
+                                           String::from_str("c=3"))));
+        assert!(h.remove("x-thing"));
+        assert!(!h.remove("x-thing"));
+        assert_eq!(h.len(), 1);
+    }
+
+    #[test]
+    fn test_received_headers() {
+        let mut h = ReceivedHeaders::new();
+        h.append_standard("Via", b"1.0 fred".to_vec());
+        h.append(String::from_str("X-Name"), b"caf\xe9".to_vec());
+        h.append(String::from_str("via"), b"1.1 nowhere.com".to_vec());
+        assert_eq!(h.len(), 3);
+        assert!(h.contains("x-name"));
+        assert_eq!(h.get("X-NAME"), Some(&b"caf\xe9"[..]));
+        assert_eq!(h.get_all("Via"), vec!(&b"1.0 fred"[..], &b"1.1 nowhere.com"[..]));
+        assert_eq!(h.get("Host"), None);
+    }
+}
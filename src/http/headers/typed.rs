@@ -0,0 +1,227 @@
+//! Typed access to headers by type rather than by field, including headers defined outside this
+//! crate.
+//!
+//! Every strongly-typed header in the `request` and `response` collections has a marker type in
+//! `headers::request::typed` or `headers::response::typed`; other headers can be described by
+//! implementing `TypedHeader` for a type of your own:
+//!
+//! ```ignore
+//! struct RequestId;
+//!
+//! impl TypedHeader for RequestId {
+//!     type Value = usize;
+//!     fn header_name() -> &'static str { "X-Request-Id" }
+//! }
+//!
+//! let id = request.headers.get::<RequestId>();
+//! response.headers.set::<RequestId>(id.unwrap_or(0));
+//! ```
+//!
+//! Such headers are stored in `extensions` as text and converted on access. To have them parsed
+//! as they are received, so that an invalid value is treated like an invalid value of a built-in
+//! header and `get` need not parse it again, add them to the `HeaderRegistry` of the server's
+//! `Config` (or of the `RequestWriter` for responses).
+//!
+//! A `TypedHeader` with the name of a built-in header must have the same `Value` type; using one
+//! which does not is a programming error, and panics.
+
+use std::any::{Any, TypeId};
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::old_io::BufReader;
+
+use headers::{HeaderConvertible, HeaderValueByteIterator};
+use headers::header_names::{request_header_index, response_header_index};
+
+/// A header name together with the type of its value.
+pub trait TypedHeader: 'static {
+    /// The type of the header's value.
+    type Value: HeaderConvertible + 'static;
+
+    /// The name of the header, in its usual case (e.g. "Content-Length").
+    fn header_name() -> &'static str;
+}
+
+/// Interpret a complete header value as the given type.
+pub fn value_from_bytes<T: HeaderConvertible>(value: &[u8]) -> Option<T> {
+    let mut reader = BufReader::new(value);
+    let mut iter = HeaderValueByteIterator::new(&mut reader);
+    match HeaderConvertible::from_stream(&mut iter) {
+        Some(v) => iter.some_if_consumed(v),
+        None => None,
+    }
+}
+
+/// The value of a registered extension header as it was parsed on receipt, of whatever type its
+/// `TypedHeader` has.
+pub trait ParsedValue: Send + Sync {
+    fn as_any(&self) -> &Any;
+    fn as_any_mut(&mut self) -> &mut Any;
+    fn clone_boxed(&self) -> Box<ParsedValue>;
+}
+
+impl<T: HeaderConvertible + Send + Sync + 'static> ParsedValue for T {
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+
+    fn clone_boxed(&self) -> Box<ParsedValue> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<ParsedValue> {
+    fn clone(&self) -> Box<ParsedValue> {
+        self.clone_boxed()
+    }
+}
+
+/// Panic because a `TypedHeader` has the name of a built-in header but another `Value` type.
+pub fn type_mismatch(name: &str) -> ! {
+    panic!("the TypedHeader for {:?} does not have the Value type of the built-in header", name)
+}
+
+/// Whether `H` has the name of a built-in request or response header whose type is not its own.
+fn conflicts_with_builtin<H: TypedHeader>() -> bool {
+    let name = H::header_name().as_bytes();
+    let types = [request_header_index(name).map(::headers::request::type_id),
+                 response_header_index(name).map(::headers::response::type_id)];
+    types.iter().any(|t| t.map_or(false, |t| t != TypeId::of::<H::Value>()))
+}
+
+/// Parse a received value of the header `H`, combining it with the value already parsed from
+/// earlier fields of the same name; false if it is not valid or cannot be combined.
+fn parse_into<H: TypedHeader>(value: &[u8], parsed: &mut Option<Box<ParsedValue>>) -> bool
+        where H::Value: Send + Sync {
+    let value = match value_from_bytes::<H::Value>(value) {
+        Some(value) => value,
+        None => return false,
+    };
+    match *parsed {
+        Some(ref mut existing) => match existing.as_any_mut().downcast_mut::<H::Value>() {
+            Some(existing) => return existing.combine(value).is_ok(),
+            None => return false,
+        },
+        None => (),
+    }
+    *parsed = Some(Box::new(value) as Box<ParsedValue>);
+    true
+}
+
+/// The user-defined headers which are to be parsed on receipt.
+pub struct HeaderRegistry {
+    // Keyed by the lowercase header name.
+    parsers: HashMap<String, fn(&[u8], &mut Option<Box<ParsedValue>>) -> bool>,
+}
+
+impl HeaderRegistry {
+    /// An empty registry; received extension headers are all accepted as they are.
+    pub fn new() -> HeaderRegistry {
+        HeaderRegistry {
+            parsers: HashMap::new(),
+        }
+    }
+
+    /// Parse received values of the header `H` as its type.
+    ///
+    /// Panics if `H` has the name of a built-in header but another `Value` type.
+    pub fn register<H: TypedHeader>(&mut self) where H::Value: Send + Sync {
+        if conflicts_with_builtin::<H>() {
+            type_mismatch(H::header_name());
+        }
+        self.parsers.insert(H::header_name().to_ascii_lowercase(),
+                            parse_into::<H> as fn(&[u8], &mut Option<Box<ParsedValue>>) -> bool);
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.parsers.contains_key(&name.to_ascii_lowercase())
+    }
+
+    /// Parse a received value of the named header into `parsed`, combining it with any value
+    /// already there. This is `None` if the header is not registered, or else whether the value
+    /// was valid.
+    pub fn parse(&self, name: &str, value: &[u8], parsed: &mut Option<Box<ParsedValue>>)
+            -> Option<bool> {
+        self.parsers.get(&name.to_ascii_lowercase()).map(|parser| (*parser)(value, parsed))
+    }
+
+    /// Whether the value is acceptable for the named header: true unless the header is registered
+    /// and the value does not convert to its type.
+    pub fn validate(&self, name: &str, value: &[u8]) -> bool {
+        self.parse(name, value, &mut None).unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TypedHeader, HeaderRegistry};
+    use headers::request::HeaderCollection;
+    use headers::request::typed::{ContentLength, Via};
+
+    struct RequestId;
+
+    impl TypedHeader for RequestId {
+        type Value = usize;
+        fn header_name() -> &'static str { "X-Request-Id" }
+    }
+
+    /// A mistake: Content-Length is a `usize`.
+    struct TextLength;
+
+    impl TypedHeader for TextLength {
+        type Value = String;
+        fn header_name() -> &'static str { "Content-Length" }
+    }
+
+    #[test]
+    fn test_builtin_headers() {
+        let mut headers = HeaderCollection::new();
+        assert_eq!(headers.get::<ContentLength>(), None);
+        headers.set::<ContentLength>(42);
+        assert_eq!(headers.content_length, Some(42));
+        assert_eq!(headers.get::<ContentLength>(), Some(42));
+        headers.via = Some(String::from_str("1.1 example.com"));
+        assert_eq!(headers.get::<Via>(), Some(String::from_str("1.1 example.com")));
+    }
+
+    #[test]
+    fn test_user_defined_headers() {
+        let mut registry = HeaderRegistry::new();
+        registry.register::<RequestId>();
+        assert!(registry.is_registered("x-request-id"));
+
+        let mut headers = HeaderCollection::new();
+        assert_eq!(headers.append_raw_with("x-request-id", b"12", &registry), Ok(()));
+        assert_eq!(headers.get::<RequestId>(), Some(12));
+        assert_eq!(headers.append_raw_with("X-Request-Id", b"twelve", &registry), Err(()));
+        assert_eq!(headers.get::<RequestId>(), Some(12));
+        assert_eq!(headers.raw.len(), 2);
+
+        headers.set::<RequestId>(7);
+        assert_eq!(headers.extensions.get_all("X-Request-Id"), vec!("7"));
+        assert_eq!(headers.get::<RequestId>(), Some(7));
+        // What was received is left as it was.
+        assert_eq!(headers.raw.get_all("x-request-id"), vec!(&b"12"[..], &b"twelve"[..]));
+
+        // Without the registry, the value is only parsed when it is asked for.
+        let mut headers = HeaderCollection::new();
+        assert_eq!(headers.append_raw("X-Request-Id", b"twelve"), Ok(()));
+        assert_eq!(headers.get::<RequestId>(), None);
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_register_type_mismatch() {
+        HeaderRegistry::new().register::<TextLength>();
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_set_type_mismatch() {
+        HeaderCollection::new().set::<TextLength>(String::from_str("42"));
+    }
+}
@@ -0,0 +1,93 @@
+//! The Upgrade general header, defined in RFC 7230, Section 6.7.
+//!
+//! Upgrade          = 1#protocol
+//! protocol         = protocol-name ["/" protocol-version]
+
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::old_io::IoResult;
+use headers::serialization_utils::WriterUtil;
+
+/// A protocol to switch to, such as `websocket` or `HTTP/2.0`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Protocol {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl Protocol {
+    pub fn new(name: String, version: Option<String>) -> Protocol {
+        Protocol {
+            name: name,
+            version: version,
+        }
+    }
+
+    /// Whether this is the named protocol (of any version); protocol names are case-insensitive.
+    pub fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.version {
+            Some(ref version) => write!(f, "{}/{}", self.name, version),
+            None => f.write_str(&self.name[..]),
+        }
+    }
+}
+
+impl super::CommaListHeaderConvertible for Protocol {}
+
+impl super::HeaderConvertible for Protocol {
+    fn from_stream<R: Reader>(reader: &mut super::HeaderValueByteIterator<R>)
+            -> Option<Protocol> {
+        let name = match reader.read_token() {
+            Some(name) => name,
+            None => return None,
+        };
+        let version = match reader.next() {
+            Some(b'/') => match reader.read_token() {
+                Some(version) => Some(version),
+                None => return None,
+            },
+            Some(b) => {
+                // Not ours; leave it for whatever comes after the protocol.
+                reader.next_byte = Some(b);
+                None
+            },
+            None => None,
+        };
+        Some(Protocol::new(name, version))
+    }
+
+    fn to_stream<W: Writer>(&self, writer: &mut W) -> IoResult<()> {
+        try!(writer.write_token(&self.name));
+        match self.version {
+            Some(ref version) => {
+                try!(writer.write_all(b"/"));
+                writer.write_token(version)
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn http_value(&self) -> String {
+        format!("{}", self)
+    }
+}
+
+#[test]
+fn test_upgrade() {
+    use headers::test_utils::{assert_conversion_correct, assert_interpretation_correct,
+                              assert_invalid};
+    let websocket = Protocol::new(String::from_str("websocket"), None);
+    let http2 = Protocol::new(String::from_str("HTTP"), Some(String::from_str("2.0")));
+    assert_conversion_correct("websocket", vec!(websocket.clone()));
+    assert_conversion_correct("HTTP/2.0, websocket", vec!(http2.clone(), websocket.clone()));
+    assert_interpretation_correct("HTTP/2.0 ,websocket", vec!(http2, websocket));
+    assert_invalid::<Vec<Protocol>>("HTTP/");
+    assert_invalid::<Vec<Protocol>>("websocket/1 2");
+    assert!(Protocol::new(String::from_str("WebSocket"), None).is("websocket"));
+}
@@ -1,4 +1,4 @@
-#![feature(collections,core,old_io,io,rustc_private,std_misc,unicode,concat_idents)]
+#![feature(collections,core,old_io,io,rustc_private,std_misc,unicode,concat_idents,libc,os)]
 #![crate_name = "http"]
 
 #![doc(html_root_url = "http://www.rust-ci.org/chris-morgan/rust-http/doc/")]
@@ -11,15 +11,26 @@
 extern crate url;
 extern crate time;
 extern crate collections;
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate crypto;
+extern crate rand;
+extern crate libc;
+extern crate flate2;
 
 pub mod buffer;
 pub mod client;
+pub mod coding;
 pub mod common;
 pub mod connecter;
 pub mod server;
 pub mod method;
 pub mod headers;
+pub mod digest;
+pub mod form;
+pub mod multipart;
+pub mod parser;
 pub mod rfc2616;
+pub mod websocket;
 include!(concat!(env!("OUT_DIR"), "/status.rs"));  // defines pub mod status
 
 /// TODO: submit upstream
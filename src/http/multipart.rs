@@ -0,0 +1,735 @@
+//! Reading `multipart/form-data` bodies (RFC 7578), as browsers send for file uploads.
+//!
+//! A `MultipartReader` reads the parts one after another from any `Reader`, never holding more
+//! than a little of the body in memory: `next_part` gives the headers of the next part, after
+//! which the `MultipartReader` is itself a `Reader` of that part's body, ending where the part
+//! does. `read_part_body` and `read_parts` collect bodies instead, keeping large ones in temporary
+//! files if `MultipartLimits.spill_threshold` is set.
+//!
+//! On the server, a request's body has already been read into memory by the time it is handled
+//! (see `Config::max_body_len`), so `Request::multipart` gains nothing from either; they matter
+//! for a body read from the connection, such as that of a `ResponseReader`.
+//!
+//! For sending, a `MultipartBuilder` puts a body together from fields and files (see
+//! `RequestWriter.write_multipart`).
+//!
+//! ```ignore
+//! let mut parts = try!(request.multipart(MultipartLimits::new()));
+//! while let Some(part) = try!(parts.next_part()) {
+//!     if part.filename().is_some() {
+//!         let mut file = try!(File::create(&dest));
+//!         try!(copy(&mut parts, &mut file));
+//!     }
+//! }
+//! ```
+
+use std::ascii::AsciiExt;
+use std::cmp::min;
+use std::error::FromError;
+use std::old_io::{IoError, IoResult, OtherIoError, EndOfFile, TempDir, File, standard_error};
+use std::slice;
+use rand;
+use rustc_serialize::hex::ToHex;
+
+use headers::content_disposition::ContentDisposition;
+use headers::content_type::MediaType;
+use headers::raw::RawHeaders;
+use headers::typed::value_from_bytes;
+use parser;
+use parser::HeaderLine::{Field, EndOfHead};
+
+/// The default limits on a multipart body; see `MultipartLimits`.
+pub const DEFAULT_MAX_PART_LEN: u64 = 0x1000000;
+pub const DEFAULT_MAX_TOTAL_LEN: u64 = 0x4000000;
+
+/// The most bytes of headers a part may have.
+const MAX_PART_HEAD_LEN: usize = 0x2000;
+
+const READ_SIZE: usize = 0x2000;
+
+/// Limits on what a `MultipartReader` will read.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MultipartLimits {
+    /// The longest body of a single part.
+    pub max_part_len: u64,
+    /// The longest the whole multipart body may be.
+    pub max_total_len: u64,
+    /// Collected part bodies longer than this are kept in a temporary file rather than in memory;
+    /// `None` keeps them all in memory.
+    pub spill_threshold: Option<usize>,
+}
+
+impl MultipartLimits {
+    pub fn new() -> MultipartLimits {
+        MultipartLimits {
+            max_part_len: DEFAULT_MAX_PART_LEN,
+            max_total_len: DEFAULT_MAX_TOTAL_LEN,
+            spill_threshold: None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MultipartError {
+    /// The content is not multipart, or has no boundary.
+    NotMultipart,
+    /// The body is not valid multipart.
+    Malformed,
+    /// A part is longer than `MultipartLimits.max_part_len`.
+    PartTooLarge,
+    /// The body is longer than `MultipartLimits.max_total_len`.
+    TooLarge,
+    /// Reading the body (or writing a temporary file) failed.
+    Io(IoError),
+}
+
+impl FromError<IoError> for MultipartError {
+    fn from_error(err: IoError) -> MultipartError {
+        MultipartError::Io(err)
+    }
+}
+
+/// The boundary of a multipart body, from its Content-Type.
+pub fn boundary(content_type: &MediaType) -> Option<&str> {
+    if !content_type.type_.eq_ignore_ascii_case("multipart") {
+        return None;
+    }
+    // RFC 2046 §5.1.1: one to seventy characters.
+    content_type.parameters.iter()
+        .find(|&&(ref k, _)| k.eq_ignore_ascii_case("boundary"))
+        .map(|&(_, ref v)| &v[..])
+        .and_then(|v| if v.len() >= 1 && v.len() <= 70 { Some(v) } else { None })
+}
+
+/// The headers of a part.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PartHeaders {
+    /// All of them, as they were sent.
+    pub headers: RawHeaders,
+    pub disposition: Option<ContentDisposition>,
+    pub content_type: Option<MediaType>,
+}
+
+impl PartHeaders {
+    /// The name of the form field.
+    pub fn name(&self) -> Option<&str> {
+        self.disposition.as_ref().and_then(|d| d.name())
+    }
+
+    /// The name of the uploaded file, if the part is a file.
+    pub fn filename(&self) -> Option<&str> {
+        self.disposition.as_ref().and_then(|d| d.filename())
+    }
+}
+
+/// A part's body stored in a temporary file, which is removed when this is dropped.
+pub struct TempFile {
+    // Kept for removing the directory (and so the file) on drop.
+    _dir: TempDir,
+    path: Path,
+    len: u64,
+}
+
+impl TempFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Open the file for reading.
+    pub fn open(&self) -> IoResult<File> {
+        File::open(&self.path)
+    }
+}
+
+/// A collected part body.
+pub enum PartBody {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+/// A part with its body collected.
+pub struct Part {
+    pub headers: PartHeaders,
+    pub body: PartBody,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum State {
+    /// Before the first boundary.
+    Preamble,
+    /// In the body of a part.
+    Body,
+    /// Just after a boundary delimiter.
+    Delimiter,
+    /// After the close delimiter.
+    End,
+}
+
+/// A streaming reader of a multipart body; see the module documentation.
+pub struct MultipartReader<R> {
+    reader: R,
+    /// CR LF "--" boundary, which ends each part (and the preamble).
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    pos: usize,
+    eof: bool,
+    state: State,
+    limits: MultipartLimits,
+    total_len: u64,
+    part_len: u64,
+}
+
+impl<R: Reader> MultipartReader<R> {
+    pub fn new(reader: R, boundary: &str, limits: MultipartLimits) -> MultipartReader<R> {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.push_all(boundary.as_bytes());
+        MultipartReader {
+            reader: reader,
+            delimiter: delimiter,
+            // The first delimiter is not preceded by CR LF if there is no preamble; pretend that it
+            // is so that it can be found in the same way as the others.
+            buf: b"\r\n".to_vec(),
+            pos: 0,
+            eof: false,
+            state: State::Preamble,
+            limits: limits,
+            total_len: 0,
+            part_len: 0,
+        }
+    }
+
+    /// A reader for a body with the given Content-Type, which must be multipart.
+    pub fn for_content_type(reader: R, content_type: &MediaType, limits: MultipartLimits)
+            -> Result<MultipartReader<R>, MultipartError> {
+        match boundary(content_type) {
+            Some(boundary) => Ok(MultipartReader::new(reader, boundary, limits)),
+            None => Err(MultipartError::NotMultipart),
+        }
+    }
+
+    /// Read more onto the end of the buffer, returning false at the end of the input.
+    fn fill(&mut self) -> Result<bool, MultipartError> {
+        if self.eof {
+            return Ok(false);
+        }
+        if self.pos > 0 && self.pos >= self.buf.len() / 2 {
+            self.buf = self.buf[self.pos..].to_vec();
+            self.pos = 0;
+        }
+        let mut chunk = [0u8; READ_SIZE];
+        match self.reader.read(&mut chunk) {
+            Ok(amount) => {
+                self.total_len += amount as u64;
+                if self.total_len > self.limits.max_total_len {
+                    return Err(MultipartError::TooLarge);
+                }
+                self.buf.push_all(&chunk[..amount]);
+                Ok(true)
+            },
+            Err(ref err) if err.kind == EndOfFile => {
+                self.eof = true;
+                Ok(false)
+            },
+            Err(err) => Err(MultipartError::Io(err)),
+        }
+    }
+
+    /// Make sure at least `amount` bytes are buffered; it is malformed if there are not that many.
+    fn ensure(&mut self, amount: usize) -> Result<(), MultipartError> {
+        while self.buf.len() - self.pos < amount {
+            if !try!(self.fill()) {
+                return Err(MultipartError::Malformed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Move on to the next part, returning its headers, or `None` after the last part.
+    ///
+    /// Whatever was not read of the current part's body is skipped.
+    pub fn next_part(&mut self) -> Result<Option<PartHeaders>, MultipartError> {
+        match self.state {
+            State::End => return Ok(None),
+            State::Preamble | State::Body => {
+                self.state = State::Body;
+                let mut scratch = [0u8; READ_SIZE];
+                while try!(self.read_body(&mut scratch)) > 0 { }
+            },
+            State::Delimiter => (),
+        }
+
+        // The close delimiter has "--" after it; any other is followed by optional white space
+        // and CR LF.
+        try!(self.ensure(2));
+        if &self.buf[self.pos..self.pos + 2] == b"--" {
+            self.state = State::End;
+            return Ok(None);
+        }
+        loop {
+            try!(self.ensure(1));
+            match self.buf[self.pos] {
+                b' ' | b'\t' => self.pos += 1,
+                _ => break,
+            }
+        }
+        try!(self.ensure(2));
+        if &self.buf[self.pos..self.pos + 2] != b"\r\n" {
+            return Err(MultipartError::Malformed);
+        }
+        self.pos += 2;
+
+        let mut headers = RawHeaders::new();
+        let mut head_len = 0;
+        loop {
+            let step = match parser::parse_header_line(&self.buf[self.pos..]) {
+                Ok(Some((amount, Field(field)))) => {
+                    let value = if parser::is_folded(field.value) {
+                        String::from_utf8_lossy(&parser::unfold(field.value)[..]).into_owned()
+                    } else {
+                        String::from_utf8_lossy(field.value).into_owned()
+                    };
+                    Some((amount, Some((String::from_str(field.name), value))))
+                },
+                Ok(Some((amount, EndOfHead))) => Some((amount, None)),
+                Ok(None) => None,
+                Err(_) => return Err(MultipartError::Malformed),
+            };
+            match step {
+                Some((amount, field)) => {
+                    self.pos += amount;
+                    head_len += amount;
+                    match field {
+                        Some((name, value)) => headers.append(name, value),
+                        None => break,
+                    }
+                },
+                None => {
+                    if head_len + self.buf.len() - self.pos > MAX_PART_HEAD_LEN ||
+                            !try!(self.fill()) {
+                        return Err(MultipartError::Malformed);
+                    }
+                },
+            }
+        }
+
+        self.state = State::Body;
+        self.part_len = 0;
+        let disposition = headers.get("Content-Disposition")
+                                 .and_then(|v| value_from_bytes(v.as_bytes()));
+        let content_type = headers.get("Content-Type").and_then(|v| value_from_bytes(v.as_bytes()));
+        Ok(Some(PartHeaders {
+            headers: headers,
+            disposition: disposition,
+            content_type: content_type,
+        }))
+    }
+
+    /// Read some of the current part's body, returning zero at the end of it.
+    fn read_body(&mut self, out: &mut [u8]) -> Result<usize, MultipartError> {
+        if self.state != State::Body || out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let found = {
+                let rest = &self.buf[self.pos..];
+                rest.windows(self.delimiter.len()).position(|w| w == &self.delimiter[..])
+            };
+            let available = match found {
+                Some(0) => {
+                    self.pos += self.delimiter.len();
+                    self.state = State::Delimiter;
+                    return Ok(0);
+                },
+                Some(i) => i,
+                // Keep back what could be the start of the delimiter.
+                None => (self.buf.len() - self.pos).saturating_sub(self.delimiter.len() - 1),
+            };
+            if available > 0 {
+                let amount = min(available, out.len());
+                slice::bytes::copy_memory(out, &self.buf[self.pos..self.pos + amount]);
+                self.pos += amount;
+                self.part_len += amount as u64;
+                if self.part_len > self.limits.max_part_len {
+                    return Err(MultipartError::PartTooLarge);
+                }
+                return Ok(amount);
+            }
+            if !try!(self.fill()) {
+                return Err(MultipartError::Malformed);
+            }
+        }
+    }
+
+    /// Read the rest of the current part's body, into a temporary file if it is longer than
+    /// `spill_threshold`.
+    pub fn read_part_body(&mut self) -> Result<PartBody, MultipartError> {
+        let mut data = Vec::new();
+        let mut chunk = [0u8; READ_SIZE];
+        loop {
+            let amount = try!(self.read_body(&mut chunk));
+            if amount == 0 {
+                return Ok(PartBody::Memory(data));
+            }
+            data.push_all(&chunk[..amount]);
+            match self.limits.spill_threshold {
+                Some(threshold) if data.len() > threshold => break,
+                _ => (),
+            }
+        }
+
+        let dir = try!(TempDir::new("http-multipart"));
+        let path = dir.path().join("part");
+        let mut file = try!(File::create(&path));
+        try!(file.write_all(&data[..]));
+        let mut len = data.len() as u64;
+        loop {
+            let amount = try!(self.read_body(&mut chunk));
+            if amount == 0 {
+                break;
+            }
+            try!(file.write_all(&chunk[..amount]));
+            len += amount as u64;
+        }
+        try!(file.flush());
+        Ok(PartBody::File(TempFile {
+            _dir: dir,
+            path: path,
+            len: len,
+        }))
+    }
+
+    /// Read all the remaining parts.
+    pub fn read_parts(&mut self) -> Result<Vec<Part>, MultipartError> {
+        let mut parts = Vec::new();
+        while let Some(headers) = try!(self.next_part()) {
+            let body = try!(self.read_part_body());
+            parts.push(Part { headers: headers, body: body });
+        }
+        Ok(parts)
+    }
+}
+
+/// Read the body of the current part; its end is `EndOfFile`.
+impl<R: Reader> Reader for MultipartReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        match self.read_body(buf) {
+            Ok(0) if !buf.is_empty() => Err(standard_error(EndOfFile)),
+            Ok(amount) => Ok(amount),
+            Err(MultipartError::Io(err)) => Err(err),
+            Err(err) => Err(IoError {
+                kind: OtherIoError,
+                desc: "invalid multipart body",
+                detail: Some(format!("{:?}", err)),
+            }),
+        }
+    }
+}
+
+/// Where the body of a part to be sent comes from.
+enum Source {
+    Bytes(Vec<u8>),
+    /// A reader, and how much it will give if that is known.
+    Reader(Box<Reader + Send>, Option<u64>),
+}
+
+struct OutgoingPart {
+    /// The boundary and headers before the body.
+    head: Vec<u8>,
+    source: Source,
+}
+
+/// A `multipart/form-data` body to send.
+///
+/// Add the fields and files in order, then give the Content-Type header the value of
+/// `content_type` and, if `content_length` is known, the Content-Length header; otherwise the
+/// body has to be sent with the chunked transfer-coding. `RequestWriter.write_multipart` does all
+/// of this.
+pub struct MultipartBuilder {
+    boundary: String,
+    parts: Vec<OutgoingPart>,
+}
+
+impl MultipartBuilder {
+    /// A builder with a random boundary.
+    pub fn new() -> MultipartBuilder {
+        let bytes: [u8; 16] = rand::random();
+        MultipartBuilder::with_boundary(format!("http-boundary-{}", bytes.to_hex()))
+    }
+
+    /// A builder with the given boundary, which must not occur in any of the parts.
+    pub fn with_boundary(boundary: String) -> MultipartBuilder {
+        MultipartBuilder {
+            boundary: boundary,
+            parts: Vec::new(),
+        }
+    }
+
+    pub fn boundary(&self) -> &str {
+        &self.boundary[..]
+    }
+
+    /// Add a text field.
+    pub fn add_text(&mut self, name: &str, value: &str) {
+        self.add(name, None, None, Source::Bytes(value.as_bytes().to_vec()));
+    }
+
+    /// Add a file whose content is in memory.
+    pub fn add_file(&mut self, name: &str, filename: &str, content_type: MediaType,
+                    content: Vec<u8>) {
+        self.add(name, Some(filename), Some(content_type), Source::Bytes(content));
+    }
+
+    /// Add a file whose content is read as the body is written, such as a `File`. If the length
+    /// is not given, the body will not have a known length.
+    pub fn add_reader(&mut self, name: &str, filename: &str, content_type: MediaType,
+                      reader: Box<Reader + Send>, len: Option<u64>) {
+        self.add(name, Some(filename), Some(content_type), Source::Reader(reader, len));
+    }
+
+    fn add(&mut self, name: &str, filename: Option<&str>, content_type: Option<MediaType>,
+           source: Source) {
+        let mut head = format!("--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+                               self.boundary, escape_param(name));
+        match filename {
+            Some(filename) => head.push_str(&format!("; filename=\"{}\"",
+                                                     escape_param(filename))[..]),
+            None => (),
+        }
+        head.push_str("\r\n");
+        match content_type {
+            Some(content_type) => head.push_str(&format!("Content-Type: {}\r\n",
+                                                         content_type)[..]),
+            None => (),
+        }
+        head.push_str("\r\n");
+        self.parts.push(OutgoingPart { head: head.into_bytes(), source: source });
+    }
+
+    /// The value for the Content-Type header.
+    pub fn content_type(&self) -> MediaType {
+        MediaType::new(String::from_str("multipart"), String::from_str("form-data"),
+                       vec!((String::from_str("boundary"), self.boundary.clone())))
+    }
+
+    /// The length of the whole body, if the lengths of all the parts are known.
+    pub fn content_length(&self) -> Option<u64> {
+        let mut len = self.closing().len() as u64;
+        for part in self.parts.iter() {
+            len += part.head.len() as u64 + 2;
+            len += match part.source {
+                Source::Bytes(ref bytes) => bytes.len() as u64,
+                Source::Reader(_, Some(part_len)) => part_len,
+                Source::Reader(_, None) => return None,
+            };
+        }
+        Some(len)
+    }
+
+    fn closing(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+
+    /// Write the body.
+    ///
+    /// It is an error if a reader with a given length gives a different amount, since the
+    /// Content-Length would then be wrong.
+    pub fn write_to<W: Writer>(self, writer: &mut W) -> IoResult<()> {
+        let closing = self.closing();
+        for part in self.parts.into_iter() {
+            try!(writer.write_all(&part.head[..]));
+            match part.source {
+                Source::Bytes(bytes) => try!(writer.write_all(&bytes[..])),
+                Source::Reader(mut reader, len) => {
+                    let mut buf = [0u8; READ_SIZE];
+                    let mut written = 0u64;
+                    loop {
+                        let amount = match reader.read(&mut buf) {
+                            Ok(amount) => amount,
+                            Err(ref err) if err.kind == EndOfFile => break,
+                            Err(err) => return Err(err),
+                        };
+                        try!(writer.write_all(&buf[..amount]));
+                        written += amount as u64;
+                    }
+                    match len {
+                        Some(len) if len != written => return Err(IoError {
+                            kind: OtherIoError,
+                            desc: "multipart part was not the length given",
+                            detail: Some(format!("{} bytes given, {} read", len, written)),
+                        }),
+                        _ => (),
+                    }
+                },
+            }
+            try!(writer.write_all(b"\r\n"));
+        }
+        writer.write_all(closing.as_bytes())
+    }
+}
+
+/// Escape a name or file name for a quoted parameter as browsers do (percent-encoding the quote
+/// and line breaks), since servers do not understand backslash escapes.
+fn escape_param(s: &str) -> String {
+    s.replace("\"", "%22").replace("\r", "%0D").replace("\n", "%0A")
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::min;
+    use std::old_io::{BufReader, IoResult, EndOfFile, standard_error};
+    use std::slice;
+    use super::{MultipartReader, MultipartLimits, MultipartError, PartBody};
+
+    /// Gives at most `step` bytes at a time.
+    struct Trickle {
+        data: &'static [u8],
+        step: usize,
+    }
+
+    impl Reader for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+            if self.data.is_empty() {
+                return Err(standard_error(EndOfFile));
+            }
+            let amount = min(min(self.step, buf.len()), self.data.len());
+            slice::bytes::copy_memory(buf, &self.data[..amount]);
+            self.data = &self.data[amount..];
+            Ok(amount)
+        }
+    }
+
+    const BODY: &'static [u8] = b"\
+preamble\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\
+\r\n\
+A --XyZ lookalike\r\n\
+--XyZ  \r\n\
+Content-Disposition: form-data; name=\"upload\"; filename=\"notes.txt\"\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+line one\r\nline two\r\n\
+--XyZ--\r\n\
+epilogue";
+
+    #[test]
+    fn test_read_parts() {
+        // Reading a few bytes at a time puts boundaries across reads.
+        for &step in [1, 7, BODY.len()].iter() {
+            let reader = Trickle { data: BODY, step: step };
+            let mut multipart = MultipartReader::new(reader, "XyZ", MultipartLimits::new());
+            let parts = multipart.read_parts().unwrap();
+            assert_eq!(parts.len(), 2);
+            assert_eq!(parts[0].headers.name(), Some("title"));
+            assert_eq!(parts[0].headers.filename(), None);
+            match parts[0].body {
+                PartBody::Memory(ref data) => assert_eq!(&data[..], b"A --XyZ lookalike"),
+                PartBody::File(_) => panic!("a small part was spilled"),
+            }
+            assert_eq!(parts[1].headers.filename(), Some("notes.txt"));
+            assert_eq!(parts[1].headers.content_type.as_ref().map(|t| &t.subtype[..]),
+                       Some("plain"));
+            match parts[1].body {
+                PartBody::Memory(ref data) => assert_eq!(&data[..], b"line one\r\nline two"),
+                PartBody::File(_) => panic!("a small part was spilled"),
+            }
+            assert_eq!(multipart.next_part(), Ok(None));
+        }
+    }
+
+    #[test]
+    fn test_streaming_and_skipping() {
+        let mut multipart = MultipartReader::new(BufReader::new(BODY), "XyZ",
+                                                 MultipartLimits::new());
+        // Skip the first part unread.
+        assert!(multipart.next_part().unwrap().is_some());
+        let headers = multipart.next_part().unwrap().unwrap();
+        assert_eq!(headers.name(), Some("upload"));
+        let mut buf = [0u8; 3];
+        assert_eq!(multipart.read(&mut buf), Ok(3));
+        assert_eq!(&buf, b"lin");
+        assert_eq!(multipart.read_to_end().unwrap(), b"e one\r\nline two".to_vec());
+        assert_eq!(multipart.next_part(), Ok(None));
+    }
+
+    #[test]
+    fn test_limits_and_spilling() {
+        let limits = MultipartLimits { max_part_len: 10, .. MultipartLimits::new() };
+        let mut multipart = MultipartReader::new(BufReader::new(BODY), "XyZ", limits);
+        assert_eq!(multipart.read_parts().err(), Some(MultipartError::PartTooLarge));
+
+        let limits = MultipartLimits { max_total_len: 50, .. MultipartLimits::new() };
+        let mut multipart = MultipartReader::new(BufReader::new(BODY), "XyZ", limits);
+        assert_eq!(multipart.read_parts().err(), Some(MultipartError::TooLarge));
+
+        let limits = MultipartLimits { spill_threshold: Some(4), .. MultipartLimits::new() };
+        let mut multipart = MultipartReader::new(BufReader::new(BODY), "XyZ", limits);
+        let parts = multipart.read_parts().unwrap();
+        match parts[1].body {
+            PartBody::File(ref file) => {
+                assert_eq!(file.len(), 18);
+                assert_eq!(file.open().unwrap().read_to_end().unwrap(),
+                           b"line one\r\nline two".to_vec());
+            },
+            PartBody::Memory(_) => panic!("a large part was not spilled"),
+        }
+
+        let mut multipart = MultipartReader::new(BufReader::new(b"--XyZ\r\nno end"), "XyZ",
+                                                 MultipartLimits::new());
+        assert_eq!(multipart.read_parts().err(), Some(MultipartError::Malformed));
+    }
+
+    #[test]
+    fn test_builder_round_trip() {
+        use std::old_io::MemWriter;
+        use headers::content_type::MediaType;
+        use super::{MultipartBuilder, boundary};
+
+        let mut builder = MultipartBuilder::with_boundary(String::from_str("b0undary"));
+        builder.add_text("title", "Say \"hi\"");
+        builder.add_file("upload", "hi.txt",
+                         MediaType::new(String::from_str("text"), String::from_str("plain"),
+                                        Vec::new()),
+                         b"hi\r\n".to_vec());
+        builder.add_reader("more", "more.bin",
+                           MediaType::new(String::from_str("application"),
+                                          String::from_str("octet-stream"), Vec::new()),
+                           Box::new(BufReader::new(b"streamed")), Some(8));
+        let content_type = builder.content_type();
+        assert_eq!(boundary(&content_type), Some("b0undary"));
+        let length = builder.content_length();
+
+        let mut writer = MemWriter::new();
+        builder.write_to(&mut writer).unwrap();
+        let body = writer.into_inner();
+        assert_eq!(length, Some(body.len() as u64));
+        assert!(body.starts_with(b"--b0undary\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\r\nSay \"hi\"\r\n"));
+
+        let mut multipart = MultipartReader::for_content_type(BufReader::new(&body[..]),
+                                                              &content_type,
+                                                              MultipartLimits::new()).unwrap();
+        let parts = multipart.read_parts().unwrap();
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[1].headers.filename(), Some("hi.txt"));
+        match parts[1].body {
+            PartBody::Memory(ref data) => assert_eq!(&data[..], b"hi\r\n"),
+            PartBody::File(_) => panic!(),
+        }
+        match parts[2].body {
+            PartBody::Memory(ref data) => assert_eq!(&data[..], b"streamed"),
+            PartBody::File(_) => panic!(),
+        }
+
+        let mut builder = MultipartBuilder::new();
+        builder.add_reader("f", "f", MediaType::new(String::from_str("text"),
+                                                    String::from_str("plain"), Vec::new()),
+                           Box::new(BufReader::new(b"?")), None);
+        assert_eq!(builder.content_length(), None);
+        assert!(builder.boundary().starts_with("http-boundary-"));
+    }
+}
@@ -0,0 +1,1003 @@
+/*!
+ * Parsing of HTTP/1.x messages straight out of a byte slice.
+ *
+ * Nothing is copied: the method, request-target, header names and values and so forth all come
+ * back as slices of the buffer that was passed in, so that they can be used in place (typically in
+ * the read buffer of a `BufferedStream`).
+ *
+ * The `parse_*` functions each parse one part of a message head. They return `Ok(None)` if the
+ * slice ends before the thing being parsed does; call again with the same bytes and more after
+ * them. Otherwise they return `Ok(Some((n, thing)))`, where `n` is the number of bytes which made up
+ * the thing.
+ *
+ * `Parser` puts these together, with the framing of the body, into an incremental parser of whole
+ * messages.
+ */
+
+use std::ascii::AsciiExt;
+use std::cmp::min;
+use std::str;
+use method::Method;
+use method::Method::{Head, Connect};
+use rfc2616::{CR, LF, SP, HT, COLON, is_token_item, is_ctl, is_hex};
+
+use self::HeaderLine::{Field, EndOfHead};
+
+/// The result of parsing: `None` if more bytes are needed, or the number of bytes consumed and
+/// what was parsed.
+pub type ParseResult<T> = Result<Option<(usize, T)>, ParseError>;
+
+/// The part of a message head which was found to be malformed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ParseError {
+    Method,
+    RequestTarget,
+    Version,
+    StatusCode,
+    HeaderName,
+    HeaderValue,
+    /// A CR which was not followed by LF.
+    NewLine,
+    /// An invalid Content-Length, or several different ones.
+    ContentLength,
+    /// A Transfer-Encoding which does not allow the body to be read.
+    TransferEncoding,
+    /// A malformed chunk in a chunked body.
+    Chunk,
+    /// The input ended part of the way through a message.
+    Truncated,
+}
+
+/// The Request-Line of a request (RFC 7230 §3.1.1).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RequestLine<'a> {
+    /// The method, which is a token and so always ASCII.
+    pub method: &'a str,
+    /// The request-target, exactly as it was sent.
+    pub target: &'a [u8],
+    /// The HTTP version; `(0, 9)` if there was none, as in the simple requests of HTTP/0.9.
+    pub version: (usize, usize),
+}
+
+/// The Status-Line of a response (RFC 7230 §3.1.2).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct StatusLine<'a> {
+    pub version: (usize, usize),
+    pub code: u16,
+    pub reason: &'a [u8],
+}
+
+/// A header field, borrowed from the buffer it was parsed out of.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct HeaderField<'a> {
+    /// The name as it was sent; it is a token and so always ASCII.
+    pub name: &'a str,
+    /// The value, without leading or trailing white space. If the field was folded over several
+    /// lines, the line breaks are still in it; see `unfold`.
+    pub value: &'a [u8],
+}
+
+/// A line of the header section: either a field or the empty line which ends the head.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HeaderLine<'a> {
+    Field(HeaderField<'a>),
+    EndOfHead,
+}
+
+/// A cursor over the bytes being parsed.
+struct Bytes<'a> {
+    slice: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn new(slice: &'a [u8]) -> Bytes<'a> {
+        Bytes { slice: slice, pos: 0 }
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<u8> {
+        if self.pos < self.slice.len() { Some(self.slice[self.pos]) } else { None }
+    }
+
+    #[inline]
+    fn next(&mut self) -> Option<u8> {
+        let b = self.peek();
+        if b.is_some() {
+            self.pos += 1;
+        }
+        b
+    }
+}
+
+/// Get the next byte, or return `Ok(None)` (need more bytes) if there are no more.
+macro_rules! next {
+    ($bytes:expr) => (match $bytes.next() {
+        Some(b) => b,
+        None => return Ok(None),
+    })
+}
+
+/// Peek at the next byte, or return `Ok(None)` (need more bytes) if there are no more.
+macro_rules! peek {
+    ($bytes:expr) => (match $bytes.peek() {
+        Some(b) => b,
+        None => return Ok(None),
+    })
+}
+
+/// Consume the LF of a line ending which began with `b` (either CR or LF).
+fn finish_newline(bytes: &mut Bytes, b: u8) -> Result<Option<()>, ParseError> {
+    if b == CR && next!(bytes) != LF {
+        return Err(ParseError::NewLine);
+    }
+    Ok(Some(()))
+}
+
+/// Read `1*DIGIT`, leaving whatever follows it unconsumed.
+fn parse_number(bytes: &mut Bytes) -> Result<Option<usize>, ParseError> {
+    let mut n = 0usize;
+    let mut got_digit = false;
+    loop {
+        match peek!(bytes) {
+            b @ b'0'...b'9' => {
+                n = match n.checked_mul(10).and_then(|n| n.checked_add((b - b'0') as usize)) {
+                    Some(n) => n,
+                    None => return Err(ParseError::Version),
+                };
+                got_digit = true;
+                bytes.next();
+            },
+            _ if got_digit => return Ok(Some(n)),
+            _ => return Err(ParseError::Version),
+        }
+    }
+}
+
+/// Read `HTTP/major.minor`, leaving whatever follows it unconsumed. ("HTTP" is matched
+/// case-insensitively.)
+fn parse_version(bytes: &mut Bytes) -> Result<Option<(usize, usize)>, ParseError> {
+    for &expected in b"http".iter() {
+        if next!(bytes) | 0x20 != expected {
+            return Err(ParseError::Version);
+        }
+    }
+    if next!(bytes) != b'/' {
+        return Err(ParseError::Version);
+    }
+    let major = match try!(parse_number(bytes)) {
+        Some(n) => n,
+        None => return Ok(None),
+    };
+    if next!(bytes) != b'.' {
+        return Err(ParseError::Version);
+    }
+    let minor = match try!(parse_number(bytes)) {
+        Some(n) => n,
+        None => return Ok(None),
+    };
+    Ok(Some((major, minor)))
+}
+
+/// Parse a Request-Line, including the line ending.
+///
+/// As RFC 7230 §3.5 suggests, a run of spaces between the method and the request-target is
+/// tolerated, and a line ending of a bare LF is accepted.
+pub fn parse_request_line<'a>(buf: &'a [u8]) -> ParseResult<RequestLine<'a>> {
+    let mut bytes = Bytes::new(buf);
+
+    loop {
+        match next!(bytes) {
+            SP => break,
+            b if is_token_item(b) => (),
+            _ => return Err(ParseError::Method),
+        }
+    }
+    let method_end = bytes.pos - 1;
+    if method_end == 0 {
+        return Err(ParseError::Method);
+    }
+    // The method is made of token characters, which are all ASCII.
+    let method = unsafe { str::from_utf8_unchecked(&buf[..method_end]) };
+
+    while peek!(bytes) == SP {
+        bytes.next();
+    }
+
+    let target_start = bytes.pos;
+    let mut target_end = target_start;
+    let mut version = (0, 9);
+    loop {
+        match next!(bytes) {
+            SP => {
+                target_end = bytes.pos - 1;
+                version = match try!(parse_version(&mut bytes)) {
+                    Some(version) => version,
+                    None => return Ok(None),
+                };
+                match next!(bytes) {
+                    b @ CR | b @ LF => if try!(finish_newline(&mut bytes, b)).is_none() {
+                        return Ok(None);
+                    },
+                    _ => return Err(ParseError::Version),
+                }
+                break;
+            },
+            // With no HTTP-Version, it is a simple request of HTTP/0.9.
+            b @ CR | b @ LF => {
+                target_end = bytes.pos - 1;
+                if try!(finish_newline(&mut bytes, b)).is_none() {
+                    return Ok(None);
+                }
+                break;
+            },
+            b if is_ctl(b) => return Err(ParseError::RequestTarget),
+            _ => (),
+        }
+    }
+    if target_end == target_start {
+        return Err(ParseError::RequestTarget);
+    }
+
+    Ok(Some((bytes.pos, RequestLine {
+        method: method,
+        target: &buf[target_start..target_end],
+        version: version,
+    })))
+}
+
+/// Parse a Status-Line, including the line ending.
+///
+/// The reason phrase may be empty, and the space before it may be left out entirely.
+pub fn parse_status_line<'a>(buf: &'a [u8]) -> ParseResult<StatusLine<'a>> {
+    let mut bytes = Bytes::new(buf);
+
+    let version = match try!(parse_version(&mut bytes)) {
+        Some(version) => version,
+        None => return Ok(None),
+    };
+    if next!(bytes) != SP {
+        return Err(ParseError::Version);
+    }
+
+    let mut code = 0u16;
+    for _ in 0..3 {
+        match next!(bytes) {
+            b @ b'0'...b'9' => code = code * 10 + (b - b'0') as u16,
+            _ => return Err(ParseError::StatusCode),
+        }
+    }
+
+    let mut reason_start = bytes.pos;
+    let mut reason_end = reason_start;
+    match next!(bytes) {
+        SP => {
+            reason_start = bytes.pos;
+            loop {
+                match next!(bytes) {
+                    b @ CR | b @ LF => {
+                        reason_end = bytes.pos - 1;
+                        match try!(finish_newline(&mut bytes, b)) {
+                            Some(()) => break,
+                            None => return Ok(None),
+                        }
+                    },
+                    HT => (),
+                    b if is_ctl(b) => return Err(ParseError::StatusCode),
+                    _ => (),
+                }
+            }
+        },
+        b @ CR | b @ LF => {
+            reason_start = bytes.pos - 1;
+            reason_end = reason_start;
+            if try!(finish_newline(&mut bytes, b)).is_none() {
+                return Ok(None);
+            }
+        },
+        _ => return Err(ParseError::StatusCode),
+    }
+
+    Ok(Some((bytes.pos, StatusLine {
+        version: version,
+        code: code,
+        reason: &buf[reason_start..reason_end],
+    })))
+}
+
+/// Parse one line of the header section: a header field (which may be folded over several lines)
+/// or the empty line which ends the head.
+pub fn parse_header_line<'a>(buf: &'a [u8]) -> ParseResult<HeaderLine<'a>> {
+    let mut bytes = Bytes::new(buf);
+
+    match next!(bytes) {
+        b @ CR | b @ LF => return match try!(finish_newline(&mut bytes, b)) {
+            Some(()) => Ok(Some((bytes.pos, EndOfHead))),
+            None => Ok(None),
+        },
+        b if is_token_item(b) => (),
+        _ => return Err(ParseError::HeaderName),
+    }
+
+    // The name, which the colon must follow at once: white space before it is refused, as it
+    // may be read differently by something else in the chain (RFC 7230 §3.2.4).
+    let name_end;
+    loop {
+        match next!(bytes) {
+            COLON => {
+                name_end = bytes.pos - 1;
+                break;
+            },
+            b if is_token_item(b) => (),
+            _ => return Err(ParseError::HeaderName),
+        }
+    }
+    // Token characters are all ASCII.
+    let name = unsafe { str::from_utf8_unchecked(&buf[..name_end]) };
+
+    loop {
+        match peek!(bytes) {
+            SP | HT => { bytes.next(); },
+            _ => break,
+        }
+    }
+
+    let value_start = bytes.pos;
+    let mut value_end = value_start;
+    loop {
+        match next!(bytes) {
+            b @ CR | b @ LF => {
+                if try!(finish_newline(&mut bytes, b)).is_none() {
+                    return Ok(None);
+                }
+                // A line beginning with white space continues the field (obs-fold).
+                match peek!(bytes) {
+                    SP | HT => (),
+                    _ => break,
+                }
+            },
+            SP | HT => (),
+            b if is_ctl(b) => return Err(ParseError::HeaderValue),
+            _ => value_end = bytes.pos,
+        }
+    }
+
+    Ok(Some((bytes.pos, Field(HeaderField {
+        name: name,
+        value: &buf[value_start..value_end],
+    }))))
+}
+
+/// Parse the head of a request: the Request-Line and, unless it is an HTTP/0.9 request, the header
+/// fields and the empty line after them.
+///
+/// The header fields are put in `headers`, which is cleared first.
+pub fn parse_request_head<'a>(buf: &'a [u8], headers: &mut Vec<HeaderField<'a>>)
+                              -> ParseResult<RequestLine<'a>> {
+    headers.clear();
+    let (mut consumed, line) = match try!(parse_request_line(buf)) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    if line.version == (0, 9) {
+        return Ok(Some((consumed, line)));
+    }
+    loop {
+        match try!(parse_header_line(&buf[consumed..])) {
+            Some((n, Field(field))) => {
+                consumed += n;
+                headers.push(field);
+            },
+            Some((n, EndOfHead)) => {
+                consumed += n;
+                return Ok(Some((consumed, line)));
+            },
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Join the lines of a folded header value, replacing each line break and the white space around
+/// it with a single space.
+///
+/// This copies, but folding is obsolete and rare; check for it with `is_folded` first.
+pub fn unfold(value: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(value.len());
+    let mut in_break = false;
+    for &b in value.iter() {
+        match b {
+            CR | LF => in_break = true,
+            SP | HT if in_break => (),
+            _ => {
+                if in_break {
+                    while out.last().map_or(false, |&b| b == SP || b == HT) {
+                        out.pop();
+                    }
+                    out.push(SP);
+                    in_break = false;
+                }
+                out.push(b);
+            },
+        }
+    }
+    out
+}
+
+/// Whether a header value was folded over several lines.
+#[inline]
+pub fn is_folded(value: &[u8]) -> bool {
+    value.contains(&LF)
+}
+
+/// Whether a `Parser` reads requests or responses.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MessageKind {
+    Request,
+    Response,
+}
+
+/// How the end of a message body is found (RFC 7230 §3.3.3).
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BodyLength {
+    /// The body is this many bytes long (zero if there is no body at all).
+    Length(u64),
+    /// The body has the chunked transfer-coding.
+    Chunked,
+    /// The body goes on until the connection is closed (only for responses).
+    UntilClose,
+}
+
+/// Something found by a `Parser` in its input.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Event<'a> {
+    /// Nothing more can be parsed until there are more bytes.
+    Incomplete,
+    RequestLine(RequestLine<'a>),
+    StatusLine(StatusLine<'a>),
+    Header(HeaderField<'a>),
+    /// The end of the head; the framing of the body is now known (see `Parser.body_length`).
+    HeadersComplete,
+    /// Part of the body, with any chunked transfer-coding removed.
+    Body(&'a [u8]),
+    /// A field of the trailer which may follow a chunked body.
+    Trailer(HeaderField<'a>),
+    /// The end of the message. The parser is ready for the next message on the connection.
+    MessageComplete,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum State {
+    StartLine,
+    Headers,
+    /// So many bytes of body are left.
+    Body(u64),
+    UntilClose,
+    ChunkSize,
+    /// So many bytes of the current chunk are left.
+    ChunkData(u64),
+    /// The line ending after a chunk's data.
+    ChunkDataEnd,
+    Trailers,
+}
+
+/// An incremental HTTP/1.x message parser which does no I/O of its own.
+///
+/// Feed it whatever bytes are available with `parse`; it says how many of them it consumed and
+/// what it found in them. The caller is responsible for keeping any unconsumed bytes and passing
+/// them again, with more after them, in the next call. Parsed parts borrow from the input, so
+/// nothing is copied. This makes it equally suitable for blocking streams, non-blocking sockets
+/// driven by an event loop, and tests.
+///
+/// Messages may follow each other on the same connection; after `MessageComplete` the parser
+/// begins on the next one.
+pub struct Parser {
+    kind: MessageKind,
+    state: State,
+    /// For responses, whether the request was one whose response never has a body (HEAD).
+    no_body: bool,
+    /// For responses, whether the request was CONNECT, whose successful response has no body.
+    connect: bool,
+    /// Whether this is a simple (HTTP/0.9) request, which has no headers.
+    simple: bool,
+    status_code: u16,
+    content_length: Option<u64>,
+    /// Whether the last transfer-coding is chunked, if there was a Transfer-Encoding header.
+    chunked: Option<bool>,
+    body_length: Option<BodyLength>,
+}
+
+impl Parser {
+    pub fn new(kind: MessageKind) -> Parser {
+        Parser {
+            kind: kind,
+            state: State::StartLine,
+            no_body: false,
+            connect: false,
+            simple: false,
+            status_code: 0,
+            content_length: None,
+            chunked: None,
+            body_length: None,
+        }
+    }
+
+    /// A parser for the requests arriving at a server.
+    pub fn request() -> Parser {
+        Parser::new(MessageKind::Request)
+    }
+
+    /// A parser for the response to a request with the given method.
+    ///
+    /// Responses to HEAD requests have no body, whatever their headers say; neither do successful
+    /// responses to CONNECT, after which the connection is a tunnel.
+    pub fn response(request_method: &Method) -> Parser {
+        let mut parser = Parser::new(MessageKind::Response);
+        parser.no_body = *request_method == Head;
+        parser.connect = *request_method == Connect;
+        parser
+    }
+
+    /// How the body of the current message is framed; `None` until `HeadersComplete`.
+    pub fn body_length(&self) -> Option<BodyLength> {
+        self.body_length
+    }
+
+    /// Whether the parser is between messages (it has not begun parsing another one).
+    pub fn is_idle(&self) -> bool {
+        self.state == State::StartLine
+    }
+
+    /// Whether the parser is reading the header fields of a message.
+    pub fn is_in_head(&self) -> bool {
+        self.state == State::Headers
+    }
+
+    /// Parse as much as possible of `data`, up to the next event.
+    ///
+    /// Returns the number of bytes consumed (which can be non-zero even for `Incomplete`) and the
+    /// event. A zero-length body produces `MessageComplete` without consuming anything.
+    pub fn parse<'a>(&mut self, data: &'a [u8]) -> Result<(usize, Event<'a>), ParseError> {
+        let mut consumed = 0;
+        loop {
+            let rest = &data[consumed..];
+            match self.state {
+                State::StartLine => {
+                    // RFC 7230 §3.5: ignore empty lines before the start-line.
+                    match rest.first() {
+                        Some(&LF) => {
+                            consumed += 1;
+                            continue;
+                        },
+                        Some(&CR) => match rest.get(1) {
+                            Some(&LF) => {
+                                consumed += 2;
+                                continue;
+                            },
+                            Some(_) => return Err(ParseError::NewLine),
+                            None => return Ok((consumed, Event::Incomplete)),
+                        },
+                        _ => (),
+                    }
+                    self.content_length = None;
+                    self.chunked = None;
+                    self.body_length = None;
+                    return match self.kind {
+                        MessageKind::Request => match try!(parse_request_line(rest)) {
+                            Some((n, line)) => {
+                                self.simple = line.version == (0, 9);
+                                self.state = State::Headers;
+                                Ok((consumed + n, Event::RequestLine(line)))
+                            },
+                            None => Ok((consumed, Event::Incomplete)),
+                        },
+                        MessageKind::Response => match try!(parse_status_line(rest)) {
+                            Some((n, line)) => {
+                                self.status_code = line.code;
+                                self.state = State::Headers;
+                                Ok((consumed + n, Event::StatusLine(line)))
+                            },
+                            None => Ok((consumed, Event::Incomplete)),
+                        },
+                    };
+                },
+
+                State::Headers if self.simple => {
+                    try!(self.start_body());
+                    return Ok((consumed, Event::HeadersComplete));
+                },
+
+                State::Headers => return match try!(parse_header_line(rest)) {
+                    Some((n, Field(field))) => {
+                        try!(self.note_header(&field));
+                        Ok((consumed + n, Event::Header(field)))
+                    },
+                    Some((n, EndOfHead)) => {
+                        try!(self.start_body());
+                        Ok((consumed + n, Event::HeadersComplete))
+                    },
+                    None => Ok((consumed, Event::Incomplete)),
+                },
+
+                State::Body(0) => {
+                    self.state = State::StartLine;
+                    return Ok((consumed, Event::MessageComplete));
+                },
+
+                State::Body(remaining) => {
+                    if rest.is_empty() {
+                        return Ok((consumed, Event::Incomplete));
+                    }
+                    let n = min(remaining, rest.len() as u64) as usize;
+                    self.state = State::Body(remaining - n as u64);
+                    return Ok((consumed + n, Event::Body(&rest[..n])));
+                },
+
+                State::UntilClose => {
+                    if rest.is_empty() {
+                        return Ok((consumed, Event::Incomplete));
+                    }
+                    return Ok((data.len(), Event::Body(rest)));
+                },
+
+                State::ChunkSize => match try!(parse_chunk_size(rest)) {
+                    Some((n, 0)) => {
+                        consumed += n;
+                        self.state = State::Trailers;
+                    },
+                    Some((n, size)) => {
+                        consumed += n;
+                        self.state = State::ChunkData(size);
+                    },
+                    None => return Ok((consumed, Event::Incomplete)),
+                },
+
+                State::ChunkData(remaining) => {
+                    if rest.is_empty() {
+                        return Ok((consumed, Event::Incomplete));
+                    }
+                    let n = min(remaining, rest.len() as u64) as usize;
+                    self.state = if remaining == n as u64 {
+                        State::ChunkDataEnd
+                    } else {
+                        State::ChunkData(remaining - n as u64)
+                    };
+                    return Ok((consumed + n, Event::Body(&rest[..n])));
+                },
+
+                State::ChunkDataEnd => match (rest.get(0), rest.get(1)) {
+                    (Some(&LF), _) => {
+                        consumed += 1;
+                        self.state = State::ChunkSize;
+                    },
+                    (Some(&CR), Some(&LF)) => {
+                        consumed += 2;
+                        self.state = State::ChunkSize;
+                    },
+                    (None, _) | (Some(&CR), None) => return Ok((consumed, Event::Incomplete)),
+                    _ => return Err(ParseError::Chunk),
+                },
+
+                State::Trailers => return match try!(parse_header_line(rest)) {
+                    Some((n, Field(field))) => Ok((consumed + n, Event::Trailer(field))),
+                    Some((n, EndOfHead)) => {
+                        self.state = State::StartLine;
+                        Ok((consumed + n, Event::MessageComplete))
+                    },
+                    None => Ok((consumed, Event::Incomplete)),
+                },
+            }
+        }
+    }
+
+    /// Tell the parser that the input has ended (the connection was closed).
+    ///
+    /// This completes a body which runs until the connection is closed. It is an error if a
+    /// message was otherwise only partly parsed.
+    pub fn finish(&mut self) -> Result<(), ParseError> {
+        match self.state {
+            State::StartLine => Ok(()),
+            State::UntilClose => {
+                self.state = State::StartLine;
+                Ok(())
+            },
+            _ => Err(ParseError::Truncated),
+        }
+    }
+
+    /// Take note of the headers which determine the framing of the body.
+    fn note_header(&mut self, field: &HeaderField) -> Result<(), ParseError> {
+        if field.name.eq_ignore_ascii_case("content-length") {
+            let length = match str::from_utf8(field.value).ok().and_then(|v| v.parse::<u64>().ok()) {
+                Some(length) => length,
+                None => return Err(ParseError::ContentLength),
+            };
+            match self.content_length {
+                Some(existing) if existing != length => return Err(ParseError::ContentLength),
+                _ => self.content_length = Some(length),
+            }
+        } else if field.name.eq_ignore_ascii_case("transfer-encoding") {
+            let value = match str::from_utf8(field.value) {
+                Ok(value) => value,
+                Err(_) => return Err(ParseError::TransferEncoding),
+            };
+            let last = value.split(',').last().unwrap().trim();
+            self.chunked = Some(last.eq_ignore_ascii_case("chunked"));
+        }
+        Ok(())
+    }
+
+    /// Work out how the body is framed, now that all the headers are known.
+    fn start_body(&mut self) -> Result<(), ParseError> {
+        let is_request = self.kind == MessageKind::Request;
+        // A refusal of CONNECT (say, 407 or 502) has a body like any other response.
+        let length = if !is_request && (self.no_body || self.status_code / 100 == 1 ||
+                                        self.status_code == 204 || self.status_code == 304 ||
+                                        (self.connect && self.status_code / 100 == 2)) {
+            BodyLength::Length(0)
+        } else {
+            match (self.chunked, self.content_length) {
+                (Some(true), _) => BodyLength::Chunked,
+                // A request's body must be chunked if it has a transfer-coding at all.
+                (Some(false), _) if is_request => return Err(ParseError::TransferEncoding),
+                (Some(false), _) => BodyLength::UntilClose,
+                (None, Some(length)) => BodyLength::Length(length),
+                (None, None) if is_request => BodyLength::Length(0),
+                (None, None) => BodyLength::UntilClose,
+            }
+        };
+        self.body_length = Some(length);
+        self.state = match length {
+            BodyLength::Length(length) => State::Body(length),
+            BodyLength::Chunked => State::ChunkSize,
+            BodyLength::UntilClose => State::UntilClose,
+        };
+        Ok(())
+    }
+}
+
+/// Parse the line which begins a chunk: the size in hexadecimal, perhaps some chunk extensions
+/// (which are ignored) and the line ending.
+fn parse_chunk_size(buf: &[u8]) -> ParseResult<u64> {
+    let mut bytes = Bytes::new(buf);
+    let mut size = 0u64;
+    let mut got_digit = false;
+    loop {
+        match next!(bytes) {
+            b if is_hex(b) => {
+                let digit = match b {
+                    b'0'...b'9' => b - b'0',
+                    b'a'...b'f' => b - b'a' + 10,
+                    _ => b - b'A' + 10,
+                };
+                size = match size.checked_mul(16).and_then(|s| s.checked_add(digit as u64)) {
+                    Some(size) => size,
+                    None => return Err(ParseError::Chunk),
+                };
+                got_digit = true;
+            },
+            b @ CR | b @ LF if got_digit => {
+                if try!(finish_newline(&mut bytes, b)).is_none() {
+                    return Ok(None);
+                }
+                return Ok(Some((bytes.pos, size)));
+            },
+            b';' | SP | HT if got_digit => {
+                // Chunk extensions; skip to the end of the line.
+                loop {
+                    match next!(bytes) {
+                        b @ CR | b @ LF => {
+                            if try!(finish_newline(&mut bytes, b)).is_none() {
+                                return Ok(None);
+                            }
+                            return Ok(Some((bytes.pos, size)));
+                        },
+                        _ => (),
+                    }
+                }
+            },
+            _ => return Err(ParseError::Chunk),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_request_line, parse_status_line, parse_header_line, parse_request_head,
+                unfold, RequestLine, StatusLine, HeaderField, ParseError, Parser, Event,
+                BodyLength};
+    use super::HeaderLine::{Field, EndOfHead};
+    use method::Method::{Get, Head, Connect};
+    use std::cmp::min;
+
+    /// What a parser found in a sequence of messages.
+    #[derive(PartialEq, Eq, Debug)]
+    struct Summary {
+        start_lines: usize,
+        headers: Vec<(String, Vec<u8>)>,
+        body: Vec<u8>,
+        trailers: Vec<(String, Vec<u8>)>,
+        messages: usize,
+    }
+
+    /// Feed `input` to the parser `step` bytes at a time, keeping unconsumed bytes for the next
+    /// call as a caller must.
+    fn run(parser: &mut Parser, input: &[u8], step: usize) -> Result<Summary, ParseError> {
+        let mut summary = Summary { start_lines: 0, headers: vec!(), body: vec!(), trailers: vec!(),
+                                    messages: 0 };
+        let mut buffer = Vec::new();
+        let mut fed = 0;
+        loop {
+            let (consumed, incomplete) = {
+                let (consumed, event) = try!(parser.parse(&buffer[..]));
+                match event {
+                    Event::RequestLine(_) | Event::StatusLine(_) => summary.start_lines += 1,
+                    Event::Header(f) =>
+                        summary.headers.push((String::from_str(f.name), f.value.to_vec())),
+                    Event::HeadersComplete => (),
+                    Event::Body(b) => summary.body.push_all(b),
+                    Event::Trailer(f) =>
+                        summary.trailers.push((String::from_str(f.name), f.value.to_vec())),
+                    Event::MessageComplete => summary.messages += 1,
+                    Event::Incomplete => (),
+                }
+                (consumed, event == Event::Incomplete)
+            };
+            buffer = buffer[consumed..].to_vec();
+            if incomplete {
+                if fed == input.len() {
+                    try!(parser.finish());
+                    return Ok(summary);
+                }
+                let end = min(fed + step, input.len());
+                buffer.push_all(&input[fed..end]);
+                fed = end;
+            }
+        }
+    }
+
+    fn field(name: &str, value: &[u8]) -> (String, Vec<u8>) {
+        (String::from_str(name), value.to_vec())
+    }
+
+    #[test]
+    fn test_parse_request_line() {
+        assert_eq!(parse_request_line(b"GET /a?b HTTP/1.1\r\nHost"),
+                   Ok(Some((19, RequestLine { method: "GET", target: b"/a?b", version: (1, 1) }))));
+        assert_eq!(parse_request_line(b"get   http://example.com/ HTTP/42.17\n"),
+                   Ok(Some((37, RequestLine { method: "get",
+                                              target: b"http://example.com/",
+                                              version: (42, 17) }))));
+        assert_eq!(parse_request_line(b"FOO /\r\n"),
+                   Ok(Some((7, RequestLine { method: "FOO", target: b"/", version: (0, 9) }))));
+        assert_eq!(parse_request_line(b"GET /\xc3\xbc HTTP/1.0\r\n"),
+                   Ok(Some((18, RequestLine { method: "GET", target: b"/\xc3\xbc",
+                                              version: (1, 0) }))));
+
+        assert_eq!(parse_request_line(b"GE,T / HTTP/1.1\r\n"), Err(ParseError::Method));
+        assert_eq!(parse_request_line(b" / HTTP/1.1\r\n"), Err(ParseError::Method));
+        assert_eq!(parse_request_line(b"GET / HTTX/1.1\r\n"), Err(ParseError::Version));
+        assert_eq!(parse_request_line(b"GET / HTTP/1.1\rX"), Err(ParseError::NewLine));
+        assert_eq!(parse_request_line(b"GET / HTTP/1.1 \r\n"), Err(ParseError::Version));
+        assert_eq!(parse_request_line(b"GET /\x00 HTTP/1.1\r\n"), Err(ParseError::RequestTarget));
+    }
+
+    #[test]
+    fn test_parse_incomplete() {
+        let request = b"GET / HTTP/1.1\r\nHost: example.com\r\n  .org\r\n\r\n";
+        let mut headers = Vec::new();
+        for i in 0..request.len() {
+            assert_eq!(parse_request_head(&request[..i], &mut headers), Ok(None));
+        }
+        assert_eq!(parse_request_head(request, &mut headers),
+                   Ok(Some((request.len(), RequestLine { method: "GET", target: b"/",
+                                                         version: (1, 1) }))));
+        assert_eq!(headers, vec!(HeaderField { name: "Host", value: b"example.com\r\n  .org" }));
+        assert_eq!(unfold(headers[0].value), b"example.com .org".to_vec());
+    }
+
+    #[test]
+    fn test_parse_status_line() {
+        assert_eq!(parse_status_line(b"HTTP/1.1 404 Not Found\r\n"),
+                   Ok(Some((24, StatusLine { version: (1, 1), code: 404, reason: b"Not Found" }))));
+        assert_eq!(parse_status_line(b"HTTP/1.0 200\n"),
+                   Ok(Some((13, StatusLine { version: (1, 0), code: 200, reason: b"" }))));
+        assert_eq!(parse_status_line(b"HTTP/1.1 20 OK\r\n"), Err(ParseError::StatusCode));
+        assert_eq!(parse_status_line(b"HTTP/1.1 200 OK"), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_header_line() {
+        assert_eq!(parse_header_line(b"X-Foo:  bar baz \r\n\r\n"),
+                   Ok(Some((18, Field(HeaderField { name: "X-Foo", value: b"bar baz" })))));
+        assert_eq!(parse_header_line(b"X-Foo:\r\nY"),
+                   Ok(Some((8, Field(HeaderField { name: "X-Foo", value: b"" })))));
+        assert_eq!(parse_header_line(b"X-Foo :\r\nY"), Err(ParseError::HeaderName));
+        assert_eq!(parse_header_line(b"Transfer-Encoding\t: chunked\r\n\r\n"),
+                   Err(ParseError::HeaderName));
+        assert_eq!(parse_header_line(b"\r\n"), Ok(Some((2, EndOfHead))));
+        assert_eq!(parse_header_line(b"\n"), Ok(Some((1, EndOfHead))));
+        // Whether the line is folded cannot be known until the next byte is seen.
+        assert_eq!(parse_header_line(b"X-Foo: bar\r\n"), Ok(None));
+        assert_eq!(parse_header_line(b"X Foo: bar\r\n\r\n"), Err(ParseError::HeaderName));
+        assert_eq!(parse_header_line(b": bar\r\n\r\n"), Err(ParseError::HeaderName));
+        assert_eq!(parse_header_line(b"X-Foo: b\x00r\r\n\r\n"), Err(ParseError::HeaderValue));
+    }
+
+    #[test]
+    fn test_parser_requests() {
+        let input = b"\
+POST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello\r\n\
+GET /b HTTP/1.1\r\nHost: x\r\n\r\n";
+        for &step in [1, 7, input.len()].iter() {
+            let mut parser = Parser::request();
+            assert_eq!(run(&mut parser, input, step), Ok(Summary {
+                start_lines: 2,
+                headers: vec!(field("Host", b"x"), field("Content-Length", b"5"),
+                              field("Host", b"x")),
+                body: b"hello".to_vec(),
+                trailers: vec!(),
+                messages: 2,
+            }));
+            assert!(parser.is_idle());
+        }
+    }
+
+    #[test]
+    fn test_parser_chunked_response() {
+        let input = b"\
+HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\n\
+4\r\nWiki\r\n5;ext=1\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\nX-Sum: 1\r\n\r\n";
+        for &step in [1, 3, input.len()].iter() {
+            let mut parser = Parser::response(&Get);
+            let summary = run(&mut parser, input, step).unwrap();
+            assert_eq!(parser.body_length(), Some(BodyLength::Chunked));
+            assert_eq!(summary.body, b"Wikipedia in\r\n\r\nchunks.".to_vec());
+            assert_eq!(summary.trailers, vec!(field("X-Sum", b"1")));
+            assert_eq!(summary.messages, 1);
+        }
+    }
+
+    #[test]
+    fn test_parser_response_framing() {
+        // No body in a response to HEAD, nor in a 1xx, 204 or 304 response.
+        let mut parser = Parser::response(&Head);
+        let summary = run(&mut parser, b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n", 4);
+        assert_eq!(summary.map(|s| (s.body, s.messages)), Ok((vec!(), 1)));
+        let mut parser = Parser::response(&Get);
+        let summary = run(&mut parser, b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n", 4);
+        assert_eq!(summary.map(|s| (s.start_lines, s.messages)), Ok((2, 2)));
+
+        // Only a successful response to CONNECT goes without a body.
+        let mut parser = Parser::response(&Connect);
+        let summary = run(&mut parser, b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n", 4);
+        assert_eq!(summary.map(|s| (s.body, s.messages)), Ok((vec!(), 1)));
+        let mut parser = Parser::response(&Connect);
+        let summary = run(&mut parser, b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+                                         Content-Length: 6\r\n\r\ndenied", 4);
+        assert_eq!(summary.map(|s| (s.body, s.messages)), Ok((b"denied".to_vec(), 1)));
+
+        // Without a length, the body goes on until the connection is closed.
+        let mut parser = Parser::response(&Get);
+        let summary = run(&mut parser, b"HTTP/1.0 200 OK\r\n\r\nall of this", 4);
+        assert_eq!(summary.map(|s| (s.body, s.messages)), Ok((b"all of this".to_vec(), 0)));
+        assert_eq!(parser.body_length(), Some(BodyLength::UntilClose));
+
+        // ... which is not the case for a length which is cut short.
+        let mut parser = Parser::response(&Get);
+        assert_eq!(run(&mut parser, b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc", 4),
+                   Err(ParseError::Truncated));
+    }
+
+    #[test]
+    fn test_parser_errors() {
+        let mut parser = Parser::request();
+        assert_eq!(run(&mut parser, b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n", 100),
+                   Err(ParseError::ContentLength));
+        let mut parser = Parser::request();
+        assert_eq!(run(&mut parser, b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n", 100),
+                   Err(ParseError::TransferEncoding));
+        let mut parser = Parser::request();
+        assert_eq!(run(&mut parser, b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n", 100),
+                   Err(ParseError::Chunk));
+    }
+}
@@ -145,7 +145,7 @@ pub fn is_separator(o: u8) -> bool {
 // see https://www.iana.org/assignments/http-parameters/http-parameters.xml
 
 /// Content-coding value tokens
-#[derive(Copy)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum ContentCoding {
     // An encoding format produced by the file compression program "gzip" (GNU zip) as described
     // in RFC 1952 [25]. This format is a Lempel-Ziv coding (LZ77) with a 32 bit CRC.
@@ -250,4 +250,5 @@ impl FromStr for TransferCoding {
 // connection. A server MUST NOT send transfer-codings to an HTTP/1.0
 // client.
 
-// [My note: implication of this is that I need to track HTTP version number of clients.]
+// [My note: see `Request::check_head` and `ResponseWriter::write_headers`, which track the HTTP
+// version number of clients for this.]
@@ -0,0 +1,304 @@
+//! Compressing responses with gzip or deflate, as a layer of middleware.
+//!
+//! ```ignore
+//! Stack::new(server).layer(Compression::new()).serve_forever();
+//! ```
+//!
+//! The coding is chosen from the request's Accept-Encoding header; the response is compressed as
+//! it is written, so its Content-Length is dropped (and it is sent chunked). Responses which are
+//! small, have no body, are already encoded or are of a media type which is compressed already
+//! (most images, audio and video, archives) are left alone.
+
+use std::ascii::AsciiExt;
+use std::old_io::IoResult;
+
+use coding::Encoder;
+use headers::content_type::MediaType;
+use headers::response::HeaderCollection;
+use rfc2616::ContentCoding;
+use server::{Request, ResponseWriter};
+use server::middleware::{Middleware, Next};
+use server::response::ResponseFilter;
+use status::Status;
+
+/// Responses with a Content-Length below this are not worth compressing.
+pub const DEFAULT_MIN_LEN: usize = 256;
+
+/// Middleware which compresses response bodies; see the module documentation.
+#[derive(Copy, Clone, Debug)]
+pub struct Compression {
+    /// The smallest Content-Length which is compressed; responses of unknown length always are
+    /// (if they are of a suitable type).
+    pub min_len: usize,
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression {
+            min_len: DEFAULT_MIN_LEN,
+        }
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: Request, w: &mut ResponseWriter, next: Next) {
+        let coding = request.headers.accept_encoding.as_ref()
+            .and_then(|accept_encoding| choose_coding(&accept_encoding[..]));
+        w.add_filter(Box::new(CompressionFilter::new(coding, self.min_len)));
+        next(request, w)
+    }
+}
+
+/// The coding to compress with, given the value of an Accept-Encoding header: gzip or deflate,
+/// whichever has the higher quality value (gzip if they are the same), or `None` if neither is
+/// acceptable.
+pub fn choose_coding(accept_encoding: &str) -> Option<ContentCoding> {
+    let (mut gzip, mut deflate, mut any) = (None, None, None);
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap().trim();
+        let mut quality = 1.0f32;
+        for parameter in parts {
+            let parameter = parameter.trim();
+            if parameter.starts_with("q=") || parameter.starts_with("Q=") {
+                quality = match parameter[2..].parse() {
+                    Ok(quality) => quality,
+                    Err(_) => 0.0,
+                };
+            }
+        }
+        match coding.parse() {
+            Ok(ContentCoding::Gzip) => gzip = Some(quality),
+            Ok(ContentCoding::Deflate) => deflate = Some(quality),
+            _ if coding.eq_ignore_ascii_case("x-gzip") => gzip = Some(quality),
+            _ if coding == "*" => any = Some(quality),
+            _ => (),
+        }
+    }
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    if gzip > 0.0 && gzip >= deflate {
+        Some(ContentCoding::Gzip)
+    } else if deflate > 0.0 {
+        Some(ContentCoding::Deflate)
+    } else {
+        None
+    }
+}
+
+/// Whether a media type is worth compressing: not if it is compressed already.
+pub fn is_compressible(media_type: &MediaType) -> bool {
+    let type_ = media_type.type_.to_ascii_lowercase();
+    let subtype = media_type.subtype.to_ascii_lowercase();
+    match (&type_[..], &subtype[..]) {
+        ("image", "svg+xml") | ("image", "x-icon") | ("image", "bmp") => true,
+        ("image", _) | ("audio", _) | ("video", _) => false,
+        ("font", "woff") | ("font", "woff2") => false,
+        ("application", "zip") | ("application", "gzip") | ("application", "x-gzip") |
+        ("application", "x-bzip2") | ("application", "x-xz") | ("application", "x-7z-compressed") |
+        ("application", "x-rar-compressed") | ("application", "pdf") |
+        ("application", "octet-stream") => false,
+        _ => true,
+    }
+}
+
+/// The filter which `Compression` adds to each response.
+struct CompressionFilter {
+    /// The coding the client would accept, if any.
+    coding: Option<ContentCoding>,
+    min_len: usize,
+    /// The encoder, once it has been decided to compress the response.
+    encoder: Option<Encoder>,
+    /// Whether any of the body has been written (a response to HEAD has none).
+    written: bool,
+}
+
+impl CompressionFilter {
+    fn new(coding: Option<ContentCoding>, min_len: usize) -> CompressionFilter {
+        CompressionFilter {
+            coding: coding,
+            min_len: min_len,
+            encoder: None,
+            written: false,
+        }
+    }
+}
+
+impl ResponseFilter for CompressionFilter {
+    fn filter_headers(&mut self, status: &mut Status, headers: &mut HeaderCollection) {
+        let compressible = match headers.content_type {
+            Some(ref media_type) => is_compressible(media_type),
+            None => false,
+        };
+        if !compressible || headers.content_encoding.is_some() {
+            return;
+        }
+        // Whether or not this one is compressed, the response depends on Accept-Encoding.
+        headers.vary = Some(match headers.vary.take() {
+            Some(ref vary) if vary.to_ascii_lowercase().contains("accept-encoding") => vary.clone(),
+            Some(vary) => format!("{}, Accept-Encoding", vary),
+            None => String::from_str("Accept-Encoding"),
+        });
+
+        let code = status.code();
+        let has_body = code >= 200 && code != 204 && code != 206 && code != 304;
+        let big_enough = headers.content_length.map_or(true, |len| len >= self.min_len);
+        let coding = match self.coding {
+            Some(coding) if has_body && big_enough && headers.content_range.is_none() => coding,
+            _ => return,
+        };
+        self.encoder = match Encoder::new(coding) {
+            Some(encoder) => Some(encoder),
+            None => return,
+        };
+        headers.content_encoding = Some(format!("{}", coding));
+        headers.content_length = None;
+        // The compressed representation is not byte-for-byte the same as the original one.
+        if let Some(ref mut etag) = headers.etag {
+            etag.weak = true;
+        }
+    }
+
+    fn filter_body(&mut self, data: &[u8], out: &mut Vec<u8>) -> IoResult<()> {
+        match self.encoder {
+            Some(ref mut encoder) => {
+                self.written = self.written || !data.is_empty();
+                encoder.encode(data, out)
+            },
+            None => {
+                out.push_all(data);
+                Ok(())
+            },
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> IoResult<()> {
+        let encoder = match self.encoder.take() {
+            Some(encoder) => encoder,
+            None => return Ok(()),
+        };
+        let mut rest = Vec::new();
+        try!(encoder.finish(&mut rest));
+        if self.written {
+            out.push_all(&rest[..]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::old_io::BufReader;
+    use flate2::reader::{GzDecoder, ZlibDecoder};
+    use headers::content_type::MediaType;
+    use headers::etag::strong_etag;
+    use headers::response::HeaderCollection;
+    use rfc2616::ContentCoding::{Gzip, Deflate};
+    use server::response::ResponseFilter;
+    use status::Status;
+    use super::{CompressionFilter, choose_coding};
+
+    #[test]
+    fn test_choose_coding() {
+        assert_eq!(choose_coding("gzip, deflate"), Some(Gzip));
+        assert_eq!(choose_coding("deflate"), Some(Deflate));
+        assert_eq!(choose_coding("gzip;q=0.5, deflate"), Some(Deflate));
+        assert_eq!(choose_coding("x-gzip"), Some(Gzip));
+        assert_eq!(choose_coding("*"), Some(Gzip));
+        assert_eq!(choose_coding("*;q=0.2, gzip;q=0"), Some(Deflate));
+        assert_eq!(choose_coding("identity"), None);
+        assert_eq!(choose_coding("gzip;q=0, deflate;q=0"), None);
+        assert_eq!(choose_coding(""), None);
+    }
+
+    fn text_headers(len: Option<usize>) -> HeaderCollection {
+        let mut headers = HeaderCollection::new();
+        headers.content_type = Some(MediaType::new(String::from_str("text"),
+                                                   String::from_str("plain"), Vec::new()));
+        headers.content_length = len;
+        headers
+    }
+
+    /// Run a body through a filter in pieces.
+    fn filter_all(filter: &mut CompressionFilter, body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for piece in body.chunks(100) {
+            filter.filter_body(piece, &mut out).unwrap();
+        }
+        filter.finish(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_gzip() {
+        let body: Vec<u8> = (0..5000).map(|i| b"abcdefgh"[i % 8]).collect();
+        let mut filter = CompressionFilter::new(Some(Gzip), 256);
+        let mut headers = text_headers(Some(body.len()));
+        headers.etag = Some(strong_etag(String::from_str("x")));
+        filter.filter_headers(&mut Status::Ok, &mut headers);
+        assert_eq!(headers.content_encoding, Some(String::from_str("gzip")));
+        assert_eq!(headers.vary, Some(String::from_str("Accept-Encoding")));
+        assert_eq!(headers.content_length, None);
+        assert!(headers.etag.as_ref().unwrap().weak);
+
+        let compressed = filter_all(&mut filter, &body[..]);
+        assert!(compressed.len() < body.len());
+        let mut decoder = GzDecoder::new(BufReader::new(&compressed[..])).unwrap();
+        assert_eq!(decoder.read_to_end().unwrap(), body);
+    }
+
+    #[test]
+    fn test_deflate() {
+        let body = b"Hello, World! Hello, World! Hello, World!";
+        let mut filter = CompressionFilter::new(Some(Deflate), 0);
+        let mut headers = text_headers(None);
+        headers.vary = Some(String::from_str("Cookie"));
+        filter.filter_headers(&mut Status::Ok, &mut headers);
+        assert_eq!(headers.content_encoding, Some(String::from_str("deflate")));
+        assert_eq!(headers.vary, Some(String::from_str("Cookie, Accept-Encoding")));
+        let compressed = filter_all(&mut filter, body);
+        let mut decoder = ZlibDecoder::new(BufReader::new(&compressed[..]));
+        assert_eq!(decoder.read_to_end().unwrap(), body.to_vec());
+    }
+
+    #[test]
+    fn test_skipped() {
+        // Too small.
+        let mut filter = CompressionFilter::new(Some(Gzip), 256);
+        let mut headers = text_headers(Some(10));
+        filter.filter_headers(&mut Status::Ok, &mut headers);
+        assert_eq!(headers.content_encoding, None);
+        assert_eq!(headers.content_length, Some(10));
+        assert_eq!(headers.vary, Some(String::from_str("Accept-Encoding")));
+        assert_eq!(filter_all(&mut filter, b"0123456789"), b"0123456789".to_vec());
+
+        // Already compressed.
+        let mut filter = CompressionFilter::new(Some(Gzip), 0);
+        let mut headers = HeaderCollection::new();
+        headers.content_type = Some(MediaType::new(String::from_str("image"),
+                                                   String::from_str("png"), Vec::new()));
+        filter.filter_headers(&mut Status::Ok, &mut headers);
+        assert_eq!(headers.content_encoding, None);
+        assert_eq!(headers.vary, None);
+
+        // No body.
+        let mut filter = CompressionFilter::new(Some(Gzip), 0);
+        let mut headers = text_headers(Some(1000));
+        filter.filter_headers(&mut Status::NotModified, &mut headers);
+        assert_eq!(headers.content_encoding, None);
+
+        // Not accepted.
+        let mut filter = CompressionFilter::new(None, 0);
+        let mut headers = text_headers(Some(1000));
+        filter.filter_headers(&mut Status::Ok, &mut headers);
+        assert_eq!(headers.content_encoding, None);
+        assert_eq!(headers.vary, Some(String::from_str("Accept-Encoding")));
+
+        // A response to HEAD has headers saying it would be compressed, but no body.
+        let mut filter = CompressionFilter::new(Some(Gzip), 0);
+        let mut headers = text_headers(None);
+        filter.filter_headers(&mut Status::Ok, &mut headers);
+        assert_eq!(headers.content_encoding, Some(String::from_str("gzip")));
+        assert_eq!(filter_all(&mut filter, b""), Vec::new());
+    }
+}
@@ -0,0 +1,184 @@
+//! Evaluating conditional requests (RFC 7232): If-Match, If-Unmodified-Since, If-None-Match,
+//! If-Modified-Since and If-Range.
+//!
+//! ```ignore
+//! match request.preconditions(Some(&etag), Some(&last_modified)) {
+//!     Precondition::Proceed => send_the_resource(w),
+//!     Precondition::NotModified => w.status = NotModified,
+//!     Precondition::Failed => w.status = PreconditionFailed,
+//! }
+//! ```
+
+use time::Tm;
+
+use headers::etag::{EntityTag, EntityTagMatch};
+use headers::request::HeaderCollection;
+use headers::typed::value_from_bytes;
+use method::Method;
+use method::Method::{Get, Head};
+use server::Request;
+
+/// The outcome of the preconditions of a request.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Precondition {
+    /// Carry on and handle the request as usual.
+    Proceed,
+    /// Answer `304 Not Modified` (only ever for GET and HEAD).
+    NotModified,
+    /// Answer `412 Precondition Failed`.
+    Failed,
+}
+
+/// Whether `modified` is no later than `since`, to the second (as HTTP dates go).
+fn not_modified_since(modified: &Tm, since: &Tm) -> bool {
+    modified.to_timespec().sec <= since.to_timespec().sec
+}
+
+/// Evaluate the preconditions of a request for a resource which exists and has the given current
+/// ETag and Last-Modified date (either may be unknown), in the order of RFC 7232 §6.
+pub fn evaluate(method: &Method, headers: &HeaderCollection, etag: Option<&EntityTag>,
+                last_modified: Option<&Tm>) -> Precondition {
+    // 1 and 2: If-Match, or else If-Unmodified-Since.
+    match (&headers.if_match, &headers.if_unmodified_since) {
+        (&Some(ref if_match), _) => {
+            let matched = match etag {
+                Some(etag) => if_match.matches_strong(etag),
+                None => if_match == &EntityTagMatch::Any,
+            };
+            if !matched {
+                return Precondition::Failed;
+            }
+        },
+        (&None, &Some(ref since)) => {
+            if let Some(modified) = last_modified {
+                if !not_modified_since(modified, since) {
+                    return Precondition::Failed;
+                }
+            }
+        },
+        (&None, &None) => (),
+    }
+
+    let safe = *method == Get || *method == Head;
+    // 3 and 4: If-None-Match, or else If-Modified-Since (only for GET and HEAD).
+    match (&headers.if_none_match, &headers.if_modified_since) {
+        (&Some(ref if_none_match), _) => {
+            let matched = match etag {
+                Some(etag) => if_none_match.matches_weak(etag),
+                None => if_none_match == &EntityTagMatch::Any,
+            };
+            if matched {
+                return if safe { Precondition::NotModified } else { Precondition::Failed };
+            }
+        },
+        (&None, &Some(ref since)) if safe => {
+            if let Some(modified) = last_modified {
+                if not_modified_since(modified, since) {
+                    return Precondition::NotModified;
+                }
+            }
+        },
+        _ => (),
+    }
+    Precondition::Proceed
+}
+
+/// Whether a Range header should be honoured given the If-Range header (RFC 7233 §3.2): it must
+/// be absent, or be a strong match for the current ETag, or be exactly the Last-Modified date.
+pub fn range_applies(headers: &HeaderCollection, etag: Option<&EntityTag>,
+                     last_modified: Option<&Tm>) -> bool {
+    let if_range = match headers.if_range {
+        Some(ref if_range) => if_range.trim(),
+        None => return true,
+    };
+    if if_range.starts_with("\"") || if_range.starts_with("W/") {
+        match (value_from_bytes::<EntityTag>(if_range.as_bytes()), etag) {
+            (Some(ref tag), Some(etag)) => tag.strong_eq(etag),
+            _ => false,
+        }
+    } else {
+        match (value_from_bytes::<Tm>(if_range.as_bytes()), last_modified) {
+            (Some(ref date), Some(modified)) =>
+                date.to_timespec().sec == modified.to_timespec().sec,
+            _ => false,
+        }
+    }
+}
+
+impl Request {
+    /// Evaluate the preconditions of the request; see `conditional::evaluate`.
+    pub fn preconditions(&self, etag: Option<&EntityTag>, last_modified: Option<&Tm>)
+            -> Precondition {
+        evaluate(&self.method, &self.headers, etag, last_modified)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time;
+    use time::{Tm, Timespec};
+    use headers::etag::{EntityTagMatch, strong_etag, weak_etag};
+    use headers::request::HeaderCollection;
+    use method::Method::{Get, Head, Put};
+    use super::{evaluate, range_applies};
+    use super::Precondition::{Proceed, NotModified, Failed};
+
+    fn at(sec: i64) -> Tm {
+        time::at_utc(Timespec::new(sec, 0))
+    }
+
+    #[test]
+    fn test_if_match() {
+        let etag = strong_etag(String::from_str("v2"));
+        let mut headers = HeaderCollection::new();
+        headers.if_match = Some(EntityTagMatch::Tags(vec!(strong_etag(String::from_str("v1")))));
+        assert_eq!(evaluate(&Put, &headers, Some(&etag), None), Failed);
+        headers.if_match = Some(EntityTagMatch::Tags(vec!(weak_etag(String::from_str("v2")))));
+        assert_eq!(evaluate(&Put, &headers, Some(&etag), None), Failed);
+        headers.if_match = Some(EntityTagMatch::Tags(vec!(strong_etag(String::from_str("v2")))));
+        assert_eq!(evaluate(&Put, &headers, Some(&etag), None), Proceed);
+        headers.if_match = Some(EntityTagMatch::Any);
+        assert_eq!(evaluate(&Put, &headers, None, None), Proceed);
+
+        // If-Unmodified-Since is ignored when there is If-Match.
+        headers.if_unmodified_since = Some(at(100));
+        assert_eq!(evaluate(&Put, &headers, Some(&etag), Some(&at(200))), Proceed);
+        headers.if_match = None;
+        assert_eq!(evaluate(&Put, &headers, Some(&etag), Some(&at(200))), Failed);
+        assert_eq!(evaluate(&Put, &headers, Some(&etag), Some(&at(100))), Proceed);
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = strong_etag(String::from_str("v2"));
+        let mut headers = HeaderCollection::new();
+        headers.if_none_match = Some(EntityTagMatch::Tags(vec!(weak_etag(String::from_str("v2")))));
+        assert_eq!(evaluate(&Get, &headers, Some(&etag), None), NotModified);
+        assert_eq!(evaluate(&Head, &headers, Some(&etag), None), NotModified);
+        assert_eq!(evaluate(&Put, &headers, Some(&etag), None), Failed);
+        headers.if_none_match = Some(EntityTagMatch::Tags(vec!(strong_etag(String::from_str("v1")))));
+        assert_eq!(evaluate(&Get, &headers, Some(&etag), None), Proceed);
+
+        // If-Modified-Since is ignored when there is If-None-Match.
+        headers.if_modified_since = Some(at(300));
+        assert_eq!(evaluate(&Get, &headers, Some(&etag), Some(&at(200))), Proceed);
+        headers.if_none_match = None;
+        assert_eq!(evaluate(&Get, &headers, Some(&etag), Some(&at(200))), NotModified);
+        assert_eq!(evaluate(&Get, &headers, Some(&etag), Some(&at(400))), Proceed);
+        assert_eq!(evaluate(&Put, &headers, Some(&etag), Some(&at(200))), Proceed);
+    }
+
+    #[test]
+    fn test_range_applies() {
+        let etag = strong_etag(String::from_str("v2"));
+        let mut headers = HeaderCollection::new();
+        assert!(range_applies(&headers, Some(&etag), None));
+        headers.if_range = Some(String::from_str("\"v2\""));
+        assert!(range_applies(&headers, Some(&etag), None));
+        headers.if_range = Some(String::from_str("W/\"v2\""));
+        assert!(!range_applies(&headers, Some(&etag), None));
+        headers.if_range = Some(String::from_str("Thu, 01 Jan 1970 00:01:40 GMT"));
+        assert!(range_applies(&headers, None, Some(&at(100))));
+        assert!(!range_applies(&headers, None, Some(&at(101))));
+    }
+}
@@ -0,0 +1,212 @@
+//! A minimal binding to Linux's epoll, for the event loop server.
+
+use libc::{c_int, c_void, size_t};
+use std::old_io::{IoResult, IoError};
+use std::os::unix::Fd;
+
+pub const EPOLLIN: u32 = 0x001;
+pub const EPOLLRDHUP: u32 = 0x2000;
+pub const EPOLLET: u32 = 1 << 31;
+
+const EPOLL_CTL_ADD: c_int = 1;
+const EPOLL_CTL_DEL: c_int = 2;
+const EPOLL_CLOEXEC: c_int = 0o2000000;
+
+/// The kernel's `struct epoll_event`, which is packed on x86-64.
+#[cfg(target_arch = "x86_64")]
+#[repr(C, packed)]
+#[derive(Copy)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+#[repr(C)]
+#[derive(Copy)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+extern {
+    fn epoll_create1(flags: c_int) -> c_int;
+    fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut EpollEvent) -> c_int;
+    fn epoll_wait(epfd: c_int, events: *mut EpollEvent, maxevents: c_int, timeout: c_int) -> c_int;
+}
+
+fn check(result: c_int) -> IoResult<c_int> {
+    if result < 0 {
+        Err(IoError::last_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// An epoll instance; file descriptors are registered with a token which is given back with
+/// their events.
+pub struct Epoll {
+    fd: c_int,
+}
+
+impl Epoll {
+    pub fn new() -> IoResult<Epoll> {
+        let fd = try!(check(unsafe { epoll_create1(EPOLL_CLOEXEC) }));
+        Ok(Epoll { fd: fd })
+    }
+
+    /// Watch `fd` for the given events (`EPOLLIN | EPOLLET` and the like).
+    pub fn add(&self, fd: Fd, events: u32, token: u64) -> IoResult<()> {
+        let mut event = EpollEvent { events: events, data: token };
+        try!(check(unsafe { epoll_ctl(self.fd, EPOLL_CTL_ADD, fd, &mut event) }));
+        Ok(())
+    }
+
+    /// Stop watching `fd`.
+    pub fn delete(&self, fd: Fd) -> IoResult<()> {
+        // Kernels before 2.6.9 require an event even though it is ignored.
+        let mut event = EpollEvent { events: 0, data: 0 };
+        try!(check(unsafe { epoll_ctl(self.fd, EPOLL_CTL_DEL, fd, &mut event) }));
+        Ok(())
+    }
+
+    /// Wait for events, filling `events` from the start and returning how many there were.
+    ///
+    /// A timeout of `None` waits indefinitely. Being interrupted by a signal counts as there
+    /// being no events.
+    pub fn wait(&self, events: &mut [EpollEvent], timeout_ms: Option<u32>) -> IoResult<usize> {
+        let timeout = timeout_ms.map(|t| t as c_int).unwrap_or(-1);
+        let result = unsafe {
+            epoll_wait(self.fd, events.as_mut_ptr(), events.len() as c_int, timeout)
+        };
+        if result < 0 && os_errno() == ::libc::EINTR {
+            return Ok(0);
+        }
+        Ok(try!(check(result)) as usize)
+    }
+}
+
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        unsafe { ::libc::close(self.fd); }
+    }
+}
+
+fn os_errno() -> c_int {
+    ::std::os::errno() as c_int
+}
+
+/// Switch a file descriptor between blocking and non-blocking mode.
+pub fn set_nonblocking(fd: Fd, nonblocking: bool) -> IoResult<()> {
+    let flags = try!(check(unsafe { ::libc::fcntl(fd, ::libc::F_GETFL) }));
+    let flags = if nonblocking {
+        flags | ::libc::O_NONBLOCK
+    } else {
+        flags & !::libc::O_NONBLOCK
+    };
+    try!(check(unsafe { ::libc::fcntl(fd, ::libc::F_SETFL, flags) }));
+    Ok(())
+}
+
+/// The outcome of reading from a non-blocking file descriptor.
+#[derive(PartialEq, Eq, Debug)]
+pub enum ReadOutcome {
+    /// So many bytes were read.
+    Read(usize),
+    /// Nothing is available yet.
+    WouldBlock,
+    /// The other end has closed the connection.
+    Closed,
+}
+
+/// Read from a non-blocking file descriptor.
+pub fn read_nonblocking(fd: Fd, buf: &mut [u8]) -> IoResult<ReadOutcome> {
+    loop {
+        let result = unsafe {
+            ::libc::read(fd, buf.as_mut_ptr() as *mut c_void, buf.len() as size_t)
+        };
+        if result > 0 {
+            return Ok(ReadOutcome::Read(result as usize));
+        } else if result == 0 {
+            return Ok(ReadOutcome::Closed);
+        }
+        match os_errno() {
+            ::libc::EINTR => continue,
+            e if e == ::libc::EAGAIN || e == ::libc::EWOULDBLOCK =>
+                return Ok(ReadOutcome::WouldBlock),
+            _ => return Err(IoError::last_error()),
+        }
+    }
+}
+
+/// A pipe whose reading end is watched by the event loop, so that other threads can wake it.
+pub struct Waker {
+    read_fd: c_int,
+    write_fd: c_int,
+}
+
+impl Waker {
+    pub fn new() -> IoResult<Waker> {
+        let mut fds = [0 as c_int; 2];
+        try!(check(unsafe { ::libc::pipe(fds.as_mut_ptr()) }));
+        try!(set_nonblocking(fds[0], true));
+        try!(set_nonblocking(fds[1], true));
+        Ok(Waker { read_fd: fds[0], write_fd: fds[1] })
+    }
+
+    /// The file descriptor to watch for readiness.
+    pub fn fd(&self) -> Fd {
+        self.read_fd
+    }
+
+    /// Make the watched end readable. If the pipe is full, it is already readable.
+    pub fn wake(&self) {
+        unsafe { ::libc::write(self.write_fd, b"\x01".as_ptr() as *const c_void, 1); }
+    }
+
+    /// Empty the pipe after it has been reported readable.
+    pub fn drain(&self) {
+        let mut buf = [0u8; 64];
+        loop {
+            match read_nonblocking(self.read_fd, &mut buf) {
+                Ok(ReadOutcome::Read(_)) => (),
+                _ => break,
+            }
+        }
+    }
+}
+
+impl Drop for Waker {
+    fn drop(&mut self) {
+        unsafe {
+            ::libc::close(self.read_fd);
+            ::libc::close(self.write_fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Epoll, EpollEvent, Waker, EPOLLIN, EPOLLET};
+
+    #[test]
+    fn test_wake_edge_triggered() {
+        let epoll = Epoll::new().unwrap();
+        let waker = Waker::new().unwrap();
+        epoll.add(waker.fd(), EPOLLIN | EPOLLET, 7).unwrap();
+        let mut events = [EpollEvent { events: 0, data: 0 }; 4];
+        assert_eq!(epoll.wait(&mut events, Some(0)).unwrap(), 0);
+
+        waker.wake();
+        waker.wake();
+        assert_eq!(epoll.wait(&mut events, Some(1000)).unwrap(), 1);
+        let token = events[0].data;
+        assert_eq!(token, 7);
+
+        // Edge-triggered: nothing more is reported until something new is written.
+        assert_eq!(epoll.wait(&mut events, Some(0)).unwrap(), 0);
+        waker.drain();
+        waker.wake();
+        assert_eq!(epoll.wait(&mut events, Some(1000)).unwrap(), 1);
+    }
+}
@@ -0,0 +1,427 @@
+//! Serving many connections from one event loop thread, for `Server::serve_event_loop`.
+//!
+//! The loop watches every connection with edge-triggered epoll and reads what arrives into a
+//! `RequestLoader`, so an idle keep-alive connection costs no more than its buffer. Once a request
+//! has been read in full, the connection is handed to a pool of worker threads, where the handler
+//! runs and the response is written in the usual blocking fashion; if the connection is to be kept
+//! alive, it then goes back to the loop for the next request. Pipelined requests which have already
+//! arrived in full are answered by the same worker first (up to `Config::pipeline_depth` of them).
+
+use std::collections::HashMap;
+use std::old_io::{Listener, Acceptor, IoResult};
+use std::old_io::net::tcp::{TcpListener, TcpAcceptor, TcpStream};
+use std::os::unix::{AsRawFd, Fd};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::thread::Thread;
+
+use buffer::BufferedStream;
+use status;
+use server::{Server, Config, Request, ResponseWriter};
+use server::request::RequestLoader;
+use server::epoll::{Epoll, EpollEvent, Waker, ReadOutcome, EPOLLIN, EPOLLRDHUP, EPOLLET};
+use server::epoll::{set_nonblocking, read_nonblocking};
+
+/// The epoll token of the `Waker`; the tokens of connections are their file descriptors.
+const WAKER_TOKEN: u64 = !0;
+
+/// The most that is buffered of a request before it is refused as too large; the same as the read
+/// buffer of a blocking connection.
+const MAX_BUFFERED: usize = 0x10000;
+
+const READ_SIZE: usize = 0x4000;
+
+const MAX_EVENTS: usize = 256;
+
+/// A connection on its way to the event loop, either newly accepted or back from a worker.
+struct Incoming {
+    stream: TcpStream,
+    /// Anything already received after the previous request (a pipelined request).
+    leftover: Vec<u8>,
+}
+
+/// A connection which the event loop is reading a request from.
+struct Connection {
+    stream: TcpStream,
+    /// Received bytes which the loader has not consumed yet.
+    buffer: Vec<u8>,
+    loader: RequestLoader,
+}
+
+/// A request read in full, for a worker to answer.
+struct Job {
+    stream: TcpStream,
+    leftover: Vec<u8>,
+    request: Request,
+    result: Result<(), status::Status>,
+}
+
+enum Progress {
+    /// More of the request is needed.
+    Waiting,
+    /// The request is complete, or is to be answered with an error status.
+    Ready(Result<(), status::Status>),
+    /// The connection was closed or failed.
+    Closed,
+}
+
+impl Connection {
+    fn new(mut incoming: Incoming) -> Connection {
+        let remote_addr = incoming.stream.peer_name().ok();
+        Connection {
+            stream: incoming.stream,
+            buffer: incoming.leftover,
+            loader: RequestLoader::new(remote_addr),
+        }
+    }
+
+    /// Pass everything buffered to the loader.
+    fn advance(&mut self, config: &Config) -> Progress {
+        let (consumed, result) = self.loader.feed(&self.buffer[..], config);
+        if consumed > 0 {
+            self.buffer = self.buffer[consumed..].to_vec();
+        }
+        if result == Ok(false) && self.loader.take_continue() {
+            // The interim response is short enough to go out in one write even when not blocking.
+            if self.stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").is_err() {
+                return Progress::Closed;
+            }
+        }
+        match result {
+            Ok(true) => Progress::Ready(Ok(())),
+            Ok(false) if self.buffer.len() >= MAX_BUFFERED =>
+                Progress::Ready(Err(self.loader.too_large_status())),
+            Ok(false) => Progress::Waiting,
+            Err(status) => Progress::Ready(Err(status)),
+        }
+    }
+
+    /// Read everything available (edge-triggered readiness is only reported once), stopping early
+    /// if the request is complete.
+    fn read_available(&mut self, config: &Config) -> Progress {
+        let fd = self.stream.as_raw_fd();
+        let mut buf = [0u8; READ_SIZE];
+        loop {
+            match self.advance(config) {
+                Progress::Waiting => (),
+                progress => return progress,
+            }
+            match read_nonblocking(fd, &mut buf) {
+                Ok(ReadOutcome::Read(amount)) => self.buffer.push_all(&buf[..amount]),
+                Ok(ReadOutcome::WouldBlock) => return Progress::Waiting,
+                Ok(ReadOutcome::Closed) | Err(_) => return Progress::Closed,
+            }
+        }
+    }
+}
+
+/// Serve forever from an event loop; see `Server::serve_event_loop`.
+pub fn serve<T: Server>(server: T) {
+    let config = server.get_config();
+    debug!("About to bind to {}", config.bind_address);
+    match TcpListener::bind(config.bind_address).listen() {
+        Err(err) => error!("bind or listen failed :-(: {}", err),
+        Ok(acceptor) => serve_from(server, acceptor),
+    }
+}
+
+/// Serve forever from an event loop the connections accepted from `acceptor`.
+fn serve_from<T: Server>(server: T, mut acceptor: TcpAcceptor) {
+    let config = server.get_config();
+    let (epoll, waker) = match (Epoll::new(), Waker::new()) {
+        (Ok(epoll), Ok(waker)) => (epoll, Arc::new(waker)),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("setting up the event loop failed: {}", err);
+            return;
+        },
+    };
+    if let Err(err) = epoll.add(waker.fd(), EPOLLIN | EPOLLET, WAKER_TOKEN) {
+        error!("setting up the event loop failed: {}", err);
+        return;
+    }
+    debug!("listening");
+
+    let (incoming_sender, incoming_receiver) = channel();
+
+    // Accepting is left to a thread of its own, which passes new connections to the loop.
+    let accept_sender = incoming_sender.clone();
+    let accept_waker = waker.clone();
+    Thread::spawn(move || {
+        loop {
+            match acceptor.accept() {
+                Err(error) => {
+                    // As in `serve_forever`, some accept failures ought to be fatal. TODO.
+                    debug!("accept failed: {}", error);
+                },
+                Ok(stream) => {
+                    debug!("accepted connection");
+                    if accept_sender.send(Incoming { stream: stream, leftover: Vec::new() })
+                            .is_err() {
+                        return;
+                    }
+                    accept_waker.wake();
+                },
+            }
+        }
+    });
+
+    let (job_sender, job_receiver) = channel();
+    let job_receiver = Arc::new(Mutex::new(job_receiver));
+    for _ in 0..config.worker_threads {
+        let worker_server = server.clone();
+        let worker_jobs = job_receiver.clone();
+        let worker_sender = incoming_sender.clone();
+        let worker_waker = waker.clone();
+        Thread::spawn(move || {
+            work(worker_server, worker_jobs, worker_sender, worker_waker);
+        });
+    }
+
+    run_loop(&config, &epoll, &*waker, incoming_receiver, job_sender);
+}
+
+fn run_loop(config: &Config, epoll: &Epoll, waker: &Waker, incoming: Receiver<Incoming>,
+            jobs: Sender<Job>) {
+    let mut connections: HashMap<Fd, Connection> = HashMap::new();
+    let mut events = [EpollEvent { events: 0, data: 0 }; MAX_EVENTS];
+    loop {
+        let count = match epoll.wait(&mut events, None) {
+            Ok(count) => count,
+            Err(err) => {
+                error!("waiting for events failed: {}", err);
+                return;
+            },
+        };
+        for event in events[..count].iter() {
+            let token = event.data;
+            let fds = if token == WAKER_TOKEN {
+                waker.drain();
+                let mut fds = Vec::new();
+                while let Ok(incoming) = incoming.try_recv() {
+                    let connection = Connection::new(incoming);
+                    let fd = connection.stream.as_raw_fd();
+                    // A connection added when it is already readable is reported at once.
+                    let registered = set_nonblocking(fd, true).and_then(|()| {
+                        epoll.add(fd, EPOLLIN | EPOLLRDHUP | EPOLLET, fd as u64)
+                    });
+                    match registered {
+                        Ok(()) => {
+                            connections.insert(fd, connection);
+                            // There may be a whole pipelined request in its buffer already.
+                            fds.push(fd);
+                        },
+                        Err(err) => debug!("registering a connection failed: {}", err),
+                    }
+                }
+                fds
+            } else {
+                vec!(token as Fd)
+            };
+
+            for &fd in fds.iter() {
+                let progress = match connections.get_mut(&fd) {
+                    Some(connection) => connection.read_available(config),
+                    None => continue,
+                };
+                match progress {
+                    Progress::Waiting => (),
+                    Progress::Closed => {
+                        let _ = epoll.delete(fd);
+                        connections.remove(&fd);
+                    },
+                    Progress::Ready(result) => {
+                        let _ = epoll.delete(fd);
+                        let Connection { stream, buffer, loader } = connections.remove(&fd).unwrap();
+                        let job = Job {
+                            stream: stream,
+                            leftover: buffer,
+                            request: loader.into_request(),
+                            result: result,
+                        };
+                        if jobs.send(job).is_err() {
+                            error!("all the workers have gone");
+                            return;
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// Answer requests until the loop goes away, handing back connections which are kept alive.
+fn work<T: Server>(server: T, jobs: Arc<Mutex<Receiver<Job>>>, incoming: Sender<Incoming>,
+                   waker: Arc<Waker>) {
+    let config = server.get_config();
+    loop {
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        let Job { stream, leftover, request, result } = job;
+        if let Err(err) = set_nonblocking(stream.as_raw_fd(), false) {
+            error!("switching to blocking mode failed: {}", err);
+            continue;
+        }
+        let remote_addr = request.remote_addr;
+        let mut stream = BufferedStream::new(stream);
+        // Whatever came after the request is read from the stream's buffer as usual from now on.
+        stream.unread(&leftover[..]);
+        let (mut request, mut result) = (request, result);
+        let mut answered = 0;
+        let mut keep_alive = false;
+        loop {
+            // A request which could not be read properly may have left the rest of the connection
+            // in an unknown state.
+            let close_connection = request.close_connection || result.is_err();
+            let version = request.version;
+            let method = request.method.clone();
+            let close_connection = {
+                let mut response = ResponseWriter::new(&mut stream);
+                response.request_version = version;
+                response.request_method = method;
+                response.close_connection = close_connection;
+                if let Err(err) = respond(&server, request, result, &mut response) {
+                    error!("writing response failed: {}", err);
+                    break;  // Presumably bad connection, so give up.
+                }
+                response.close_connection
+            };
+            answered += 1;
+            if close_connection {
+                break;
+            }
+            // A pipelined request which has already arrived in full is answered straight away
+            // (its response going out with this one), up to the pipeline depth.
+            if answered < config.pipeline_depth {
+                let mut loader = RequestLoader::new(remote_addr);
+                let (consumed, next) = loader.feed(stream.buffered(), &config);
+                if next != Ok(false) {
+                    stream.consume(consumed);
+                    request = loader.into_request();
+                    result = next.map(|_| ());
+                    continue;
+                }
+            }
+            keep_alive = true;
+            break;
+        }
+        if let Err(err) = stream.flush() {
+            error!("writing response failed: {}", err);
+            continue;
+        }
+        if !keep_alive {
+            continue;
+        }
+        let leftover = stream.buffered().to_vec();
+        if incoming.send(Incoming { stream: stream.wrapped, leftover: leftover }).is_err() {
+            return;
+        }
+        waker.wake();
+    }
+}
+
+fn respond<T: Server>(server: &T, request: Request, result: Result<(), status::Status>,
+                      response: &mut ResponseWriter) -> IoResult<()> {
+    match result {
+        Ok(()) => {
+            server.handle_request(request, response);
+            // Ensure that we actually do send a response:
+            try!(response.try_write_headers());
+        },
+        Err(status) => {
+            // As in `serve_forever`, a request which the server cannot cope with gets a response
+            // with no body.
+            response.status = status;
+            response.headers.content_length = Some(0);
+            try!(response.write_headers());
+        },
+    }
+    // Ensure any Transfer-Encoding is completed, etc.; the caller flushes the response.
+    response.end_response()
+}
+
+#[cfg(test)]
+mod test {
+    use std::old_io::Listener;
+    use std::old_io::net::ip::SocketAddr;
+    use std::old_io::net::tcp::{TcpListener, TcpStream};
+    use std::old_io::timer::sleep;
+    use std::thread::Thread;
+    use std::time::Duration;
+    use server::{Server, Config, Request, ResponseWriter};
+    use server::request::RequestUri::AbsolutePath;
+    use super::serve_from;
+
+    /// Answers with the method, path and body of the request.
+    #[derive(Clone)]
+    struct Echo {
+        addr: SocketAddr,
+    }
+
+    impl Server for Echo {
+        fn handle_request(&self, request: Request, w: &mut ResponseWriter) {
+            let path = match request.request_uri {
+                AbsolutePath(ref path) => path.path.clone(),
+                _ => String::new(),
+            };
+            let body = format!("[{} {} {}]", request.method, path,
+                               String::from_utf8_lossy(&request.body[..]));
+            w.headers.content_length = Some(body.len());
+            w.write_all(body.as_bytes()).unwrap();
+        }
+
+        fn get_config(&self) -> Config {
+            let mut config = Config::new(self.addr);
+            config.worker_threads = 2;
+            config.pipeline_depth = 2;
+            config
+        }
+    }
+
+    /// Write each piece of a request separately, pausing so that the loop sees them one by one.
+    fn send_in_pieces(stream: &mut TcpStream, pieces: &[&str]) {
+        for piece in pieces.iter() {
+            stream.write_all(piece.as_bytes()).unwrap();
+            stream.flush().unwrap();
+            sleep(Duration::milliseconds(20));
+        }
+    }
+
+    #[test]
+    fn test_event_loop() {
+        let mut listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.socket_name().unwrap();
+        let acceptor = listener.listen().unwrap();
+        Thread::spawn(move || serve_from(Echo { addr: addr }, acceptor));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        send_in_pieces(&mut stream, &["GET /a HT", "TP/1.1\r\nHo", "st: x\r\n", "\r\n"]);
+
+        // The interim response is written by the loop, before the body is read.
+        send_in_pieces(&mut stream, &["PUT /b HTTP/1.1\r\nHost: x\r\nContent-Length: 6\r\n",
+                                      "Expect: 100-continue\r\n\r\n"]);
+        let mut received = Vec::new();
+        while !received.ends_with(b"HTTP/1.1 100 Continue\r\n\r\n") {
+            received.push(stream.read_byte().unwrap());
+        }
+        send_in_pieces(&mut stream, &["abc", "def"]);
+
+        // Two requests at once: the worker answers the second from what it was handed, and then,
+        // at the pipeline depth, hands the start of the third back to the loop to be finished.
+        send_in_pieces(&mut stream, &["GET /c HTTP/1.1\r\nHost: x\r\n\r\n\
+                                       GET /d HTTP/1.1\r\nHost: x\r\n\r\nGET /e HTTP/1.1\r\n",
+                                      "Host: x\r\nConnection: close\r\n\r\n"]);
+        received.push_all(&stream.read_to_end().unwrap()[..]);
+        let responses = String::from_utf8(received).unwrap();
+
+        let positions: Vec<usize> = ["[GET /a ]", "100 Continue", "[PUT /b abcdef]", "[GET /c ]",
+                                     "[GET /d ]", "[GET /e ]"].iter()
+            .map(|body| responses.find(*body).expect(*body))
+            .collect();
+        for pair in positions.windows(2) {
+            assert!(pair[0] < pair[1]);
+        }
+        assert_eq!(responses.match_indices("HTTP/1.1 200 OK\r\n").count(), 5);
+    }
+}
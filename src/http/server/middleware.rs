@@ -0,0 +1,82 @@
+//! Layers of request handling which wrap a `Server`, for such things as logging, authentication,
+//! CORS and compression which would otherwise be repeated in every handler.
+//!
+//! ```ignore
+//! let server = Stack::new(router)
+//!     .layer(log_requests)
+//!     .layer(RequireAuth::new(users));
+//! server.serve_forever();
+//! ```
+//!
+//! Each layer is given the request, the response and the rest of the stack (`next`), and may:
+//!
+//! - inspect or modify the request before passing it on with `next(request, response)`;
+//! - answer the request itself, by not calling `next`;
+//! - add a `ResponseFilter` to the response before calling `next`, to transform the headers and
+//!   body which the inner layers write;
+//! - do something after `next` returns, such as logging how long the request took.
+//!
+//! Functions and closures of the right type are middleware already.
+
+use std::sync::Arc;
+
+use server::{Server, Config, Request, ResponseWriter};
+
+/// The rest of a stack, from the point of view of a layer.
+pub type Next<'a> = &'a (Fn(Request, &mut ResponseWriter) + 'a);
+
+/// A layer of a `Stack`.
+pub trait Middleware: Send + Sync + 'static {
+    /// Handle a request, calling `next` to pass it on to the inner layers and the server.
+    fn handle(&self, request: Request, response: &mut ResponseWriter, next: Next);
+}
+
+impl<F> Middleware for F
+        where F: Fn(Request, &mut ResponseWriter, Next) + Send + Sync + 'static {
+    fn handle(&self, request: Request, response: &mut ResponseWriter, next: Next) {
+        (*self)(request, response, next)
+    }
+}
+
+/// A `Server` with layers of middleware around it.
+///
+/// The first layer added is the outermost: it sees the request first and the response last.
+#[derive(Clone)]
+pub struct Stack<S> {
+    server: S,
+    layers: Vec<Arc<Box<Middleware + Send + Sync>>>,
+}
+
+impl<S: Server> Stack<S> {
+    pub fn new(server: S) -> Stack<S> {
+        Stack {
+            server: server,
+            layers: Vec::new(),
+        }
+    }
+
+    /// Add a layer inside those already added.
+    pub fn layer<M: Middleware>(mut self, middleware: M) -> Stack<S> {
+        self.layers.push(Arc::new(Box::new(middleware) as Box<Middleware + Send + Sync>));
+        self
+    }
+
+    /// Handle the request with the layers from `index` inwards.
+    fn run(&self, index: usize, request: Request, response: &mut ResponseWriter) {
+        match self.layers.get(index) {
+            Some(layer) => layer.handle(request, response,
+                                        &|request, response| self.run(index + 1, request, response)),
+            None => self.server.handle_request(request, response),
+        }
+    }
+}
+
+impl<S: Server> Server for Stack<S> {
+    fn get_config(&self) -> Config {
+        self.server.get_config()
+    }
+
+    fn handle_request(&self, request: Request, response: &mut ResponseWriter) {
+        self.run(0, request, response)
+    }
+}
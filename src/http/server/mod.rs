@@ -2,17 +2,33 @@ use std::old_io::{Listener, Acceptor, IoResult};
 use std::old_io::net::ip::SocketAddr;
 use time::precise_time_ns;
 use std::thread::Thread;
-use std::sync::mpsc::{channel, Receiver};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::sync::Arc;
 
-use std::old_io::net::tcp::TcpListener;
+use std::old_io::net::tcp::{TcpListener, TcpStream};
 
 use buffer::BufferedStream;
+use headers::typed::HeaderRegistry;
+use status;
 
-pub use self::request::{RequestBuffer, Request};
-pub use self::response::ResponseWriter;
+pub use self::request::Request;
+pub use self::response::{ResponseWriter, Upgraded};
 
 pub mod request;
 pub mod response;
+pub mod middleware;
+pub mod compression;
+pub mod conditional;
+pub mod router;
+pub mod static_files;
+#[cfg(target_os = "linux")]
+mod epoll;
+#[cfg(target_os = "linux")]
+mod event_loop;
+
+/// Decides, from the head of a request with `Expect: 100-continue`, whether its body is wanted;
+/// see `Config::continue_hook`.
+pub type ContinueHook = Box<Fn(&Request) -> Result<(), status::Status> + Send + Sync>;
 
 pub trait Server: Send + 'static + Clone {
 	fn handle_request(&self, request: Request, response: &mut ResponseWriter) -> ();
@@ -56,72 +72,30 @@ pub trait Server: Send + 'static + Clone {
             };
             let child_perf_sender = perf_sender.clone();
             let child_self = self.clone();
+            let child_config = config.clone();
             Thread::spawn(move || {
-                let mut time_start = time_start;
-                let mut stream = BufferedStream::new(stream);
-                debug!("accepted connection");
-                let mut first = true;
-                loop {  // A keep-alive loop, condition at end
-                    let mut time_spawned = precise_time_ns();
-                    let (request, err_status) = Request::load(&mut stream);
-                    let close_connection = request.close_connection;
-                    let time_request_made = precise_time_ns();
-                    if !first {
-                        // Subsequent requests on this connection have no spawn time.
-                        // Moreover we cannot detect the time spent parsing the request as we have
-                        // not exposed the time when the first byte was received.
-                        time_start = time_request_made;
-                        time_spawned = time_request_made;
-                    }
-                    let mut response = ResponseWriter::new(&mut stream);
-                    let time_response_made = precise_time_ns();
-                    match err_status {
-                        Ok(()) => {
-                            child_self.handle_request(request, &mut response);
-                            // Ensure that we actually do send a response:
-                            match response.try_write_headers() {
-                                Err(err) => {
-                                    error!("Writing headers failed: {}", err);
-                                    return;  // Presumably bad connection, so give up.
-                                },
-                                Ok(_) => (),
-                            }
-                        },
-                        Err(status) => {
-                            // Uh oh, it's a response that I as a server cannot cope with.
-                            // No good user-agent should have caused this, so for the moment
-                            // at least I am content to send no body in the response.
-                            response.status = status;
-                            response.headers.content_length = Some(0);
-                            match response.write_headers() {
-                                Err(err) => {
-                                    error!("Writing headers failed: {}", err);
-                                    return;  // Presumably bad connection, so give up.
-                                },
-                                Ok(_) => (),
-                            }
-                        },
-                    }
-                    // Ensure the request is flushed, any Transfer-Encoding completed, etc.
-                    match response.finish_response() {
-                        Err(err) => {
-                            error!("finishing response failed: {}", err);
-                            return;  // Presumably bad connection, so give up.
-                        },
-                        Ok(_) => (),
-                    }
-                    let time_finished = precise_time_ns();
-                    child_perf_sender.send((time_start, time_spawned, time_request_made, time_response_made, time_finished)).unwrap();
-
-                    if close_connection {
-                        break;
-                    }
-                    first = false;
-                }
+                serve_connection(&child_self, stream, &child_config, time_start,
+                                 &child_perf_sender);
             });
         }
     }
 
+    /**
+     * Attempt to bind to the address and port and serve forever from an event loop.
+     *
+     * Rather than a thread for each connection, this uses one thread which waits on all of the
+     * connections at once (with epoll) and reads requests as their bytes arrive. Each complete
+     * request is passed to one of `config.worker_threads` threads, where `handle_request` is
+     * called as with `serve_forever`. Connections which are idle between requests thus cost no
+     * thread, which suits holding very many keep-alive connections.
+     *
+     * This will only return if the initial connection fails or something else blows up.
+     */
+    #[cfg(target_os = "linux")]
+    fn serve_event_loop(self) {
+        event_loop::serve(self)
+    }
+
     /**
      * Attempt to bind to the address and port and serve for only one request.
      *
@@ -159,8 +133,12 @@ pub trait Server: Send + 'static + Clone {
 
             let mut stream = BufferedStream::new(stream);
             debug!("accepted connection");
-            let (request, err_status) = Request::load(&mut stream);
+            let (request, err_status) = Request::load(&mut stream, &config);
             let mut response = ResponseWriter::new(&mut stream);
+            response.request_version = request.version;
+            response.request_method = request.method.clone();
+            // Only the one request is answered.
+            response.close_connection = true;
             match err_status {
                 Ok(()) => {
                     self.handle_request(request, &mut response);
@@ -186,13 +164,181 @@ pub trait Server: Send + 'static + Clone {
     }
 }
 
+/// Answer the requests on a connection one after another until it is to be closed.
+///
+/// Pipelined requests (sent before the responses to earlier ones have been received) are answered
+/// in order like any others. While the next request is already buffered, up to
+/// `config.pipeline_depth` responses are held back to be written out together.
+fn serve_connection<T: Server>(server: &T, stream: TcpStream, config: &Config, time_start: u64,
+                               perf_sender: &Sender<(u64, u64, u64, u64, u64)>) {
+    let mut time_start = time_start;
+    let mut stream = BufferedStream::new(stream);
+    debug!("accepted connection");
+    let mut first = true;
+    // The number of responses written but not yet flushed.
+    let mut held = 0;
+    loop {  // A keep-alive loop, condition at end
+        let mut time_spawned = precise_time_ns();
+        // This flushes any held responses before it waits for more of the request.
+        let (request, err_status) = Request::load(&mut stream, config);
+        // After a request which could not be read, where the next one starts is not known.
+        let close_connection = request.close_connection || err_status.is_err();
+        let version = request.version;
+        let method = request.method.clone();
+        let time_request_made = precise_time_ns();
+        if !first {
+            // Subsequent requests on this connection have no spawn time.
+            // Moreover we cannot detect the time spent parsing the request as we have
+            // not exposed the time when the first byte was received.
+            time_start = time_request_made;
+            time_spawned = time_request_made;
+        }
+        let (close_connection, time_response_made) = {
+            let mut response = ResponseWriter::new(&mut stream);
+            response.request_version = version;
+            response.request_method = method;
+            response.close_connection = close_connection;
+            let time_response_made = precise_time_ns();
+            match err_status {
+                Ok(()) => {
+                    server.handle_request(request, &mut response);
+                    // Ensure that we actually do send a response:
+                    match response.try_write_headers() {
+                        Err(err) => {
+                            error!("Writing headers failed: {}", err);
+                            return;  // Presumably bad connection, so give up.
+                        },
+                        Ok(_) => (),
+                    }
+                },
+                Err(status) => {
+                    // Uh oh, it's a response that I as a server cannot cope with.
+                    // No good user-agent should have caused this, so for the moment
+                    // at least I am content to send no body in the response.
+                    response.status = status;
+                    response.headers.content_length = Some(0);
+                    match response.write_headers() {
+                        Err(err) => {
+                            error!("Writing headers failed: {}", err);
+                            return;  // Presumably bad connection, so give up.
+                        },
+                        Ok(_) => (),
+                    }
+                },
+            }
+            // Ensure any Transfer-Encoding is completed, etc.
+            match response.end_response() {
+                Err(err) => {
+                    error!("finishing response failed: {}", err);
+                    return;  // Presumably bad connection, so give up.
+                },
+                Ok(_) => (),
+            }
+            (response.close_connection, time_response_made)
+        };
+
+        // Hold the response back only if there is another request to answer straight away.
+        held += 1;
+        if close_connection || held >= config.pipeline_depth || stream.buffered().is_empty() {
+            match stream.flush() {
+                Err(err) => {
+                    error!("flushing response failed: {}", err);
+                    return;  // Presumably bad connection, so give up.
+                },
+                Ok(_) => (),
+            }
+            held = 0;
+        }
+        let time_finished = precise_time_ns();
+        let _ = perf_sender.send((time_start, time_spawned, time_request_made, time_response_made,
+                                  time_finished));
+
+        if close_connection {
+            break;
+        }
+        first = false;
+    }
+}
+
+/// The default `Config::pipeline_depth`.
+pub const DEFAULT_PIPELINE_DEPTH: usize = 16;
+
 /// The necessary configuration for an HTTP server.
 ///
-/// At present, only the IP address and port to bind to are needed, but it's possible that other
-/// options may turn up later.
-#[derive(Copy)]
+/// Only the IP address and port to bind to are required; start with `Config::new` and change any
+/// other options from there.
+#[derive(Clone)]
 pub struct Config {
 	pub bind_address: SocketAddr,
+
+	/// User-defined headers to check in requests as they are received.
+	pub header_registry: Arc<HeaderRegistry>,
+
+	/// The number of threads which run `handle_request` when serving from an event loop.
+	pub worker_threads: usize,
+
+	/// The longest request-target accepted; longer ones get `414 Request-URI Too Long`.
+	///
+	/// The whole request head is read into a 64 KiB buffer, so a request line which does not fit
+	/// is refused whatever this allows; raising it towards that ceiling gains nothing.
+	pub max_request_uri_len: usize,
+
+	/// The longest method name accepted; longer ones get `400 Bad Request`.
+	pub max_method_len: usize,
+
+	/// The longest header field line accepted, name and value together.
+	pub max_header_len: usize,
+
+	/// The most header fields accepted in a request.
+	pub max_header_count: usize,
+
+	/// The most bytes of header fields accepted in a request.
+	///
+	/// Requests exceeding any of the header limits get `431 Request Header Fields Too Large`.
+	pub max_headers_len: usize,
+
+	/// The longest request body accepted, as it is sent; longer ones get `413 Request Entity Too
+	/// Large`. The whole body is read into memory before the request is handled.
+	pub max_body_len: usize,
+
+	/// The longest a body sent with a gzip or deflate transfer-coding may be once decoded; longer
+	/// ones get `413 Request Entity Too Large`. Decoding stops as soon as this is passed.
+	pub max_decoded_body_len: usize,
+
+	/// The most responses to pipelined requests which are held back to be written out together;
+	/// 1 writes each response out as soon as it is complete. Pipelined requests are answered in
+	/// order regardless.
+	///
+	/// When serving from an event loop, this is also the most requests a worker answers in a row
+	/// from what a connection has already sent, before the connection goes back to the loop.
+	pub pipeline_depth: usize,
+
+	/// Looks at the head of a request with `Expect: 100-continue` before its body is read (the
+	/// request's body is still empty). `Ok(())` sends `100 Continue` so that the client sends the
+	/// body; `Err(status)` answers at once with that final status, such as `417 Expectation Failed`
+	/// or `413 Request Entity Too Large`, and closes the connection. Without a hook, `100 Continue`
+	/// is always sent.
+	pub continue_hook: Option<Arc<ContinueHook>>,
+}
+
+impl Config {
+	/// The default configuration for serving on the given address.
+	pub fn new(bind_address: SocketAddr) -> Config {
+		Config {
+			bind_address: bind_address,
+			header_registry: Arc::new(HeaderRegistry::new()),
+			worker_threads: 4,
+			max_request_uri_len: request::DEFAULT_MAX_REQUEST_URI_LEN,
+			max_method_len: request::MAX_METHOD_LEN,
+			max_header_len: request::DEFAULT_MAX_HEADER_LEN,
+			max_header_count: request::DEFAULT_MAX_HEADER_COUNT,
+			max_headers_len: request::DEFAULT_MAX_HEADERS_LEN,
+			max_body_len: request::DEFAULT_MAX_BODY_LEN,
+			max_decoded_body_len: request::DEFAULT_MAX_DECODED_BODY_LEN,
+			continue_hook: None,
+			pipeline_depth: DEFAULT_PIPELINE_DEPTH,
+		}
+	}
 }
 
 const PERF_DUMP_FREQUENCY : u64 = 10_000;
@@ -235,3 +381,89 @@ fn perf_dumper(perf_receiver: Receiver<(u64, u64, u64, u64, u64)>) {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::old_io::{Listener, Acceptor};
+    use std::old_io::net::tcp::{TcpListener, TcpStream};
+    use std::sync::mpsc::channel;
+    use std::thread::Thread;
+    use time::precise_time_ns;
+    use server::{Server, Config, Request, ResponseWriter, serve_connection};
+    use server::request::RequestUri::AbsolutePath;
+
+    /// Answers with the method, path and body of the request; "/chunked" is answered without a
+    /// Content-Length.
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Server for Echo {
+        fn handle_request(&self, request: Request, w: &mut ResponseWriter) {
+            let path = match request.request_uri {
+                AbsolutePath(ref path) => path.path.clone(),
+                _ => String::new(),
+            };
+            let body = format!("[{} {} {}]", request.method, path,
+                               String::from_utf8_lossy(&request.body[..]));
+            if &path[..] != "/chunked" {
+                w.headers.content_length = Some(body.len());
+            }
+            w.write_all(body.as_bytes()).unwrap();
+        }
+
+        fn get_config(&self) -> Config {
+            unreachable!()
+        }
+    }
+
+    /// Send everything in one go on a fresh connection to `serve_connection`, and read everything
+    /// that comes back until the server closes the connection.
+    fn exchange(pipeline_depth: usize, requests: &[u8]) -> String {
+        let mut listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.socket_name().unwrap();
+        let mut acceptor = listener.listen().unwrap();
+        Thread::spawn(move || {
+            let stream = acceptor.accept().unwrap();
+            let mut config = Config::new(addr);
+            config.pipeline_depth = pipeline_depth;
+            let (perf_sender, _) = channel();
+            serve_connection(&Echo, stream, &config, precise_time_ns(), &perf_sender);
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(requests).unwrap();
+        String::from_utf8(stream.read_to_end().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_pipelining() {
+        let requests = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\n\
+                         POST /b HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\nxyz\
+                         GET /chunked HTTP/1.1\r\nHost: x\r\n\r\n\
+                         POST /c HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+                         2\r\nhi\r\n0\r\n\r\n\
+                         GET /d HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n";
+        for &depth in [1, 2, 16].iter() {
+            let responses = exchange(depth, requests);
+            assert!(responses.starts_with("HTTP/1.1 200 OK\r\n"));
+            let positions: Vec<usize> = ["[GET /a ]", "[POST /b xyz]", "[GET /chunked ]",
+                                         "[POST /c hi]", "[GET /d ]"].iter()
+                .map(|body| responses.find(*body).expect(*body))
+                .collect();
+            for pair in positions.windows(2) {
+                assert!(pair[0] < pair[1]);
+            }
+            // The chunked response is ended before the next one starts.
+            let chunked_end = responses.find("0\r\n\r\nHTTP/1.1 200 OK\r\n").unwrap();
+            assert!(positions[2] < chunked_end && chunked_end < positions[3]);
+        }
+
+        // After a malformed chunk, the connection is closed rather than the rest of the body being
+        // read as another request.
+        let requests = b"POST /e HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+                         zz\r\nGET /f HTTP/1.1\r\nHost: x\r\n\r\n";
+        let responses = exchange(1, requests);
+        assert!(responses.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(responses.contains("Connection: close\r\n"));
+        assert!(!responses.contains("[GET /f ]"));
+    }
+}
@@ -2,177 +2,60 @@ use url::Url;
 use method::Method;
 use method::Method::Options;
 use status;
-use status::Status::{BadRequest, RequestUriTooLong, HttpVersionNotSupported};
+use status::Status::{BadRequest, RequestUriTooLong, RequestHeaderFieldsTooLarge,
+                     HttpVersionNotSupported, NotImplemented, ExpectationFailed,
+                     RequestEntityTooLarge};
 use std::fmt::Display;
-use std::old_io::{Stream, IoResult};
-use std::old_io::net::ip::SocketAddr;
+use std::old_io::BufReader;
+use std::old_io::net::ip::{SocketAddr, IpAddr};
+use std::old_io::net::ip::IpAddr::Ipv6Addr;
+use url::percent_encoding::percent_decode;
 use std::old_io::net::tcp::TcpStream;
 use std::fmt;
-use rfc2616::{CR, LF, SP};
+use std::mem;
+use rfc2616::SP;
 use headers;
 use buffer::BufferedStream;
-use common::read_http_version;
+use parser;
+use server::Config;
+use form::{Form, FormLimits, FormError};
+use multipart::{MultipartReader, MultipartLimits, MultipartError};
+use std::ascii::AsciiExt;
 
-use headers::HeaderLineErr;
-use headers::HeaderLineErr::{EndOfFile, EndOfHeaders, MalformedHeaderSyntax,
-                             MalformedHeaderValue};
+use headers::raw::RawHeaders;
+use headers::transfer_encoding::TransferCoding::Chunked;
+use coding::{transfer_codings_supported, decode_transfer_codings, DecodeError};
 
 use self::RequestUri::{Star, AbsoluteUri, AbsolutePath, Authority};
 
-// /// Line/header can't be more than 4KB long (note that with the compacting of LWS the actual source
-// /// data could be longer than 4KB)
-// const MAX_LINE_LEN: usize = 0x1000;
-
-const MAX_REQUEST_URI_LEN: usize = 1024;
+// The default limits on the parts of a request head; see `server::Config`.
+pub const DEFAULT_MAX_REQUEST_URI_LEN: usize = 8192;
 pub const MAX_METHOD_LEN: usize = 64;
-
-pub struct RequestBuffer<'a, S: 'a> {
-    /// The socket connection to read from
-    pub stream: &'a mut BufferedStream<S>,
+pub const DEFAULT_MAX_HEADER_LEN: usize = 8192;
+pub const DEFAULT_MAX_HEADER_COUNT: usize = 100;
+pub const DEFAULT_MAX_HEADERS_LEN: usize = 0x8000;
+pub const DEFAULT_MAX_BODY_LEN: usize = 0x4000000;
+pub const DEFAULT_MAX_DECODED_BODY_LEN: usize = 0x4000000;
+
+/// The longest a request line can be beyond its method and request-target: two spaces,
+/// "HTTP/x.y" and CR LF.
+const REQUEST_LINE_OVERHEAD: usize = 12;
+
+#[cfg(test)]
+fn path(path: &str, query: Option<&str>) -> RequestUri {
+    AbsolutePath(RequestPath { path: String::from_str(path), query: query.map(String::from_str) })
 }
 
-impl<'a, S: Stream> RequestBuffer<'a, S> {
-    pub fn new(stream: &'a mut BufferedStream<S>) -> RequestBuffer<'a, S> {
-        RequestBuffer {
-            stream: stream,
-        }
-    }
-
-    pub fn read_request_line(&mut self) -> Result<(Method, RequestUri, (usize, usize)),
-                                                  status::Status> {
-        let method = match self.read_method() {
-            Ok(m) => m,
-            // TODO: this is a very common case, if a connection is kept open but then closed or
-            // timed out. We should handle that case specially if we can improve perf—check if the
-            // peer is still there and just drop the request if it is not
-            Err(_) => return Err(BadRequest),
-        };
-
-        // Finished reading the method, including consuming a single SP.
-        // Before we read the Request-URI, we should consume *SP (it's invalid,
-        // but the spec recommends supporting it anyway).
-        let mut next_byte;
-        loop {
-            match self.stream.read_byte() {
-                Ok(_b@SP) => continue,
-                Ok(b) => {
-                    next_byte = b;
-                    break;
-                },
-                _ => return Err(BadRequest),
-            };
-        }
-
-        // Good, we're now into the Request-URI. Bear in mind that as well as
-        // ending in SP, it can for HTTP/0.9 end in CR LF or LF.
-        let mut raw_request_uri = String::new();
-        loop {
-            if next_byte == CR {
-                // For CR, we must have an LF immediately afterwards.
-                if self.stream.read_byte() != Ok(LF) {
-                    return Err(BadRequest);
-                } else {
-                    // Simplify it by just dealing with the LF possibility
-                    next_byte = LF;
-                    break;
-                }
-            } else if next_byte == SP || next_byte == LF {
-                break;
-            }
-
-            if raw_request_uri.len() == MAX_REQUEST_URI_LEN {
-                return Err(RequestUriTooLong)
-            }
-            raw_request_uri.push(next_byte as char);
-
-            next_byte = match self.stream.read_byte() {
-                Ok(b) => b,
-                _ => return Err(BadRequest),
-            }
-        }
-
-        // Now parse it into a RequestUri.
-        let request_uri = match RequestUri::from_string(raw_request_uri) {
-            Some(r) => r,
-            None => return Err(BadRequest),
-        };
-
-        // At this point, we need to consider what came immediately after the
-        // Request-URI. If it was a SP, then we expect (again after allowing for
-        // possible *SP, though illegal) to get an HTTP-Version. If it was CR LF
-        // or LF, we consider it to be HTTP/0.9.
-        if next_byte == LF {
-            // Good, we got CR LF or LF; HTTP/0.9 it is.
-            return Ok((method, request_uri, (0, 9)));
-        }
-
-        // By this point, next_byte can only be SP. Now we want an HTTP-Version.
-
-        let mut read_b = 0;
-
-        // FIXME: we still have one inconsistency here: this isn't trimming *SP.
-        match read_http_version(self.stream, &mut |b| { read_b = b; b == CR || b == LF }) {
-            Ok(vv) => {
-                if read_b == LF || self.stream.read_byte() == Ok(LF) {
-                    Ok((method, request_uri, vv))  // LF or CR LF: valid
-                } else {
-                    Err(BadRequest)  // CR but no LF: not valid
-                }
-            },
-            Err(_) => Err(BadRequest),  // invalid: ... not valid ;-)
-        }
-    }
-
-    #[inline]
-    fn read_method(&mut self) -> IoResult<Method> {
-        mod dummy { include!(concat!(env!("OUT_DIR"), "/read_method.rs")); }
-
-        dummy::dummy::read_method(self.stream)
-    }
-
-    /// Read a header (name, value) pair.
-    ///
-    /// This is not necessarily just a line ending with CRLF; there are much fancier rules at work.
-    /// Where appropriate (TODO, it's everywhere at present) linear whitespace is collapsed into a
-    /// single space.
-    ///
-    /// # Error values
-    ///
-    /// - `EndOfHeaders`: I have no more headers to give; go forth and conquer on the body!
-    /// - `EndOfFile`: socket was closed unexpectedly; probable best behavour is to drop the request
-    /// - `MalformedHeaderValue`: header's value is invalid; normally, ignore it.
-    /// - `MalformedHeaderSyntax`: bad request; you could drop it or try returning 400 Bad Request
-    pub fn read_header<T: headers::HeaderEnum>(&mut self) -> Result<T, HeaderLineErr> {
-        match headers::header_enum_from_stream(&mut *self.stream) {
-        //match headers::HeaderEnum::from_stream(self.stream) {
-            (Err(m), None) => Err(m),
-            (Err(m), Some(b)) => {
-                self.stream.poke_byte(b);
-                Err(m)
-            },
-            (Ok(header), Some(b)) => {
-                self.stream.poke_byte(b);
-                Ok(header)
-            }
-            (Ok(header), None) => {
-                // This should have read an extra byte, on account of the CR LF SP possibility
-                error!("header with no next byte, did reading go wrong?");
-                Ok(header)
-            }
-        }
-    }
-}
-
-impl<'a, S: Stream> Reader for RequestBuffer<'a, S> {
-    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
-        self.stream.read(buf)
-    }
+#[cfg(test)]
+fn authority(host: &str, port: Option<u16>) -> RequestUri {
+    Authority(RequestAuthority { host: String::from_str(host), port: port })
 }
 
 #[test]
 fn test_request_uri_from_string() {
     assert_eq!(RequestUri::from_string("*".to_string()), Some(RequestUri::Star));
-    assert_eq!(RequestUri::from_string("/abc".to_string()), Some(RequestUri::AbsolutePath("/abc".to_string())));
+    assert_eq!(RequestUri::from_string("/abc".to_string()), Some(path("/abc", None)));
+    assert_eq!(RequestUri::from_string("/a%20b?c=d?".to_string()), Some(path("/a%20b", Some("c=d?"))));
     let url = "http://example.com/abc";
     match RequestUri::from_string(url.to_string()) {
 	Some(RequestUri::AbsoluteUri(url)) => {
@@ -182,51 +65,69 @@ fn test_request_uri_from_string() {
 	_ => panic!("Parse failed for {}", url),
     };
     assert_eq!(RequestUri::from_string("".to_string()), None);
-    assert_eq!(RequestUri::from_string(" ".to_string()), Some(Authority(" ".to_string())));
+    assert_eq!(RequestUri::from_string(" ".to_string()), None);
+    assert_eq!(RequestUri::from_string("example.com:443".to_string()),
+               Some(authority("example.com", Some(443))));
+    assert_eq!(RequestUri::from_string("[::1]:8080".to_string()),
+               Some(authority("[::1]", Some(8080))));
+    assert_eq!(RequestUri::from_string("[::1]".to_string()), Some(authority("[::1]", None)));
+    assert_eq!(RequestUri::from_string("[nonsense]:80".to_string()), None);
+    assert_eq!(RequestUri::from_string("example.com:http".to_string()), None);
+    assert_eq!(RequestUri::from_string("example.com:99999".to_string()), None);
     Url::parse("").unwrap_err();    // Url::parse() should return error for empty string
 }
 
 #[test]
-fn test_read_request_line() {
-    use method::Method::{Get, Options, Connect, ExtensionMethod};
-    use buffer::BufferedStream;
-    use memstream::MemReaderFakeStream;
+fn test_request_path_decoding() {
+    let path = RequestPath::parse("/caf%C3%A9/a%2Fb?q=%E2%9C%93").unwrap();
+    assert_eq!(path.decoded_path(), Some(String::from_str("/café/a/b")));
+    assert_eq!(path.decoded_segments(),
+               Some(vec!(String::from_str("café"), String::from_str("a/b"))));
+    assert_eq!(path.decoded_query(), Some(String::from_str("q=✓")));
+    assert_eq!(RequestPath::parse("/%FF").unwrap().decoded_path(), None);
+    assert_eq!(path.to_string(), String::from_str("/caf%C3%A9/a%2Fb?q=%E2%9C%93"));
+}
 
-    macro_rules! tt {
-        ($value:expr => $expected:expr) => {{
-            let expected = $expected;
-            let mut stream = BufferedStream::new(
-                MemReaderFakeStream::new($value.bytes().collect::<Vec<_>>()));
-            assert_eq!(RequestBuffer::new(&mut stream).read_request_line(), expected);
-        }}
-    }
+#[test]
+fn test_effective_url() {
+    use headers::host::Host;
+
+    let mut loader = RequestLoader::new(None);
+    let config = Config::new(SocketAddr { ip: ::std::old_io::net::ip::Ipv4Addr(127, 0, 0, 1),
+                                          port: 8001 });
+    loader.feed(b"GET /a?b HTTP/1.1\r\nHost: example.com:8080\r\n\r\n", &config);
+    let mut request = loader.into_request();
+    assert_eq!(request.effective_url("https"), Url::parse("https://example.com:8080/a?b").ok());
+    request.request_uri = authority("example.org", Some(443));
+    assert_eq!(request.effective_url("http"), Url::parse("http://example.org:443").ok());
+    request.request_uri = path("/", None);
+    request.headers.host = None;
+    assert_eq!(request.effective_url("http"), None);
+    request.headers.host = Some(Host { name: String::from_str("[::1]"), port: None });
+    assert_eq!(request.effective_url("http"), Url::parse("http://[::1]/").ok());
+}
 
-    tt!("GET / HTTP/1.1\n" => Ok((Get, AbsolutePath(String::from_str("/")), (1, 1))));
-    tt!("GET / HTTP/1.1\r\n" => Ok((Get, AbsolutePath(String::from_str("/")), (1, 1))));
-    tt!("OPTIONS /foo/bar HTTP/1.1\r\n" => Ok((Options, AbsolutePath(String::from_str("/foo/bar")), (1, 1))));
-    tt!("OPTIONS * HTTP/1.1\r\n" => Ok((Options, Star, (1, 1))));
-    tt!("CONNECT example.com HTTP/1.1\r\n" => Ok((Connect,
-                                                Authority(String::from_str("example.com")),
-                                                (1, 1))));
-    tt!("FOO /\r\n" => Ok((ExtensionMethod(String::from_str("FOO")), AbsolutePath(String::from_str("/")), (0, 9))));
-    tt!("FOO /\n" => Ok((ExtensionMethod(String::from_str("FOO")), AbsolutePath(String::from_str("/")), (0, 9))));
-    tt!("get    http://example.com/ HTTP/42.17\r\n"
-            => Ok((ExtensionMethod(String::from_str("get")),
-                    AbsoluteUri(Url::parse("http://example.com/").unwrap()),
-                    (42, 17))));
-
-    // Now for some failing cases.
-
-    // method name is not a token
-    tt!("GE,T / HTTP/1.1\r\n" => Err(BadRequest));
-
-    // Request-URI is missing ("HTTP/1.1" isn't a valid Request-URI; I confirmed this by tracing the
-    // rule through RFC 2396: the "/" prevents it from being a reg_name authority, and it doesn't
-    // satisfy any of the other possibilities for Request-URI either)
-    tt!("GET  HTTP/1.1\r\n" => Err(BadRequest));
-
-    // Invalid HTTP-Version
-    tt!("GET / HTTX/1.1\r\n" => Err(BadRequest));
+#[test]
+fn test_request_forms() {
+    let config = Config::new(SocketAddr { ip: ::std::old_io::net::ip::Ipv4Addr(127, 0, 0, 1),
+                                          port: 8001 });
+    let mut loader = RequestLoader::new(None);
+    loader.feed(b"POST /search?q=a+b&page=2 HTTP/1.1\r\nHost: example.com\r\n\
+                  Content-Type: application/x-www-form-urlencoded\r\nContent-Length: 9\r\n\r\n\
+                  q=c&sort=", &config);
+    let request = loader.into_request();
+    let limits = FormLimits::new();
+    assert_eq!(request.query_form(&limits).unwrap().get_all("q"), vec!("a b"));
+    assert_eq!(request.post_form(&limits).unwrap().get("sort"), Some(""));
+    let form = request.form(&limits).unwrap();
+    assert_eq!(form.get_all("q"), vec!("c", "a b"));
+    assert_eq!(form.get("page"), Some("2"));
+}
+
+#[test]
+fn test_request_target_to_string() {
+    assert_eq!(request_target_to_string(b"/a%20b?c=d"), String::from_str("/a%20b?c=d"));
+    assert_eq!(request_target_to_string(b"/\xc3\xbc"), String::from_str("/%C3%BC"));
 }
 
 /// An HTTP request sent to the server.
@@ -242,6 +143,11 @@ pub struct Request {
     /// The headers sent with the request.
     pub headers: headers::request::HeaderCollection,
 
+    /// The fields of the trailer after a chunked body, as they were sent. They are kept apart from
+    /// `headers`, for they arrive after the head has been checked and so must not be able to
+    /// change it (RFC 7230 §4.1.2).
+    pub trailers: RawHeaders,
+
     /// The body of the request; empty for such methods as GET.
     pub body: Vec<u8>,
 
@@ -280,16 +186,140 @@ pub enum RequestUri {
     /// 'To allow for transition to absoluteURIs in all requests in future versions of HTTP, all
     /// HTTP/1.1 servers MUST accept the absoluteURI form in requests, even though HTTP/1.1 clients
     /// will only generate them in requests to proxies.'
-    ///
-    /// TODO: this shouldn't be a string; it should be further parsed. `extra::net::url` has some
-    /// stuff which might help, but isn't public.
-    AbsolutePath(String),
+    AbsolutePath(RequestPath),
 
     /// 'The authority form is only used by the CONNECT method (CONNECT).'
+    Authority(RequestAuthority),
+}
+
+/// An absolute path and optional query, as in an origin-form request-target (`/where?q=now`).
+///
+/// Both parts are kept as they were sent, percent-encoding and all; the `decoded_*` methods undo
+/// the percent-encoding.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RequestPath {
+    /// The path, which always begins with "/".
+    pub path: String,
+
+    /// The query, without the "?" before it.
+    pub query: Option<String>,
+}
+
+impl RequestPath {
+    /// Split an origin-form request-target into its path and query.
+    pub fn parse(target: &str) -> Option<RequestPath> {
+        if !target.starts_with("/") || target.bytes().any(|b| b <= b' ' || b >= 0x7f || b == b'#') {
+            return None;
+        }
+        let mut parts = target.splitn(1, '?');
+        Some(RequestPath {
+            path: String::from_str(parts.next().unwrap()),
+            query: parts.next().map(String::from_str),
+        })
+    }
+
+    /// The path with percent-encoding undone, or `None` if the result is not UTF-8.
     ///
-    /// TODO: this shouldn't be a string; it should be further parsed. `extra::net::url` has some
-    /// stuff which might help, but isn't public.
-    Authority(String),
+    /// Note that an encoded "/" ("%2F") is indistinguishable from a real one here; use
+    /// `decoded_segments` if that matters.
+    pub fn decoded_path(&self) -> Option<String> {
+        String::from_utf8(percent_decode(self.path.as_bytes())).ok()
+    }
+
+    /// The segments of the path (between the slashes), each with percent-encoding undone, or
+    /// `None` if any is not UTF-8. "/" has a single, empty segment.
+    pub fn decoded_segments(&self) -> Option<Vec<String>> {
+        self.path[1..].split('/')
+            .map(|segment| String::from_utf8(percent_decode(segment.as_bytes())).ok())
+            .collect()
+    }
+
+    /// The query with percent-encoding undone, or `None` if there is no query or the result is
+    /// not UTF-8.
+    pub fn decoded_query(&self) -> Option<String> {
+        self.query.as_ref().and_then(|query| String::from_utf8(percent_decode(query.as_bytes())).ok())
+    }
+}
+
+impl fmt::Display for RequestPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.query {
+            Some(ref query) => write!(f, "{}?{}", self.path, query),
+            None => f.write_str(&self.path[..]),
+        }
+    }
+}
+
+/// A host and optional port, as in an authority-form request-target (`example.com:443`).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RequestAuthority {
+    /// A registered name or IPv4 address, or an IPv6 address in square brackets ("[::1]"), as it
+    /// would be written in a URL.
+    pub host: String,
+
+    /// The port, if one was given.
+    pub port: Option<u16>,
+}
+
+impl RequestAuthority {
+    /// Parse an authority-form request-target (RFC 3986 §3.2.2 and §3.2.3; user information is
+    /// not allowed here).
+    pub fn parse(target: &str) -> Option<RequestAuthority> {
+        let (host, port) = if target.starts_with("[") {
+            // An IP literal; only IPv6 addresses are supported.
+            let end = match target.find(']') {
+                Some(end) => end,
+                None => return None,
+            };
+            match target[1..end].parse::<IpAddr>() {
+                Ok(Ipv6Addr(..)) => (),
+                _ => return None,
+            }
+            match &target[end + 1..] {
+                "" => (&target[..end + 1], None),
+                rest if rest.starts_with(":") => (&target[..end + 1], Some(&rest[1..])),
+                _ => return None,
+            }
+        } else {
+            let mut parts = target.splitn(1, ':');
+            let host = parts.next().unwrap();
+            if host.is_empty() || !host.bytes().all(is_reg_name_byte) {
+                return None;
+            }
+            (host, parts.next())
+        };
+        let port = match port {
+            // An empty port is allowed, and means the same as none.
+            None | Some("") => None,
+            Some(port) if port.bytes().all(|b| b >= b'0' && b <= b'9') => match port.parse() {
+                Ok(port) => Some(port),
+                Err(_) => return None,
+            },
+            Some(_) => return None,
+        };
+        Some(RequestAuthority {
+            host: String::from_str(host),
+            port: port,
+        })
+    }
+}
+
+/// Whether the byte may appear in a reg-name: unreserved, pct-encoded or sub-delims.
+fn is_reg_name_byte(b: u8) -> bool {
+    match b {
+        b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' | b'%' |
+        b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'=' => true,
+        _ => false,
+    }
+}
+
+impl fmt::Display for RequestAuthority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{}:{}", self.host, port),
+            None => f.write_str(&self.host[..]),
+        }
+    }
 }
 
 impl RequestUri {
@@ -300,7 +330,7 @@ impl RequestUri {
 	} else if &request_uri[..] == "*" {
             Some(Star)
         } else if request_uri.as_bytes()[0] as char == '/' {
-            Some(AbsolutePath(request_uri))
+            RequestPath::parse(&request_uri[..]).map(AbsolutePath)
         } else if request_uri.contains("/") {
             // An authority can't have a slash in it
             match Url::parse(&request_uri[..]) {
@@ -308,8 +338,7 @@ impl RequestUri {
                 Err(_) => None,
             }
         } else {
-            // TODO: parse authority with extra::net::url
-            Some(Authority(request_uri))
+            RequestAuthority::parse(&request_uri[..]).map(Authority)
         }
     }
 }
@@ -319,79 +348,261 @@ impl fmt::Display for RequestUri {
         match *self {
             Star => f.write_str("*"),
             AbsoluteUri(ref url) => url.fmt(f),
-            AbsolutePath(ref s) => f.write_str(&s[..]),
-            Authority(ref s) => f.write_str(&s[..]),
+            AbsolutePath(ref path) => path.fmt(f),
+            Authority(ref authority) => authority.fmt(f),
         }
     }
 }
 
 impl Request {
+    /// The path and query of the request-target, whether it is in origin-form or absolute-form;
+    /// `None` for `*` and authority-form.
+    pub fn path(&self) -> Option<RequestPath> {
+        match self.request_uri {
+            AbsolutePath(ref path) => Some(path.clone()),
+            AbsoluteUri(ref url) => url.serialize_path().and_then(|path| {
+                RequestPath::parse(&path[..]).map(|mut path| {
+                    path.query = url.query.clone();
+                    path
+                })
+            }),
+            Star | Authority(_) => None,
+        }
+    }
 
-    /// Get a response from an open socket.
-    pub fn load(stream: &mut BufferedStream<TcpStream>) -> (Request, Result<(), status::Status>) {
-        let mut buffer = RequestBuffer::new(stream);
-
-        // Start out with dummy values
-        let mut request = Request {
-            remote_addr: buffer.stream.wrapped.peer_name().ok(),
-            headers: headers::request::HeaderCollection::new(),
-            body: Vec::new(),
-            method: Options,
-            request_uri: Star,
-            close_connection: true,
-            version: (0, 0),
+    /// Whether the client asks to switch the connection to the named protocol (as with
+    /// `Connection: Upgrade` and `Upgrade: websocket`); see `ResponseWriter::upgrade`.
+    pub fn wants_upgrade(&self, protocol: &str) -> bool {
+        let connection_upgrade = match self.headers.connection {
+            Some(ref connection) => connection.iter().any(|token| match *token {
+                headers::connection::Connection::Token(ref s) => s.eq_ignore_ascii_case("upgrade"),
+                headers::connection::Connection::Close => false,
+            }),
+            None => false,
         };
+        connection_upgrade && self.version >= (1, 1) && match self.headers.upgrade {
+            Some(ref protocols) => protocols.iter().any(|p| p.is(protocol)),
+            None => false,
+        }
+    }
 
-        let (method, request_uri, version) = match buffer.read_request_line() {
-            Ok(vals) => vals,
-            Err(err) => return (request, Err(err)),
+    /// The effective request URL (RFC 7230 §5.5): the request-target, made absolute if it is not
+    /// already with the Host header and the scheme of the connection ("http", or "https" if the
+    /// server is behind something which handles TLS for it).
+    ///
+    /// This is `None` if the request-target is not absolute and there is no Host header to
+    /// complete it with, as may be the case for HTTP/1.0.
+    pub fn effective_url(&self, scheme: &str) -> Option<Url> {
+        let url = match (&self.request_uri, &self.headers.host) {
+            (&AbsoluteUri(ref url), _) => return Some(url.clone()),
+            (&Authority(ref authority), _) => format!("{}://{}", scheme, authority),
+            (&AbsolutePath(ref path), &Some(ref host)) => format!("{}://{}{}", scheme, host, path),
+            (&Star, &Some(ref host)) => format!("{}://{}", scheme, host),
+            (_, &None) => return None,
         };
-        request.method = method;
-        request.request_uri = request_uri;
-        request.version = version;
+        Url::parse(&url[..]).ok()
+    }
 
-        // request.close_connection is deliberately left set to true so that in case of a bad
-        // request we can close the connection
-        let close_connection = match version {
-            (1, 0) => true,
-            (1, 1) => false,
-            _ => return (request, Err(HttpVersionNotSupported)),
+    /// The fields of the query string; there are none if there is no query string.
+    pub fn query_form(&self, limits: &FormLimits) -> Result<Form, FormError> {
+        let query = match self.request_uri {
+            AbsolutePath(ref path) => path.query.as_ref(),
+            AbsoluteUri(ref url) => url.query.as_ref(),
+            Star | Authority(_) => None,
         };
+        match query {
+            Some(query) => Form::parse(query.as_bytes(), limits),
+            None => Ok(Form::new()),
+        }
+    }
 
+    /// The fields of the body, if it is `application/x-www-form-urlencoded` (like `PostForm` of
+    /// Go's `Request`); otherwise there are none.
+    pub fn post_form(&self, limits: &FormLimits) -> Result<Form, FormError> {
+        match self.headers.content_type {
+            Some(ref media_type) if media_type.type_.eq_ignore_ascii_case("application") &&
+                    media_type.subtype.eq_ignore_ascii_case("x-www-form-urlencoded") =>
+                Form::parse(&self.body[..], limits),
+            _ => Ok(Form::new()),
+        }
+    }
+
+    /// A reader of the parts of a `multipart/form-data` body; see `http::multipart`.
+    ///
+    /// The body has already been read into memory in full (up to `Config::max_body_len`), so
+    /// reading the parts one at a time and spilling large ones to temporary files saves no memory
+    /// here; the limits still apply.
+    pub fn multipart<'a>(&'a self, limits: MultipartLimits)
+            -> Result<MultipartReader<BufReader<'a>>, MultipartError> {
+        match self.headers.content_type {
+            Some(ref media_type) =>
+                MultipartReader::for_content_type(BufReader::new(&self.body[..]), media_type,
+                                                  limits),
+            None => Err(MultipartError::NotMultipart),
+        }
+    }
+
+    /// The fields of the body followed by those of the query string (like `Form` of Go's
+    /// `Request`). The limits apply to each separately.
+    pub fn form(&self, limits: &FormLimits) -> Result<Form, FormError> {
+        let mut form = try!(self.post_form(limits));
+        form.extend(try!(self.query_form(limits)));
+        Ok(form)
+    }
+
+    /// Get a response from an open socket.
+    pub fn load(stream: &mut BufferedStream<TcpStream>, config: &Config)
+            -> (Request, Result<(), status::Status>) {
+        // The request is parsed where it lies in the read buffer; when the loader needs more,
+        // more is read onto the end of it.
+        let mut loader = RequestLoader::new(stream.wrapped.peer_name().ok());
         loop {
-            match buffer.read_header() {
-                Err(EndOfFile) => panic!("client disconnected, nowhere to send response"),
-                Err(EndOfHeaders) => break,
-                Err(MalformedHeaderSyntax) => {
-                    println!("BAD REQUEST: malformed header (TODO: is this right?)");
-                    return (request, Err(BadRequest));
-                },
-                Err(MalformedHeaderValue) => {
-                    println!("Bad header encountered. TODO: handle this better.");
-                    // Now just ignore the header
-                },
-                Ok(header) => {
-                    request.headers.insert(header);
+            let (consumed, result) = loader.feed(stream.buffered(), config);
+            stream.consume(consumed);
+            match result {
+                Ok(true) => return (loader.into_request(), Ok(())),
+                Ok(false) => (),
+                Err(status) => return (loader.into_request(), Err(status)),
+            }
+            if loader.take_continue() {
+                if stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").is_err() {
+                    return (loader.into_request(), Err(BadRequest));
+                }
+            }
+            // Anything written but not yet sent (such as responses to earlier pipelined requests)
+            // must go out before waiting for more from the client, which may be waiting for it.
+            if stream.flush().is_err() {
+                return (loader.into_request(), Err(BadRequest));
+            }
+            match stream.fill_more() {
+                Ok(0) => {
+                    let status = loader.too_large_status();
+                    return (loader.into_request(), Err(status));
                 },
+                Ok(_) => (),
+                // Most likely the client closed the connection.
+                Err(_) => return (loader.into_request(), Err(BadRequest)),
             }
         }
+    }
+
+    /// Fill in the request from its request line, returning whether the connection should be
+    /// closed afterwards if the request does not say otherwise.
+    fn load_request_line(&mut self, line: parser::RequestLine, config: &Config)
+            -> Result<bool, status::Status> {
+        if line.method.len() > config.max_method_len {
+            return Err(BadRequest);
+        }
+        if line.target.len() > config.max_request_uri_len {
+            return Err(RequestUriTooLong);
+        }
+        self.method = Method::from_str_or_new(line.method).unwrap();
+        self.request_uri = match RequestUri::from_string(request_target_to_string(line.target)) {
+            Some(r) => r,
+            None => return Err(BadRequest),
+        };
+        self.version = line.version;
 
+        // request.close_connection is deliberately left set to true so that in case of a bad
+        // request we can close the connection
+        match line.version {
+            (1, 0) => Ok(true),
+            (1, 1) => Ok(false),
+            _ => Err(HttpVersionNotSupported),
+        }
+    }
+
+    /// Add a header field to the request.
+    ///
+    /// An invalid value is ignored (it is still in `headers.raw`), except for Content-Length: an
+    /// invalid one, or one which differs from an earlier one, leaves the framing of the request in
+    /// doubt and so makes it a bad request (RFC 7230 §3.3.2).
+    fn load_header(&mut self, field: parser::HeaderField, config: &Config)
+            -> Result<(), status::Status> {
+        let result = if parser::is_folded(field.value) {
+            self.headers.append_raw_with(field.name, &parser::unfold(field.value)[..],
+                                         &*config.header_registry)
+        } else {
+            self.headers.append_raw_with(field.name, field.value, &*config.header_registry)
+        };
+        if result.is_err() {
+            if field.name.eq_ignore_ascii_case("content-length") {
+                debug!("BAD REQUEST: invalid or conflicting Content-Length");
+                return Err(BadRequest);
+            }
+            println!("Bad header encountered. TODO: handle this better.");
+            // Now just ignore the header (it is still in headers.raw)
+        }
+        Ok(())
+    }
+
+    /// Keep a field of the trailer after a chunked body.
+    fn load_trailer(&mut self, field: parser::HeaderField) {
+        let value = if parser::is_folded(field.value) {
+            parser::unfold(field.value)
+        } else {
+            field.value.to_vec()
+        };
+        self.trailers.append(String::from_str(field.name),
+                             value.into_iter().map(|b| b as char).collect());
+    }
+
+    /// Remove any transfer-codings other than chunked from the complete body, refusing it if it
+    /// decodes to more than `max_len` bytes.
+    fn decode_body(&mut self, max_len: usize) -> Result<(), status::Status> {
+        let codings = match self.headers.transfer_encoding {
+            Some(ref codings) if codings.iter().any(|coding| *coding != Chunked) => codings.clone(),
+            _ => return Ok(()),
+        };
+        let body = mem::replace(&mut self.body, Vec::new());
+        match decode_transfer_codings(&codings[..], body, max_len) {
+            Ok(body) => {
+                self.body = body;
+                Ok(())
+            },
+            Err(DecodeError::TooLarge) => Err(RequestEntityTooLarge),
+            Err(DecodeError::Io(_)) => Err(BadRequest),
+        }
+    }
+
+    /// Check the complete head of the request and decide whether to close the connection after
+    /// it, before any of the body is read.
+    fn check_head(&mut self, close_connection: bool) -> Result<(), status::Status> {
         // HTTP/1.0 doesn't have Host, but HTTP/1.1 requires it
-        if request.version == (1, 1) && request.headers.host.is_none() {
+        if self.version == (1, 1) && self.headers.host.is_none() {
             println!("BAD REQUEST: host is none for HTTP/1.1 request");
-            return (request, Err(BadRequest));
+            return Err(BadRequest);
+        }
+
+        // A request with a transfer-coding which cannot be removed cannot be read, so the rest of
+        // the connection cannot be made sense of either.
+        if let Some(ref codings) = self.headers.transfer_encoding {
+            if self.version < (1, 1) {
+                // HTTP/1.0 has no transfer-codings, so the framing is not to be trusted.
+                return Err(BadRequest);
+            }
+            if !transfer_codings_supported(&codings[..]) {
+                return Err(NotImplemented);
+            }
+            // Content-Length is overridden, but something else along the way may have gone by it
+            // instead; such a request is refused rather than guessed at (RFC 7230 §3.3.3).
+            if self.headers.content_length.is_some() {
+                return Err(BadRequest);
+            }
         }
 
-        request.close_connection = close_connection;
-        match request.headers.connection {
+        self.close_connection = close_connection;
+        match self.headers.connection {
             Some(ref h) => for v in h.iter() {
                 match *v {
                     headers::connection::Connection::Close => {
-                        request.close_connection = true;
+                        self.close_connection = true;
                         break;
                     },
-                    headers::connection::Connection::Token(ref s) if &s[..] == "keep-alive" => {
-                        request.close_connection = false;
+                    // Tokens are in normalised header case, "Keep-Alive".
+                    headers::connection::Connection::Token(ref s)
+                            if s.eq_ignore_ascii_case("keep-alive") => {
+                        self.close_connection = false;
                         // No break; let it be overridden by close should some weird person do that
                     },
                     headers::connection::Connection::Token(_) => (),
@@ -399,20 +610,403 @@ impl Request {
             },
             None => (),
         }
+        Ok(())
+    }
+}
 
-        // Read body if its length is specified
-        match request.headers.content_length {
-            Some(length) => {
-                match buffer.read_exact(length) {
-                    Ok(body) => request.body = body,
-                    Err(_) => return (request, Err(BadRequest))
-                }
+/// Builds a `Request` from bytes as they arrive, doing no I/O of its own.
+///
+/// `Request::load` drives this from a blocking stream; the event loop server drives it from
+/// non-blocking sockets.
+pub struct RequestLoader {
+    parser: parser::Parser,
+    request: Request,
+    close_connection: bool,
+    /// The number of header fields so far, and the bytes they took.
+    header_count: usize,
+    headers_len: usize,
+
+    /// Whether `100 Continue` is yet to be sent; see `take_continue`.
+    continue_pending: bool,
+}
+
+impl RequestLoader {
+    pub fn new(remote_addr: Option<SocketAddr>) -> RequestLoader {
+        RequestLoader {
+            parser: parser::Parser::request(),
+            // Start out with dummy values
+            request: Request {
+                remote_addr: remote_addr,
+                headers: headers::request::HeaderCollection::new(),
+                trailers: RawHeaders::new(),
+                body: Vec::new(),
+                method: Options,
+                request_uri: Star,
+                close_connection: true,
+                version: (0, 0),
+            },
+            close_connection: true,
+            header_count: 0,
+            headers_len: 0,
+            continue_pending: false,
+        }
+    }
+
+    /// Whether the client is waiting for `100 Continue` before it sends the body, in which case
+    /// the caller must send it now (this is only ever true once per request).
+    pub fn take_continue(&mut self) -> bool {
+        let pending = self.continue_pending;
+        self.continue_pending = false;
+        pending
+    }
+
+    /// Act on the Expect header once the head of the request is complete: either note that
+    /// `100 Continue` is to be sent, or refuse the request (and so its body) outright.
+    fn check_expect(&mut self, config: &Config) -> Result<(), status::Status> {
+        let continue_expected = match self.request.headers.expect {
+            Some(ref expect) => Some(expect.trim().eq_ignore_ascii_case("100-continue")),
+            None => None,
+        };
+        let result = match continue_expected {
+            None => return Ok(()),
+            // An HTTP/1.0 client cannot be expecting it (RFC 7231 §5.1.1).
+            Some(true) if self.request.version < (1, 1) => return Ok(()),
+            Some(true) => match config.continue_hook {
+                Some(ref hook) => (***hook)(&self.request),
+                None => Ok(()),
             },
-            None => ()
+            Some(false) => Err(ExpectationFailed),
+        };
+        match result {
+            Ok(()) => self.continue_pending = true,
+            // The client may send the body anyway, or may not; either way, it is not to be read.
+            Err(_) => self.request.close_connection = true,
         }
+        result
+    }
 
-        (request, Ok(()))
+    /// Parse as much of `data` as possible, returning how many bytes were consumed and whether
+    /// the request is now complete. Anything after the end of the request is left unconsumed.
+    pub fn feed(&mut self, data: &[u8], config: &Config) -> (usize, Result<bool, status::Status>) {
+        let mut total = 0;
+        loop {
+            let (consumed, event) = match self.parser.parse(&data[total..]) {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    debug!("BAD REQUEST: malformed request ({:?})", err);
+                    return (total, Err(BadRequest));
+                },
+            };
+            total += consumed;
+            let result = match event {
+                parser::Event::RequestLine(line) => match self.request.load_request_line(line,
+                                                                                         config) {
+                    Ok(close) => {
+                        self.close_connection = close;
+                        Ok(false)
+                    },
+                    Err(status) => Err(status),
+                },
+                parser::Event::Header(field) | parser::Event::Trailer(field) => {
+                    self.header_count += 1;
+                    self.headers_len += consumed;
+                    if consumed > config.max_header_len ||
+                            self.header_count > config.max_header_count ||
+                            self.headers_len > config.max_headers_len {
+                        Err(RequestHeaderFieldsTooLarge)
+                    } else if self.parser.is_in_head() {
+                        self.request.load_header(field, config).map(|()| false)
+                    } else {
+                        self.request.load_trailer(field);
+                        Ok(false)
+                    }
+                },
+                parser::Event::HeadersComplete => {
+                    match self.request.check_head(self.close_connection) {
+                        // A body which is too long is refused before the client is told to send
+                        // it.
+                        Ok(()) if self.request.headers.content_length
+                                      .map_or(false, |len| len > config.max_body_len) =>
+                            Err(RequestEntityTooLarge),
+                        Ok(()) => self.check_expect(config).map(|()| false),
+                        Err(status) => Err(status),
+                    }
+                },
+                parser::Event::Body(data) => {
+                    if self.request.body.len() + data.len() > config.max_body_len {
+                        Err(RequestEntityTooLarge)
+                    } else {
+                        self.request.body.push_all(data);
+                        Ok(false)
+                    }
+                },
+                parser::Event::MessageComplete =>
+                    self.request.decode_body(config.max_decoded_body_len).map(|()| true),
+                parser::Event::Incomplete =>
+                    return (total, self.check_partial(&data[total..], config).map(|()| false)),
+                parser::Event::StatusLine(_) => unreachable!(),
+            };
+            match result {
+                Ok(false) => (),
+                result => return (total, result),
+            }
+        }
+    }
+
+    /// Refuse an incomplete line as soon as it is known to be too long, rather than waiting for
+    /// the rest of it.
+    fn check_partial(&self, pending: &[u8], config: &Config) -> Result<(), status::Status> {
+        if self.parser.is_idle() {
+            match pending.iter().position(|&b| b == SP) {
+                Some(len) if len > config.max_method_len => Err(BadRequest),
+                None if pending.len() > config.max_method_len => Err(BadRequest),
+                _ if pending.len() > config.max_method_len + config.max_request_uri_len +
+                                     REQUEST_LINE_OVERHEAD => Err(RequestUriTooLong),
+                _ => Ok(()),
+            }
+        } else if self.parser.is_in_head() && (pending.len() > config.max_header_len ||
+                                               self.headers_len + pending.len() >
+                                                   config.max_headers_len) {
+            Err(RequestHeaderFieldsTooLarge)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The status to refuse the request with if its head fills the whole read buffer.
+    pub fn too_large_status(&self) -> status::Status {
+        if self.parser.is_idle() {
+            RequestUriTooLong
+        } else {
+            RequestHeaderFieldsTooLarge
+        }
+    }
+
+    /// The request, as far as it has been loaded.
+    pub fn into_request(self) -> Request {
+        self.request
+    }
+}
+
+#[test]
+fn test_request_loader() {
+    use std::old_io::net::ip::Ipv4Addr;
+
+    let config = Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 });
+    let data = b"POST /a HTTP/1.1\r\nHost: example.com\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 3\r\nabc\r\n0\r\n\r\nGET /b HTTP/1.1\r\n";
+    let mut loader = RequestLoader::new(None);
+    let mut pos = 0;
+    let mut end = 0;
+    // Let it have a byte more at a time, as a slow client might send it.
+    loop {
+        end += 1;
+        let (consumed, result) = loader.feed(&data[pos..end], &config);
+        pos += consumed;
+        match result {
+            Ok(true) => break,
+            result => assert_eq!(result, Ok(false)),
+        }
+    }
+    assert_eq!(&data[pos..], b"GET /b HTTP/1.1\r\n");
+    let request = loader.into_request();
+    assert_eq!(request.method, Method::Post);
+    assert_eq!(request.request_uri, path("/a", None));
+    assert_eq!(request.body, b"abc".to_vec());
+    assert!(!request.close_connection);
+}
+
+#[test]
+fn test_request_loader_trailers() {
+    use std::old_io::net::ip::Ipv4Addr;
+
+    let config = Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 });
+    let data = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 3\r\nabc\r\n0\r\nX-Checksum: 1\r\nContent-Length: 99\r\nHost: b\r\n\r\n";
+    let mut loader = RequestLoader::new(None);
+    assert_eq!(loader.feed(data, &config), (data.len(), Ok(true)));
+    let request = loader.into_request();
+    assert_eq!(request.body, b"abc".to_vec());
+    // The trailer cannot change the head.
+    assert_eq!(request.headers.content_length, None);
+    assert_eq!(request.headers.host.as_ref().map(|h| &h.name[..]), Some("a"));
+    assert_eq!(request.trailers.get("x-checksum"), Some("1"));
+    assert_eq!(request.trailers.get_all("Content-Length"), vec!("99"));
+}
+
+#[test]
+fn test_load_header_content_length() {
+    use std::old_io::net::ip::Ipv4Addr;
+
+    let config = Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 });
+    let mut loader = RequestLoader::new(None);
+    assert_eq!(loader.feed(b"POST / HTTP/1.1\r\nHost: a\r\n", &config).1, Ok(false));
+    let mut request = loader.into_request();
+    fn field(value: &'static [u8]) -> parser::HeaderField<'static> {
+        parser::HeaderField { name: "Content-Length", value: value }
+    }
+    assert_eq!(request.load_header(field(b"5"), &config), Ok(()));
+    assert_eq!(request.load_header(field(b"5"), &config), Ok(()));
+    assert_eq!(request.load_header(field(b"6"), &config), Err(BadRequest));
+    assert_eq!(request.load_header(field(b"x"), &config), Err(BadRequest));
+    assert_eq!(request.headers.content_length, Some(5));
+}
+
+#[test]
+fn test_request_loader_keep_alive() {
+    use std::old_io::net::ip::Ipv4Addr;
+
+    let config = Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 });
+    macro_rules! tt {
+        ($data:expr => $close:expr) => {{
+            let mut loader = RequestLoader::new(None);
+            assert_eq!(loader.feed($data, &config).1, Ok(true));
+            assert_eq!(loader.into_request().close_connection, $close);
+        }}
+    }
+
+    tt!(b"GET / HTTP/1.0\r\n\r\n" => true);
+    tt!(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n" => false);
+    tt!(b"GET / HTTP/1.0\r\nConnection: Keep-Alive, close\r\n\r\n" => true);
+    tt!(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n" => false);
+    tt!(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n" => true);
+}
+
+#[test]
+fn test_request_loader_transfer_codings() {
+    use std::old_io::net::ip::Ipv4Addr;
+    use coding::Encoder;
+    use rfc2616::ContentCoding::Gzip;
+
+    let mut config = Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 });
+    let mut gzipped = Vec::new();
+    let mut encoder = Encoder::new(Gzip).unwrap();
+    encoder.encode(b"Hello, World!", &mut gzipped).unwrap();
+    encoder.finish(&mut gzipped).unwrap();
+    let mut data = format!("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked\r\n\r\n\
+                            {:x}\r\n", gzipped.len()).into_bytes();
+    data.push_all(&gzipped[..]);
+    data.push_all(b"\r\n0\r\n\r\n");
+    let mut loader = RequestLoader::new(None);
+    assert_eq!(loader.feed(&data[..], &config), (data.len(), Ok(true)));
+    assert_eq!(loader.into_request().body, b"Hello, World!".to_vec());
+
+    // A body which decodes to more than the limit is refused, however small it was sent.
+    config.max_decoded_body_len = 12;
+    let mut loader = RequestLoader::new(None);
+    assert_eq!(loader.feed(&data[..], &config), (data.len(), Err(RequestEntityTooLarge)));
+    config.max_decoded_body_len = 13;
+    let mut loader = RequestLoader::new(None);
+    assert_eq!(loader.feed(&data[..], &config), (data.len(), Ok(true)));
+
+    let mut loader = RequestLoader::new(None);
+    let data = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: compress, chunked\r\n\r\n";
+    assert_eq!(loader.feed(data, &config).1, Err(NotImplemented));
+
+    let mut loader = RequestLoader::new(None);
+    let data = b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n";
+    assert_eq!(loader.feed(data, &config).1, Err(BadRequest));
+
+    let mut loader = RequestLoader::new(None);
+    let data = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\
+                 Transfer-Encoding: chunked\r\n\r\n";
+    assert_eq!(loader.feed(data, &config).1, Err(BadRequest));
+}
+
+#[test]
+fn test_request_loader_body_limit() {
+    use std::old_io::net::ip::Ipv4Addr;
+
+    let mut config = Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 });
+    config.max_body_len = 3;
+    let mut loader = RequestLoader::new(None);
+    let data = b"PUT / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc";
+    assert_eq!(loader.feed(data, &config), (data.len(), Ok(true)));
+
+    // A Content-Length over the limit is refused at once, without 100 Continue.
+    let mut loader = RequestLoader::new(None);
+    let data = b"PUT / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\nExpect: 100-continue\r\n\r\n";
+    assert_eq!(loader.feed(data, &config), (data.len(), Err(RequestEntityTooLarge)));
+    assert!(!loader.take_continue());
+
+    // A chunked body is refused once it passes the limit.
+    let mut loader = RequestLoader::new(None);
+    let data = b"PUT / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 2\r\nab\r\n2\r\ncd\r\n0\r\n\r\n";
+    assert_eq!(loader.feed(data, &config).1, Err(RequestEntityTooLarge));
+}
+
+#[test]
+fn test_request_loader_expect() {
+    use std::old_io::net::ip::Ipv4Addr;
+    use std::sync::Arc;
+
+    let mut config = Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 });
+    let head = b"PUT / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nExpect: 100-continue\r\n\r\n";
+    let mut loader = RequestLoader::new(None);
+    assert_eq!(loader.feed(head, &config), (head.len(), Ok(false)));
+    assert!(loader.take_continue());
+    assert!(!loader.take_continue());
+    assert_eq!(loader.feed(b"abc", &config), (3, Ok(true)));
+    assert_eq!(loader.into_request().body, b"abc".to_vec());
+
+    let mut loader = RequestLoader::new(None);
+    let data = b"PUT / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nExpect: x-magic\r\n\r\n";
+    assert_eq!(loader.feed(data, &config).1, Err(ExpectationFailed));
+
+    config.continue_hook = Some(Arc::new(Box::new(|request: &Request| {
+        match request.headers.content_length {
+            Some(len) if len > 2 => Err(RequestEntityTooLarge),
+            _ => Ok(()),
+        }
+    }) as ::server::ContinueHook));
+    let mut loader = RequestLoader::new(None);
+    assert_eq!(loader.feed(head, &config).1, Err(RequestEntityTooLarge));
+    assert!(!loader.take_continue());
+    assert!(loader.into_request().close_connection);
+}
+
+#[test]
+fn test_request_loader_limits() {
+    use std::old_io::net::ip::Ipv4Addr;
+
+    let mut config = Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 8001 });
+    config.max_request_uri_len = 10;
+    config.max_method_len = 7;
+    config.max_header_len = 20;
+    config.max_header_count = 2;
+    config.max_headers_len = 30;
+
+    macro_rules! tt {
+        ($data:expr => $expected:expr) => {{
+            let mut loader = RequestLoader::new(None);
+            assert_eq!(loader.feed($data, &config).1, $expected);
+        }}
+    }
+
+    tt!(b"GET /0123456 HTTP/1.1\r\nHost: a\r\n\r\n" => Ok(true));
+    tt!(b"GET /01234567890 HTTP/1.1\r\n" => Err(RequestUriTooLong));
+    tt!(b"GET /01234567890123456789012345" => Err(RequestUriTooLong));
+    tt!(b"PROPFIND / HTTP/1.1\r\n" => Err(BadRequest));
+    tt!(b"PROPFIND" => Err(BadRequest));
+    tt!(b"GET / HTTP/1.1\r\nX-Long: 0123456789abcdef\r\n" => Err(RequestHeaderFieldsTooLarge));
+    tt!(b"GET / HTTP/1.1\r\nX-Long: 0123456789abcdef" => Err(RequestHeaderFieldsTooLarge));
+    tt!(b"GET / HTTP/1.1\r\nHost: a\r\nA: 1\r\nB: 2\r\n" => Err(RequestHeaderFieldsTooLarge));
+    tt!(b"GET / HTTP/1.1\r\nHost: abcdefgh\r\nA: 0123456789\r\n" => Err(RequestHeaderFieldsTooLarge));
+}
+
+/// Turn a request-target into a string, percent-encoding any bytes which are not ASCII (they are
+/// not allowed in a URI, but some clients send UTF-8 anyway).
+fn request_target_to_string(target: &[u8]) -> String {
+    let mut s = String::with_capacity(target.len());
+    for &b in target.iter() {
+        if b < 0x80 {
+            s.push(b as char);
+        } else {
+            s.push_str(&format!("%{:02X}", b)[..]);
+        }
     }
+    s
 }
 
 
@@ -1,28 +1,101 @@
-use std::old_io::IoResult;
+use std::cmp::min;
+use std::old_io::{IoResult, IoError, OtherIoError, InvalidInput};
+use std::slice;
 use std::old_io::net::tcp::TcpStream;
 
 use buffer::BufferedStream;
+use coding::TransferEncoder;
 use status;
+use method::Method;
+use method::Method::{Get, Head};
 use headers::response::HeaderCollection;
 use headers::content_type::MediaType;
 use headers::transfer_encoding::TransferCoding::Chunked;
+use headers::connection::Connection::{Close, Token};
+use headers::upgrade::Protocol;
 
-/*
- * The HTTP version tag which will be used for the response.
- *
- * At present, responses will always respond with `HTTP/1.1`, as there doesn't
- * seem much value in responding HTTP/1.0 when we don't really support it.
- * Others do this too, so there's my justification.
- */
-//const RESPONSE_HTTP_VERSION: &'static str = "HTTP/1.1";
-// Maybe we could provide a response interface
+/// A transformation of a response on its way out, such as compression; see
+/// `ResponseWriter::add_filter`.
+///
+/// All the methods have defaults which leave the response as it is.
+pub trait ResponseFilter {
+    /// Adjust the status and headers just before they are written.
+    fn filter_headers(&mut self, _status: &mut status::Status, _headers: &mut HeaderCollection) {
+    }
+
+    /// Transform a piece of the body, appending the result to `out`; a filter may hold data back
+    /// until later.
+    fn filter_body(&mut self, data: &[u8], out: &mut Vec<u8>) -> IoResult<()> {
+        out.push_all(data);
+        Ok(())
+    }
+
+    /// The body is complete: append anything still held back to `out`.
+    fn finish(&mut self, _out: &mut Vec<u8>) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+/// A connection which has been switched from HTTP to another protocol; see
+/// `ResponseWriter::upgrade`.
+///
+/// Reading gives anything the client had already sent after the request first.
+pub struct Upgraded {
+    /// The connection itself (another handle to the socket the request came in on).
+    pub stream: TcpStream,
+    /// What had already been received after the request, which is yet to be read.
+    pub leftover: Vec<u8>,
+}
+
+impl Reader for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.leftover.is_empty() {
+            return self.stream.read(buf);
+        }
+        let amount = min(self.leftover.len(), buf.len());
+        slice::bytes::copy_memory(buf, &self.leftover[..amount]);
+        self.leftover = self.leftover[amount..].to_vec();
+        Ok(amount)
+    }
+}
+
+impl Writer for Upgraded {
+    fn write_all(&mut self, buf: &[u8]) -> IoResult<()> {
+        self.stream.write_all(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.stream.flush()
+    }
+}
 
 pub struct ResponseWriter<'a> {
     // The place to write to (typically a TCP stream, io::net::tcp::TcpStream)
     writer: &'a mut BufferedStream<TcpStream>,
     headers_written: bool,
+    filters: Vec<Box<ResponseFilter + Send>>,
+    /// The transfer-codings other than chunked being applied to the body, if any.
+    transfer_encoder: Option<TransferEncoder>,
     pub headers: HeaderCollection,
     pub status: status::Status,
+    /// The HTTP version of the request being responded to. The response is of the same version
+    /// (HTTP/1.0 or HTTP/1.1); chunked and other transfer-codings are only ever sent to HTTP/1.1
+    /// peers.
+    pub request_version: (usize, usize),
+    /// Whether the connection is to be closed after this response. The server sets this from the
+    /// request; `write_headers` sets it too if the end of the body can only be marked by closing
+    /// the connection, as for an HTTP/1.0 response without a Content-Length.
+    pub close_connection: bool,
+    /// The method of the request being responded to. The response to a HEAD request has the
+    /// headers the GET would have had, but anything written as its body is discarded.
+    pub request_method: Method,
+}
+
+/// Whether a response with the status can have a body at all: 1xx, 204 and 304 responses cannot,
+/// and have no framing either (RFC 7230 §3.3.3).
+fn status_allows_body(status: &status::Status) -> bool {
+    let code = status.code();
+    code >= 200 && code != 204 && code != 304
 }
 
 impl<'a> ResponseWriter<'a> {
@@ -31,8 +104,13 @@ impl<'a> ResponseWriter<'a> {
         ResponseWriter {
             writer: writer,
             headers_written: false,
+            filters: Vec::new(),
+            transfer_encoder: None,
             headers: HeaderCollection::new(),
             status: status::Status::Ok,
+            request_version: (1, 1),
+            close_connection: false,
+            request_method: Get,
         }
     }
 
@@ -46,6 +124,54 @@ impl<'a> ResponseWriter<'a> {
         self.write_all(cbytes)
     }
 
+    /// Pass the response through a filter before it is written.
+    ///
+    /// A filter added later sees the response first: in a stack of middleware, the filter of an
+    /// inner layer transforms what the handler writes, and an outer one transforms the result.
+    ///
+    /// If the headers have already been written, this will fail.
+    pub fn add_filter(&mut self, filter: Box<ResponseFilter + Send>) {
+        if self.headers_written {
+            panic!("ResponseWriter.add_filter() called, but headers already written");
+        }
+        self.filters.push(filter);
+    }
+
+    /// Switch the connection to another protocol: answer `101 Switching Protocols`, with the
+    /// protocol in the Upgrade header (and any other headers already set), and hand over the
+    /// connection. Once the handler is done with the request, the server leaves the connection
+    /// alone; it is closed when the `Upgraded` is dropped.
+    ///
+    /// This fails if the request was HTTP/1.0, which has no upgrading. If the headers have already
+    /// been written, this will panic.
+    pub fn upgrade(&mut self, protocol: Protocol) -> IoResult<Upgraded> {
+        if self.headers_written {
+            panic!("ResponseWriter.upgrade() called, but headers already written");
+        }
+        if self.request_version < (1, 1) {
+            return Err(IoError {
+                kind: InvalidInput,
+                desc: "an HTTP/1.0 connection cannot be upgraded",
+                detail: None,
+            });
+        }
+        self.status = status::Status::SwitchingProtocols;
+        self.headers.upgrade = Some(vec!(protocol));
+        self.headers.connection = Some(vec!(Token(String::from_str("Upgrade"))));
+        self.headers.content_length = None;
+        self.close_connection = false;
+        try!(self.write_headers());
+        try!(self.writer.flush());
+        // No more HTTP on this connection.
+        self.close_connection = true;
+        let leftover = self.writer.buffered().to_vec();
+        self.writer.consume(leftover.len());
+        Ok(Upgraded {
+            stream: self.writer.wrapped.clone(),
+            leftover: leftover,
+        })
+    }
+
     /// Write the Status-Line and headers of the response, if we have not already done so.
     pub fn try_write_headers(&mut self) -> IoResult<()> {
         if !self.headers_written {
@@ -59,7 +185,17 @@ impl<'a> ResponseWriter<'a> {
     ///
     /// This also overrides the value of the Transfer-Encoding header
     /// (``self.headers.transfer_encoding``), ensuring it is ``None`` if the Content-Length header
-    /// has been specified, or to ``chunked`` if it has not, thus switching to the chunked coding.
+    /// has been specified, or ends in ``chunked`` if it has not, thus switching to the chunked
+    /// coding. Any other transfer-codings listed there (gzip or deflate) are applied to the body in
+    /// order, in which case the Content-Length is dropped; they are dropped instead if the request
+    /// was HTTP/1.0, and any other coding makes this fail.
+    ///
+    /// An HTTP/1.0 client cannot take the chunked coding, so a response to one without a
+    /// Content-Length is ended by closing the connection. The Connection header is set to say
+    /// whether the connection will be kept alive.
+    ///
+    /// A 1xx, 204 or 304 response has no body, so it gets neither Transfer-Encoding nor (except
+    /// for 304, where it describes the resource) Content-Length.
     ///
     /// If the headers have already been written, this will fail. See also `try_write_headers`.
     pub fn write_headers(&mut self) -> IoResult<()> {
@@ -67,27 +203,64 @@ impl<'a> ResponseWriter<'a> {
         if self.headers_written {
             panic!("ResponseWriter.write_headers() called, but headers already written");
         }
+        for filter in self.filters.iter_mut().rev() {
+            filter.filter_headers(&mut self.status, &mut self.headers);
+        }
+
+        // Chunked MUST come last (RFC 7230 §3.3.1); whatever else is asked for goes before it.
+        let codings: Vec<_> = match self.headers.transfer_encoding.take() {
+            Some(codings) => codings.into_iter().filter(|coding| *coding != Chunked).collect(),
+            None => Vec::new(),
+        };
+        let allows_body = status_allows_body(&self.status);
+        if !codings.is_empty() && allows_body && self.request_version >= (1, 1) {
+            match TransferEncoder::new(&codings[..]) {
+                Some(encoder) => self.transfer_encoder = Some(encoder),
+                None => return Err(IoError {
+                    kind: OtherIoError,
+                    desc: "unsupported transfer-coding",
+                    detail: None,
+                }),
+            }
+            self.headers.content_length = None;
+        }
 
         // Write the Status-Line (RFC2616 §6.1)
-        // XXX: might be better not to hardcode HTTP/1.1.
+        let http11 = self.request_version >= (1, 1);
         // XXX: Rust's current lack of statement-duration lifetime handling prevents this from being
         // one statement ("error: borrowed value does not live long enough")
-        let s = format!("HTTP/1.1 {:?}\r\n", self.status);
+        let s = format!("HTTP/1.{} {:?}\r\n", if http11 { 1 } else { 0 }, self.status);
         try!(self.writer.write_all(s.as_bytes()));
 
-        // FIXME: this is not an impressive way of handling it, but so long as chunked is the only
-        // transfer-coding we want to deal with it's tolerable. However, it is *meant* to be an
-        // extensible thing, whereby client and server could agree upon extra transformations to
-        // apply. In such a case, chunked MUST come last. This way prevents it from being extensible
-        // thus, which is suboptimal.
-        if self.headers.content_length == None {
-            self.headers.transfer_encoding = Some(vec!(Chunked));
+        if !allows_body && self.status.code() != 304 {
+            self.headers.content_length = None;
+        }
+        let chunked = allows_body && http11 && self.headers.content_length == None;
+        if chunked {
+            let mut codings = if self.transfer_encoder.is_some() { codings } else { Vec::new() };
+            codings.push(Chunked);
+            self.headers.transfer_encoding = Some(codings);
         } else {
             self.headers.transfer_encoding = None;
         }
+        let handler_closes = match self.headers.connection {
+            Some(ref connection) => connection.contains(&Close),
+            None => false,
+        };
+        // Without a length, the body of an HTTP/1.0 response runs until the connection is closed.
+        if handler_closes || self.sends_body() && !http11 && self.headers.content_length == None {
+            self.close_connection = true;
+        }
+        if self.close_connection {
+            self.headers.connection = Some(vec!(Close));
+        } else if !http11 {
+            // HTTP/1.0 connections are only kept alive if both ends say so.
+            self.headers.connection = Some(vec!(Token(String::from_str("Keep-Alive"))));
+        }
         try!(self.headers.write_all(&mut *self.writer));
         self.headers_written = true;
-        if self.headers.content_length == None {
+        // The headers of a response to HEAD are as for GET, but the body is not actually sent.
+        if chunked && self.request_method != Head {
             // Flush so that the chunked body stuff can start working correctly. TODO: don't
             // actually flush it entirely, or else it'll send the headers in a separate TCP packet,
             // which is bad for performance.
@@ -97,11 +270,74 @@ impl<'a> ResponseWriter<'a> {
         Ok(())
     }
 
+    /// Write body data which has been through the filters before `filters[end]`.
+    fn write_filtered(&mut self, end: usize, data: &[u8]) -> IoResult<()> {
+        if end == 0 {
+            return self.write_coded(data);
+        }
+        let mut data = data.to_vec();
+        for filter in self.filters[..end].iter_mut().rev() {
+            let mut out = Vec::new();
+            try!(filter.filter_body(&data[..], &mut out));
+            data = out;
+        }
+        if data.is_empty() {
+            Ok(())
+        } else {
+            self.write_coded(&data[..])
+        }
+    }
+
+    /// Whether anything written as the body is actually sent.
+    fn sends_body(&self) -> bool {
+        self.request_method != Head && status_allows_body(&self.status)
+    }
+
+    /// Write body data which has been through all the filters, applying the transfer-codings.
+    fn write_coded(&mut self, data: &[u8]) -> IoResult<()> {
+        if !self.sends_body() {
+            return Ok(());
+        }
+        match self.transfer_encoder {
+            Some(ref mut encoder) => {
+                let mut out = Vec::new();
+                try!(encoder.encode(data, &mut out));
+                if out.is_empty() {
+                    Ok(())
+                } else {
+                    self.writer.write_all(&out[..])
+                }
+            },
+            None => self.writer.write_all(data),
+        }
+    }
+
+    /// Complete the response, ending any chunked body, and send it on its way.
     pub fn finish_response(&mut self) -> IoResult<()> {
-        try!(self.writer.finish_response());
-        // Ensure that we switch away from chunked in case another request comes on the same socket
-        self.writer.writing_chunked_body = false;
-        Ok(())
+        try!(self.end_response());
+        self.writer.flush()
+    }
+
+    /// Complete the response as `finish_response` does, but leave the last of it in the write
+    /// buffer to go out with the next response (or when flushed), as when answering pipelined
+    /// requests.
+    pub fn end_response(&mut self) -> IoResult<()> {
+        // Let each filter have its last say, from the innermost out.
+        for i in (0..self.filters.len()).rev() {
+            let mut out = Vec::new();
+            try!(self.filters[i].finish(&mut out));
+            try!(self.write_filtered(i, &out[..]));
+        }
+        self.filters.clear();
+        if let Some(encoder) = self.transfer_encoder.take() {
+            let mut out = Vec::new();
+            try!(encoder.finish(&mut out));
+            if self.sends_body() {
+                try!(self.writer.write_all(&out[..]));
+            }
+        }
+        // This also switches away from chunked in case another request comes on the same socket.
+        self.writer.end_response()
     }
 }
 
@@ -111,7 +347,8 @@ impl<'a> Writer for ResponseWriter<'a> {
         if !self.headers_written {
             try!(self.write_headers());
         }
-        self.writer.write_all(buf)
+        let filters = self.filters.len();
+        self.write_filtered(filters, buf)
     }
 
     fn flush(&mut self) -> IoResult<()> {
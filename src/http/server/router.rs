@@ -0,0 +1,439 @@
+//! A `Server` which dispatches requests to handlers by method and path.
+//!
+//! ```ignore
+//! let mut router = Router::new(Config::new(address));
+//! router.get("/", |_, _, w| w.write_all(b"Home").unwrap());
+//! router.get("/users/:id", |_, params, w| {
+//!     write!(w, "User {}", params.get("id").unwrap()).unwrap();
+//! });
+//! router.get("/static/*path", serve_static);
+//! router.serve_forever();
+//! ```
+//!
+//! A pattern is a path whose segments are literals, named parameters (`:name`, matching any one
+//! non-empty segment) or, as the last segment, a wildcard (`*name`, matching the rest of the
+//! path, slashes and all). A wildcard may match nothing, but the slash before it must be there:
+//! "/static/*path" matches "/static/" but not "/static". Segments are matched after
+//! percent-decoding. Routes are tried in the order they were added.
+//!
+//! Paths which match no route get `404 Not Found`; paths which match only routes for other
+//! methods get `405 Method Not Allowed` with an Allow header. HEAD requests go to GET routes
+//! unless there is a HEAD route, and OPTIONS requests are answered with the allowed methods
+//! unless there is an OPTIONS route.
+
+use std::sync::Arc;
+
+use method::Method;
+use method::Method::{Get, Head, Options};
+use server::{Server, Config, Request, ResponseWriter};
+use server::request::RequestUri::{Star, AbsolutePath};
+use status::Status::{NotFound, MethodNotAllowed, MovedPermanently, BadRequest};
+
+/// A request handler; it is given the parameters which the route's pattern matched.
+pub type Handler = Box<Fn(Request, &Params, &mut ResponseWriter) + Send + Sync>;
+
+/// What to do with a path which matches a route only if a trailing slash is added or removed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TrailingSlash {
+    /// Treat it as matching nothing: "/users/" and "/users" are different paths.
+    Strict,
+    /// Treat it as matching.
+    Ignore,
+    /// Redirect (with `301 Moved Permanently`) to the path which matches.
+    Redirect,
+}
+
+/// The values of the named parameters and wildcard of a matched pattern.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Params {
+    params: Vec<(String, String)>,
+}
+
+impl Params {
+    fn new() -> Params {
+        Params {
+            params: Vec::new(),
+        }
+    }
+
+    /// The value of the named parameter or wildcard, percent-decoded.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|&&(ref n, _)| &n[..] == name)
+            .map(|&(_, ref v)| &v[..])
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+/// Parse a pattern, failing if it is not a valid one (which is a programming error).
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    if !pattern.starts_with("/") {
+        panic!("route pattern {:?} does not begin with \"/\"", pattern);
+    }
+    let parts: Vec<&str> = pattern[1..].split('/').collect();
+    let last = parts.len() - 1;
+    parts.iter().enumerate().map(|(i, &part)| {
+        if part.starts_with(":") && part.len() > 1 {
+            Segment::Param(String::from_str(&part[1..]))
+        } else if part.starts_with("*") {
+            if i != last {
+                panic!("route pattern {:?} has a wildcard before its end", pattern);
+            }
+            Segment::Wildcard(String::from_str(&part[1..]))
+        } else {
+            Segment::Literal(String::from_str(part))
+        }
+    }).collect()
+}
+
+/// Match a path, as decoded segments, against a pattern.
+fn match_pattern(pattern: &[Segment], path: &[String]) -> Option<Params> {
+    let mut params = Params::new();
+    for (i, segment) in pattern.iter().enumerate() {
+        match *segment {
+            Segment::Literal(ref literal) => match path.get(i) {
+                Some(s) if s == literal => (),
+                _ => return None,
+            },
+            Segment::Param(ref name) => match path.get(i) {
+                Some(s) if !s.is_empty() => params.params.push((name.clone(), s.clone())),
+                _ => return None,
+            },
+            Segment::Wildcard(ref name) => {
+                if i >= path.len() {
+                    return None;
+                }
+                params.params.push((name.clone(), path[i..].connect("/")));
+                return Some(params);
+            },
+        }
+    }
+    if pattern.len() == path.len() {
+        Some(params)
+    } else {
+        None
+    }
+}
+
+/// The path with a trailing slash removed if it has one (other than "/"), or added if not.
+fn toggle_trailing_slash(path: &[String]) -> Vec<String> {
+    let mut toggled = path.to_vec();
+    if toggled.len() > 1 && toggled.last().map_or(false, |s| s.is_empty()) {
+        toggled.pop();
+    } else {
+        toggled.push(String::new());
+    }
+    toggled
+}
+
+struct Route {
+    method: Method,
+    pattern: Vec<Segment>,
+    handler: Handler,
+}
+
+/// What the router decided to do with a request.
+#[derive(PartialEq, Eq, Debug)]
+enum Dispatch {
+    /// Call the handler of the route with this index.
+    Route(usize, Params),
+    /// Answer an OPTIONS request with these methods.
+    Options(Vec<Method>),
+    /// Answer `405 Method Not Allowed`, allowing these methods.
+    NotAllowed(Vec<Method>),
+    /// Redirect to the path with its trailing slash toggled.
+    Redirect,
+    NotFound,
+}
+
+/// A `Server` which passes each request to the handler of the first route to match it.
+#[derive(Clone)]
+pub struct Router {
+    config: Config,
+    routes: Vec<Arc<Route>>,
+
+    /// What to do about trailing slashes; `Strict` by default.
+    pub trailing_slash: TrailingSlash,
+
+    /// The scheme which clients use to reach the server, for the `Location` of redirects; "http"
+    /// by default, or "https" if the server is behind something which handles TLS for it.
+    pub scheme: String,
+}
+
+impl Router {
+    pub fn new(config: Config) -> Router {
+        Router {
+            config: config,
+            routes: Vec::new(),
+            trailing_slash: TrailingSlash::Strict,
+            scheme: String::from_str("http"),
+        }
+    }
+
+    /// Add a route for requests with the given method and a path matching the pattern.
+    ///
+    /// Panics if the pattern is not valid: it must begin with "/" and may only have a wildcard
+    /// as its last segment.
+    pub fn route<F>(&mut self, method: Method, pattern: &str, handler: F)
+            where F: Fn(Request, &Params, &mut ResponseWriter) + Send + Sync + 'static {
+        self.routes.push(Arc::new(Route {
+            method: method,
+            pattern: parse_pattern(pattern),
+            handler: Box::new(handler),
+        }));
+    }
+
+    pub fn get<F>(&mut self, pattern: &str, handler: F)
+            where F: Fn(Request, &Params, &mut ResponseWriter) + Send + Sync + 'static {
+        self.route(Get, pattern, handler)
+    }
+
+    pub fn post<F>(&mut self, pattern: &str, handler: F)
+            where F: Fn(Request, &Params, &mut ResponseWriter) + Send + Sync + 'static {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<F>(&mut self, pattern: &str, handler: F)
+            where F: Fn(Request, &Params, &mut ResponseWriter) + Send + Sync + 'static {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<F>(&mut self, pattern: &str, handler: F)
+            where F: Fn(Request, &Params, &mut ResponseWriter) + Send + Sync + 'static {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Find what to do with a request for the path (as decoded segments), or with `OPTIONS *` if
+    /// `path` is `None`.
+    fn dispatch(&self, method: &Method, path: Option<&[String]>) -> Dispatch {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                // The methods of all the routes.
+                let mut allowed = Vec::new();
+                for route in self.routes.iter() {
+                    add_allowed(&mut allowed, &route.method);
+                }
+                add_allowed(&mut allowed, &Options);
+                return Dispatch::Options(allowed);
+            },
+        };
+        let trimmed;
+        let path = if self.trailing_slash == TrailingSlash::Ignore &&
+                      path.len() > 1 && path.last().map_or(false, |s| s.is_empty()) {
+            trimmed = path[..path.len() - 1].to_vec();
+            &trimmed[..]
+        } else {
+            path
+        };
+
+        let mut allowed = Vec::new();
+        let mut head_fallback = None;
+        for (i, route) in self.routes.iter().enumerate() {
+            let params = match self.match_route(route, path) {
+                Some(params) => params,
+                None => continue,
+            };
+            if route.method == *method {
+                return Dispatch::Route(i, params);
+            }
+            if *method == Head && route.method == Get && head_fallback.is_none() {
+                head_fallback = Some((i, params));
+            }
+            add_allowed(&mut allowed, &route.method);
+        }
+        match head_fallback {
+            Some((i, params)) => return Dispatch::Route(i, params),
+            None => (),
+        }
+        if !allowed.is_empty() {
+            add_allowed(&mut allowed, &Options);
+            return if *method == Options {
+                Dispatch::Options(allowed)
+            } else {
+                Dispatch::NotAllowed(allowed)
+            };
+        }
+
+        if self.trailing_slash == TrailingSlash::Redirect {
+            let toggled = toggle_trailing_slash(path);
+            if self.routes.iter().any(|route| self.match_route(route, &toggled[..]).is_some()) {
+                return Dispatch::Redirect;
+            }
+        }
+        Dispatch::NotFound
+    }
+
+    fn match_route(&self, route: &Route, path: &[String]) -> Option<Params> {
+        if self.trailing_slash == TrailingSlash::Ignore && route.pattern.len() > 1 &&
+                route.pattern.last() == Some(&Segment::Literal(String::new())) {
+            match_pattern(&route.pattern[..route.pattern.len() - 1], path)
+        } else {
+            match_pattern(&route.pattern[..], path)
+        }
+    }
+}
+
+/// Add a method to the Allow list, along with HEAD for GET.
+fn add_allowed(allowed: &mut Vec<Method>, method: &Method) {
+    if !allowed.contains(method) {
+        allowed.push(method.clone());
+    }
+    if *method == Get && !allowed.contains(&Head) {
+        allowed.push(Head);
+    }
+}
+
+impl Server for Router {
+    fn get_config(&self) -> Config {
+        self.config.clone()
+    }
+
+    fn handle_request(&self, request: Request, w: &mut ResponseWriter) {
+        let path = request.path();
+        let segments = match (&request.request_uri, &path) {
+            (&Star, _) if request.method == Options => None,
+            (_, &Some(ref path)) => match path.decoded_segments() {
+                Some(segments) => Some(segments),
+                None => {
+                    w.status = BadRequest;
+                    w.headers.content_length = Some(0);
+                    return;
+                },
+            },
+            (_, &None) => {
+                w.status = NotFound;
+                w.headers.content_length = Some(0);
+                return;
+            },
+        };
+
+        let dispatch = self.dispatch(&request.method, segments.as_ref().map(|s| &s[..]));
+        match dispatch {
+            Dispatch::Route(i, params) => (*self.routes[i].handler)(request, &params, w),
+            Dispatch::Options(allowed) => {
+                w.headers.allow = Some(allowed);
+                w.headers.content_length = Some(0);
+            },
+            Dispatch::NotAllowed(allowed) => {
+                w.status = MethodNotAllowed;
+                w.headers.allow = Some(allowed);
+                w.headers.content_length = Some(0);
+            },
+            Dispatch::Redirect => {
+                let mut target = path.unwrap();
+                if target.path.len() > 1 && target.path.ends_with("/") {
+                    target.path.pop();
+                } else {
+                    target.path.push('/');
+                }
+                let mut request = request;
+                request.request_uri = AbsolutePath(target);
+                // Without a Host header there is nowhere absolute to send it.
+                match request.effective_url(&self.scheme[..]) {
+                    Some(url) => {
+                        w.status = MovedPermanently;
+                        w.headers.location = Some(url);
+                    },
+                    None => w.status = NotFound,
+                }
+                w.headers.content_length = Some(0);
+            },
+            Dispatch::NotFound => {
+                w.status = NotFound;
+                w.headers.content_length = Some(0);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::old_io::net::ip::{SocketAddr, Ipv4Addr};
+    use method::Method::{Get, Head, Post, Put, Options};
+    use server::Config;
+    use super::{Router, TrailingSlash, Dispatch, Params, parse_pattern, match_pattern};
+
+    fn segments(path: &str) -> Vec<String> {
+        path[1..].split('/').map(String::from_str).collect()
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        Params {
+            params: pairs.iter().map(|&(n, v)| (String::from_str(n), String::from_str(v))).collect(),
+        }
+    }
+
+    fn router() -> Router {
+        let mut router = Router::new(Config::new(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1),
+                                                              port: 8001 }));
+        router.get("/", |_, _, _| ());
+        router.get("/users/:id", |_, _, _| ());
+        router.put("/users/:id", |_, _, _| ());
+        router.get("/files/*path", |_, _, _| ());
+        router.post("/forms/", |_, _, _| ());
+        router
+    }
+
+    #[test]
+    fn test_match_pattern() {
+        let pattern = parse_pattern("/users/:id/files/*rest");
+        assert_eq!(match_pattern(&pattern[..], &segments("/users/7/files/a/b.txt")[..]),
+                   Some(params(&[("id", "7"), ("rest", "a/b.txt")])));
+        assert_eq!(match_pattern(&pattern[..], &segments("/users/7/files")[..]), None);
+        assert_eq!(match_pattern(&pattern[..], &segments("/users//files/x")[..]), None);
+        assert_eq!(match_pattern(&pattern[..], &segments("/users/7/files/")[..]),
+                   Some(params(&[("id", "7"), ("rest", "")])));
+        let pattern = parse_pattern("/");
+        assert_eq!(match_pattern(&pattern[..], &segments("/")[..]), Some(params(&[])));
+        assert_eq!(match_pattern(&pattern[..], &segments("/x")[..]), None);
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let router = router();
+        assert_eq!(router.dispatch(&Get, Some(&segments("/users/42")[..])),
+                   Dispatch::Route(1, params(&[("id", "42")])));
+        assert_eq!(router.dispatch(&Put, Some(&segments("/users/42")[..])),
+                   Dispatch::Route(2, params(&[("id", "42")])));
+        // HEAD goes to GET.
+        assert_eq!(router.dispatch(&Head, Some(&segments("/")[..])),
+                   Dispatch::Route(0, params(&[])));
+        assert_eq!(router.dispatch(&Post, Some(&segments("/users/42")[..])),
+                   Dispatch::NotAllowed(vec!(Get, Head, Put, Options)));
+        assert_eq!(router.dispatch(&Options, Some(&segments("/users/42")[..])),
+                   Dispatch::Options(vec!(Get, Head, Put, Options)));
+        assert_eq!(router.dispatch(&Options, None),
+                   Dispatch::Options(vec!(Get, Head, Put, Post, Options)));
+        assert_eq!(router.dispatch(&Get, Some(&segments("/nowhere")[..])), Dispatch::NotFound);
+    }
+
+    #[test]
+    fn test_trailing_slash() {
+        let mut router = router();
+        assert_eq!(router.dispatch(&Post, Some(&segments("/forms")[..])), Dispatch::NotFound);
+        assert_eq!(router.dispatch(&Get, Some(&segments("/users/42/")[..])), Dispatch::NotFound);
+
+        router.trailing_slash = TrailingSlash::Ignore;
+        assert_eq!(router.dispatch(&Post, Some(&segments("/forms")[..])),
+                   Dispatch::Route(4, params(&[])));
+        assert_eq!(router.dispatch(&Get, Some(&segments("/users/42/")[..])),
+                   Dispatch::Route(1, params(&[("id", "42")])));
+        assert_eq!(router.dispatch(&Get, Some(&segments("/")[..])),
+                   Dispatch::Route(0, params(&[])));
+
+        router.trailing_slash = TrailingSlash::Redirect;
+        assert_eq!(router.dispatch(&Post, Some(&segments("/forms")[..])), Dispatch::Redirect);
+        assert_eq!(router.dispatch(&Get, Some(&segments("/users/42/")[..])), Dispatch::Redirect);
+    }
+}
@@ -0,0 +1,447 @@
+//! A `Server` which serves the files of a directory.
+//!
+//! ```ignore
+//! let mut files = StaticFiles::new(Config::new(address), "/static", Path::new("public"));
+//! files.listings = true;
+//! files.serve_forever();
+//! ```
+//!
+//! Each file is sent with a Content-Type chosen by its extension, a Last-Modified date and an
+//! ETag made from its size and modification time. Conditional requests are answered with
+//! `304 Not Modified` or `412 Precondition Failed` as appropriate, and a single byte range may be
+//! requested with the Range header (and If-Range).
+//!
+//! A `StaticFiles` can also be used from a `Router` route with a wildcard, by way of `serve`.
+
+use std::ascii::AsciiExt;
+use std::cmp::min;
+use std::old_io::{File, FileType, IoResult, SeekStyle};
+use std::old_io::fs::{self, PathExtensions};
+use time;
+use time::Timespec;
+
+use headers::accept_ranges::{RangeUnits, Bytes};
+use headers::content_type::MediaType;
+use headers::etag::{EntityTag, strong_etag};
+use method::Method::{Get, Head};
+use server::{Server, Config, Request, ResponseWriter};
+use server::conditional::{Precondition, range_applies};
+use status::Status::{NotFound, Forbidden, MethodNotAllowed, MovedPermanently, NotModified,
+                     PartialContent, RequestedRangeNotSatisfiable, PreconditionFailed,
+                     InternalServerError};
+
+const COPY_BUFFER_LEN: usize = 0x10000;
+
+/// A `Server` which maps paths under a prefix to the files of a directory.
+#[derive(Clone)]
+pub struct StaticFiles {
+    config: Config,
+    /// The decoded segments of the prefix, without the trailing empty one.
+    prefix: Vec<String>,
+    root: Path,
+
+    /// Whether to list the contents of directories which have no `index.html`; if not, they are
+    /// `403 Forbidden`. Off by default.
+    pub listings: bool,
+
+    /// The scheme which clients use to reach the server, for the `Location` of redirects; "http"
+    /// by default, or "https" if the server is behind something which handles TLS for it.
+    pub scheme: String,
+}
+
+impl StaticFiles {
+    /// Serve the files under `root` at the paths under `prefix`: with a prefix of "/static",
+    /// "/static/css/site.css" is the file `root/css/site.css`.
+    pub fn new(config: Config, prefix: &str, root: Path) -> StaticFiles {
+        StaticFiles {
+            config: config,
+            prefix: prefix.split('/').filter(|s| !s.is_empty()).map(String::from_str).collect(),
+            root: root,
+            listings: false,
+            scheme: String::from_str("http"),
+        }
+    }
+
+    /// Serve the file at `path` (decoded segments, relative to the root), as with a `Router`
+    /// wildcard split on "/". Paths which try to climb out of the root are not found.
+    pub fn serve(&self, request: &Request, path: &[String], w: &mut ResponseWriter) {
+        if request.method != Get && request.method != Head {
+            w.status = MethodNotAllowed;
+            w.headers.allow = Some(vec!(Get, Head));
+            w.headers.content_length = Some(0);
+            return;
+        }
+        let file_path = match resolve(&self.root, path) {
+            Some(file_path) => file_path,
+            None => return not_found(w),
+        };
+        let stat = match file_path.stat() {
+            Ok(stat) => stat,
+            Err(_) => return not_found(w),
+        };
+
+        if stat.kind == FileType::Directory {
+            // Relative links from the directory (as in listings) need its path to end in "/".
+            if path.last().map_or(true, |s| !s.is_empty()) {
+                return redirect_to_directory(request, &self.scheme[..], w);
+            }
+            let index = file_path.join("index.html");
+            match index.stat() {
+                Ok(ref stat) if stat.kind == FileType::RegularFile =>
+                    return send_file(request, &index, stat.size, stat.modified, w),
+                _ => (),
+            }
+            if !self.listings {
+                w.status = Forbidden;
+                w.headers.content_length = Some(0);
+                return;
+            }
+            return match fs::readdir(&file_path) {
+                Ok(entries) => send_listing(request, path, entries, w),
+                Err(err) => {
+                    error!("listing {} failed: {}", file_path.display(), err);
+                    w.status = InternalServerError;
+                    w.headers.content_length = Some(0);
+                },
+            };
+        }
+        send_file(request, &file_path, stat.size, stat.modified, w)
+    }
+}
+
+impl Server for StaticFiles {
+    fn get_config(&self) -> Config {
+        self.config.clone()
+    }
+
+    fn handle_request(&self, request: Request, w: &mut ResponseWriter) {
+        let segments = match request.path().and_then(|path| path.decoded_segments()) {
+            Some(segments) => segments,
+            None => return not_found(w),
+        };
+        if segments.len() < self.prefix.len() || segments[..self.prefix.len()] != self.prefix[..] {
+            return not_found(w);
+        }
+        self.serve(&request, &segments[self.prefix.len()..], w)
+    }
+}
+
+fn not_found(w: &mut ResponseWriter) {
+    w.status = NotFound;
+    w.headers.content_length = Some(0);
+}
+
+/// The file for the segments of a path, or `None` if a segment would escape the root (or could
+/// not be a file name).
+fn resolve(root: &Path, path: &[String]) -> Option<Path> {
+    let mut file_path = root.clone();
+    for segment in path.iter() {
+        match &segment[..] {
+            "" | "." => (),
+            ".." => return None,
+            s if s.contains("/") || s.contains("\\") || s.contains("\0") => return None,
+            s => file_path.push(s),
+        }
+    }
+    Some(file_path)
+}
+
+/// Redirect to the path of the request with a "/" added.
+fn redirect_to_directory(request: &Request, scheme: &str, w: &mut ResponseWriter) {
+    // Without a Host header there is nowhere absolute to send it.
+    let location = request.effective_url(scheme).and_then(|mut url| {
+        match url.path_mut() {
+            Some(path) => path.push(String::new()),
+            None => return None,
+        }
+        Some(url)
+    });
+    match location {
+        Some(location) => {
+            w.status = MovedPermanently;
+            w.headers.location = Some(location);
+        },
+        None => w.status = NotFound,
+    }
+    w.headers.content_length = Some(0);
+}
+
+/// The Content-Type of a file, by its extension.
+pub fn content_type_for(path: &Path) -> MediaType {
+    let extension = path.extension_str().map(|e| e.to_ascii_lowercase());
+    let (type_, subtype) = match extension.as_ref().map(|e| &e[..]) {
+        Some("html") | Some("htm") => ("text", "html"),
+        Some("css") => ("text", "css"),
+        Some("js") => ("application", "javascript"),
+        Some("json") => ("application", "json"),
+        Some("txt") => ("text", "plain"),
+        Some("csv") => ("text", "csv"),
+        Some("xml") => ("application", "xml"),
+        Some("svg") => ("image", "svg+xml"),
+        Some("png") => ("image", "png"),
+        Some("jpg") | Some("jpeg") => ("image", "jpeg"),
+        Some("gif") => ("image", "gif"),
+        Some("ico") => ("image", "x-icon"),
+        Some("webp") => ("image", "webp"),
+        Some("woff") => ("font", "woff"),
+        Some("woff2") => ("font", "woff2"),
+        Some("pdf") => ("application", "pdf"),
+        Some("zip") => ("application", "zip"),
+        Some("gz") => ("application", "gzip"),
+        Some("wasm") => ("application", "wasm"),
+        Some("mp3") => ("audio", "mpeg"),
+        Some("mp4") => ("video", "mp4"),
+        _ => ("application", "octet-stream"),
+    };
+    let parameters = if type_ == "text" || subtype == "javascript" || subtype == "json" {
+        vec!((String::from_str("charset"), String::from_str("UTF-8")))
+    } else {
+        Vec::new()
+    };
+    MediaType::new(String::from_str(type_), String::from_str(subtype), parameters)
+}
+
+/// The ETag of a file of the given size and modification time (in milliseconds).
+fn etag_for(size: u64, modified: u64) -> EntityTag {
+    strong_etag(format!("{:x}-{:x}", size, modified))
+}
+
+/// The first and last byte of the range of a Range header, if it is a single satisfiable range
+/// of bytes: `Some(Ok(..))`; `Some(Err(()))` if it is unsatisfiable; `None` if it is not a kind of
+/// range served (which means the whole file is sent).
+fn byte_range(range: &str, size: u64) -> Option<Result<(u64, u64), ()>> {
+    let range = range.trim();
+    if !range.starts_with("bytes=") || range.contains(',') {
+        return None;
+    }
+    let spec = range["bytes=".len()..].trim();
+    let dash = match spec.find('-') {
+        Some(dash) => dash,
+        None => return None,
+    };
+    let (first, last) = (spec[..dash].trim(), spec[dash + 1..].trim());
+    let parsed = if first.is_empty() {
+        // A suffix: the last so many bytes.
+        match last.parse::<u64>() {
+            Ok(0) => return Some(Err(())),
+            Ok(len) if size > 0 => Ok((size - min(len, size), size - 1)),
+            Ok(_) => Err(()),
+            Err(_) => return None,
+        }
+    } else {
+        match (first.parse::<u64>(), last.parse::<u64>()) {
+            (Ok(first), _) if first >= size => Err(()),
+            (Ok(first), Ok(last)) if last >= first => Ok((first, min(last, size - 1))),
+            (Ok(first), Err(_)) if last.is_empty() => Ok((first, size - 1)),
+            _ => return None,
+        }
+    };
+    Some(parsed)
+}
+
+fn send_file(request: &Request, file_path: &Path, size: u64, modified: u64,
+             w: &mut ResponseWriter) {
+    let etag = etag_for(size, modified);
+    let last_modified = time::at_utc(Timespec::new((modified / 1000) as i64, 0));
+    w.headers.etag = Some(etag.clone());
+    w.headers.last_modified = Some(last_modified.clone());
+    w.headers.accept_ranges = Some(RangeUnits(vec!(Bytes)));
+    w.headers.content_type = Some(content_type_for(file_path));
+    w.headers.content_length = Some(size as usize);
+
+    match request.preconditions(Some(&etag), Some(&last_modified)) {
+        Precondition::Proceed => (),
+        Precondition::NotModified => {
+            // Content-Length is that of the representation which would have been sent.
+            w.status = NotModified;
+            w.headers.content_type = None;
+            return;
+        },
+        Precondition::Failed => {
+            w.status = PreconditionFailed;
+            w.headers.content_length = Some(0);
+            return;
+        },
+    }
+
+    let use_range = range_applies(&request.headers, Some(&etag), Some(&last_modified));
+    let (start, len) = match request.headers.range {
+        Some(ref range) if use_range => match byte_range(&range[..], size) {
+            Some(Ok((first, last))) => {
+                w.status = PartialContent;
+                w.headers.content_range = Some(format!("bytes {}-{}/{}", first, last, size));
+                w.headers.content_length = Some((last - first + 1) as usize);
+                (first, last - first + 1)
+            },
+            Some(Err(())) => {
+                w.status = RequestedRangeNotSatisfiable;
+                w.headers.content_range = Some(format!("bytes */{}", size));
+                w.headers.content_length = Some(0);
+                return;
+            },
+            None => (0, size),
+        },
+        _ => (0, size),
+    };
+
+    if request.method == Head {
+        return;
+    }
+    let mut file = match File::open(file_path) {
+        Ok(file) => file,
+        Err(err) => {
+            error!("opening {} failed: {}", file_path.display(), err);
+            w.status = InternalServerError;
+            w.headers.content_length = Some(0);
+            return;
+        },
+    };
+    if let Err(err) = copy_range(&mut file, start, len, w) {
+        // The headers are already sent, so there is nothing to do but give up.
+        error!("sending {} failed: {}", file_path.display(), err);
+    }
+}
+
+fn copy_range(file: &mut File, start: u64, len: u64, w: &mut ResponseWriter) -> IoResult<()> {
+    if start > 0 {
+        try!(file.seek(start as i64, SeekStyle::SeekSet));
+    }
+    let mut buf = [0u8; COPY_BUFFER_LEN];
+    let mut remaining = len;
+    while remaining > 0 {
+        let want = min(remaining, buf.len() as u64) as usize;
+        let amount = try!(file.read(&mut buf[..want]));
+        try!(w.write_all(&buf[..amount]));
+        remaining -= amount as u64;
+    }
+    Ok(())
+}
+
+/// Escape text for HTML.
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encode a file name for use in a relative link.
+fn encode_link(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for &b in name.as_bytes().iter() {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' =>
+                encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)[..]),
+        }
+    }
+    encoded
+}
+
+/// Render a listing of a directory's entries (names, with "/" after those of directories).
+fn render_listing(path: &[String], mut names: Vec<String>) -> String {
+    names.sort();
+    let title = escape_html(&format!("Index of /{}", path.connect("/"))[..]);
+    let mut html = format!("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title>\
+                            </head>\n<body>\n<h1>{0}</h1>\n<ul>\n", title);
+    if path.iter().any(|s| !s.is_empty()) {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in names.iter() {
+        let (base, slash) = if name.ends_with("/") {
+            (&name[..name.len() - 1], "/")
+        } else {
+            (&name[..], "")
+        };
+        html.push_str(&format!("<li><a href=\"{}{}\">{}</a></li>\n",
+                               encode_link(base), slash, escape_html(&name[..]))[..]);
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+fn send_listing(request: &Request, path: &[String], entries: Vec<Path>, w: &mut ResponseWriter) {
+    let names = entries.iter().filter_map(|entry| {
+        entry.filename_str().map(|name| {
+            if entry.is_dir() {
+                format!("{}/", name)
+            } else {
+                String::from_str(name)
+            }
+        })
+    }).collect();
+    let html = render_listing(path, names);
+    w.headers.content_type = Some(MediaType::new(String::from_str("text"), String::from_str("html"),
+        vec!((String::from_str("charset"), String::from_str("UTF-8")))));
+    w.headers.content_length = Some(html.len());
+    if request.method == Head {
+        return;
+    }
+    if let Err(err) = w.write_all(html.as_bytes()) {
+        error!("sending a listing failed: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{resolve, byte_range, content_type_for, render_listing};
+
+    fn segments(path: &str) -> Vec<String> {
+        path.split('/').map(String::from_str).collect()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let root = Path::new("/srv/www");
+        assert_eq!(resolve(&root, &segments("css/site.css")[..]),
+                   Some(Path::new("/srv/www/css/site.css")));
+        assert_eq!(resolve(&root, &segments("a/./b/")[..]), Some(Path::new("/srv/www/a/b")));
+        assert_eq!(resolve(&root, &segments("../etc/passwd")[..]), None);
+        assert_eq!(resolve(&root, &segments("a/../../etc")[..]), None);
+        // Decoded "%2F" and friends.
+        assert_eq!(resolve(&root, &[String::from_str("/etc/passwd")]), None);
+        assert_eq!(resolve(&root, &[String::from_str("..\\secret")]), None);
+    }
+
+    #[test]
+    fn test_byte_range() {
+        assert_eq!(byte_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(byte_range("bytes=900-", 1000), Some(Ok((900, 999))));
+        assert_eq!(byte_range("bytes=900-5000", 1000), Some(Ok((900, 999))));
+        assert_eq!(byte_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(byte_range("bytes=-5000", 1000), Some(Ok((0, 999))));
+        assert_eq!(byte_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(byte_range("bytes=-0", 1000), Some(Err(())));
+        assert_eq!(byte_range("bytes=5-1", 1000), None);
+        assert_eq!(byte_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(byte_range("lines=0-1", 1000), None);
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert_eq!(format!("{}", content_type_for(&Path::new("a/index.HTML"))),
+                   String::from_str("text/html;charset=UTF-8"));
+        assert_eq!(format!("{}", content_type_for(&Path::new("logo.png"))),
+                   String::from_str("image/png"));
+        assert_eq!(format!("{}", content_type_for(&Path::new("README"))),
+                   String::from_str("application/octet-stream"));
+    }
+
+    #[test]
+    fn test_render_listing() {
+        let html = render_listing(&segments("docs/"),
+                                  vec!(String::from_str("b <&>.txt"), String::from_str("a/")));
+        assert!(html.contains("<title>Index of /docs/</title>"));
+        assert!(html.contains("<li><a href=\"../\">../</a></li>\n\
+                               <li><a href=\"a/\">a/</a></li>\n\
+                               <li><a href=\"b%20%3C%26%3E.txt\">b &lt;&amp;&gt;.txt</a></li>\n"));
+    }
+}
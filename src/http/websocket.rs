@@ -0,0 +1,820 @@
+//! WebSocket (RFC 6455), on top of HTTP's protocol upgrades.
+//!
+//! On the server, `accept` checks a handshake request, answers it with `101 Switching Protocols`
+//! (see `ResponseWriter::upgrade`) and gives a `WebSocket` over the connection; on the client,
+//! `connect` makes the handshake through a `RequestWriter`. A `WebSocket` reads and writes whole
+//! messages, taking care of masking, fragmentation, answering pings and the closing handshake.
+//!
+//! ```ignore
+//! fn handle_request(&self, r: Request, w: &mut ResponseWriter) {
+//!     let mut socket = match websocket::accept(&r, w, None) {
+//!         Ok(socket) => socket,
+//!         // A refused handshake has had its response set up already.
+//!         Err(_) => return,
+//!     };
+//!     Thread::spawn(move || {
+//!         while let Ok(message) = socket.read_message() {
+//!             match message {
+//!                 Message::Text(..) | Message::Binary(..) => {
+//!                     if socket.write_message(message).is_err() { break }
+//!                 },
+//!                 _ => (),
+//!             }
+//!         }
+//!     });
+//! }
+//! ```
+
+use std::ascii::AsciiExt;
+use std::cmp::min;
+use std::error::FromError;
+use std::old_io::{IoError, IoResult, InvalidInput, Stream};
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use rand;
+use rustc_serialize::base64::{ToBase64, FromBase64, STANDARD};
+
+use buffer::BufferedStream;
+use client::RequestWriter;
+use headers::connection::Connection;
+use headers::typed::TypedHeader;
+use headers::upgrade::Protocol;
+use method::Method::Get;
+use server::{Request, ResponseWriter, Upgraded};
+use status::Status;
+use status::Status::{BadRequest, UpgradeRequired};
+
+/// The GUID which is appended to the handshake key to make the accept value (RFC 6455 §1.3).
+pub const GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The version of the protocol in Sec-WebSocket-Version; the only one supported.
+pub const VERSION: &'static str = "13";
+
+/// The default longest message a `WebSocket` will receive; see `WebSocket.max_message_len`.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 0x1000000;
+
+/// The longest payload of a control frame (ping, pong and close).
+const MAX_CONTROL_LEN: usize = 125;
+
+/// The Sec-WebSocket-Key request header: sixteen random bytes in base64.
+pub struct SecWebSocketKey;
+
+impl TypedHeader for SecWebSocketKey {
+    type Value = String;
+    fn header_name() -> &'static str { "Sec-WebSocket-Key" }
+}
+
+/// The Sec-WebSocket-Accept response header; see `accept_key`.
+pub struct SecWebSocketAccept;
+
+impl TypedHeader for SecWebSocketAccept {
+    type Value = String;
+    fn header_name() -> &'static str { "Sec-WebSocket-Accept" }
+}
+
+/// The Sec-WebSocket-Version header.
+pub struct SecWebSocketVersion;
+
+impl TypedHeader for SecWebSocketVersion {
+    type Value = String;
+    fn header_name() -> &'static str { "Sec-WebSocket-Version" }
+}
+
+/// The Sec-WebSocket-Protocol header: the subprotocols the client offers, or the one the server
+/// chose.
+pub struct SecWebSocketProtocol;
+
+impl TypedHeader for SecWebSocketProtocol {
+    type Value = String;
+    fn header_name() -> &'static str { "Sec-WebSocket-Protocol" }
+}
+
+/// The Sec-WebSocket-Accept value answering a Sec-WebSocket-Key: the base64 of the SHA-1 of the
+/// key and `GUID`.
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input_str(key);
+    hasher.input_str(GUID);
+    let mut digest = [0u8; 20];
+    hasher.result(&mut digest);
+    digest.to_base64(STANDARD)
+}
+
+/// The status codes of a close frame (RFC 6455 §7.4.1).
+pub mod close_code {
+    pub const NORMAL: u16 = 1000;
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const UNSUPPORTED_DATA: u16 = 1003;
+    pub const INVALID_DATA: u16 = 1007;
+    pub const POLICY_VIOLATION: u16 = 1008;
+    pub const MESSAGE_TOO_BIG: u16 = 1009;
+    pub const MANDATORY_EXTENSION: u16 = 1010;
+    pub const INTERNAL_ERROR: u16 = 1011;
+
+    /// Whether the code may be sent in a close frame: those defined for the protocol, and those
+    /// left to libraries and applications (3000–4999). 1005 and 1006 only stand for the lack of a
+    /// code and for a connection closed without a close frame.
+    pub fn is_valid(code: u16) -> bool {
+        match code {
+            1000...1003 | 1007...1011 | 3000...4999 => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    pub fn from_u8(opcode: u8) -> Option<Opcode> {
+        match opcode {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    /// Whether this is a control frame, which may come between the fragments of a message but may
+    /// not itself be fragmented.
+    pub fn is_control(self) -> bool {
+        self.as_u8() & 0x8 != 0
+    }
+}
+
+/// Mask or unmask data in place (the two are the same).
+pub fn apply_mask(mask: [u8; 4], data: &mut [u8]) {
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+/// A single frame (RFC 6455 §5.2).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Frame {
+    /// Whether this is the last frame of its message.
+    pub fin: bool,
+    /// The bits RSV1–3 (as the low three bits), which must be zero unless an extension says
+    /// otherwise.
+    pub rsv: u8,
+    pub opcode: Opcode,
+    /// The masking key; frames from a client are masked, frames from a server are not.
+    pub mask: Option<[u8; 4]>,
+    /// The payload, unmasked.
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Read a frame, refusing one with a payload longer than `max_len`.
+    pub fn read_from<R: Reader>(reader: &mut R, max_len: usize) -> Result<Frame, WebSocketError> {
+        let head = try!(reader.read_exact(2));
+        let opcode = match Opcode::from_u8(head[0] & 0xF) {
+            Some(opcode) => opcode,
+            None => return Err(WebSocketError::Protocol("unknown opcode")),
+        };
+        let len = match head[1] & 0x7F {
+            126 => try!(reader.read_be_u16()) as u64,
+            127 => {
+                let len = try!(reader.read_be_u64());
+                if len >> 63 != 0 {
+                    return Err(WebSocketError::Protocol("payload length out of range"));
+                }
+                len
+            },
+            len => len as u64,
+        };
+        let fin = head[0] & 0x80 != 0;
+        if opcode.is_control() && (!fin || len > MAX_CONTROL_LEN as u64) {
+            return Err(WebSocketError::Protocol("fragmented or overlong control frame"));
+        }
+        if len > max_len as u64 {
+            return Err(WebSocketError::TooBig);
+        }
+        let mask = if head[1] & 0x80 != 0 {
+            let key = try!(reader.read_exact(4));
+            Some([key[0], key[1], key[2], key[3]])
+        } else {
+            None
+        };
+        let mut payload = try!(reader.read_exact(len as usize));
+        if let Some(mask) = mask {
+            apply_mask(mask, &mut payload[..]);
+        }
+        Ok(Frame {
+            fin: fin,
+            rsv: (head[0] >> 4) & 0x7,
+            opcode: opcode,
+            mask: mask,
+            payload: payload,
+        })
+    }
+
+    /// Write the frame, masking the payload with `mask` if there is one.
+    pub fn write_to<W: Writer>(&self, writer: &mut W) -> IoResult<()> {
+        write_frame(writer, self.fin, self.rsv, self.opcode, self.mask, &self.payload[..])
+    }
+}
+
+fn write_frame<W: Writer>(writer: &mut W, fin: bool, rsv: u8, opcode: Opcode,
+                          mask: Option<[u8; 4]>, payload: &[u8]) -> IoResult<()> {
+    // The head and payload go out in one write.
+    let mut frame = Vec::with_capacity(14 + payload.len());
+    let fin_bit = if fin { 0x80 } else { 0 };
+    frame.push(fin_bit | (rsv & 0x7) << 4 | opcode.as_u8());
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len if len < 126 => frame.push(mask_bit | len as u8),
+        len if len < 0x10000 => {
+            frame.push(mask_bit | 126);
+            try!(frame.write_be_u16(len as u16));
+        },
+        len => {
+            frame.push(mask_bit | 127);
+            try!(frame.write_be_u64(len as u64));
+        },
+    }
+    let start = match mask {
+        Some(mask) => {
+            frame.push_all(&mask);
+            frame.len()
+        },
+        None => frame.len(),
+    };
+    frame.push_all(payload);
+    if let Some(mask) = mask {
+        apply_mask(mask, &mut frame[start..]);
+    }
+    writer.write_all(&frame[..])
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// A ping; one received has been answered with a pong already.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The closing handshake, with its status code and reason, if any. One received has been
+    /// answered already, and nothing more can be read.
+    Close(Option<u16>, String),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum WebSocketError {
+    /// Reading or writing the connection failed.
+    Io(IoError),
+    /// The other end broke the protocol; the connection has been closed with `PROTOCOL_ERROR`.
+    Protocol(&'static str),
+    /// A text message or a close reason was not UTF-8; the connection has been closed with
+    /// `INVALID_DATA`.
+    InvalidUtf8,
+    /// A message was longer than `WebSocket.max_message_len`; the connection has been closed with
+    /// `MESSAGE_TOO_BIG`.
+    TooBig,
+    /// The handshake was refused with this status: on the server, the one the response has been
+    /// given; on the client, the one the server answered with.
+    Handshake(Status),
+    /// The connection has been closed, and nothing more can be read or written.
+    Closed,
+}
+
+impl WebSocketError {
+    /// The status code to close the connection with on this error, if it is the other end's fault.
+    pub fn close_code(&self) -> Option<u16> {
+        match *self {
+            WebSocketError::Protocol(_) => Some(close_code::PROTOCOL_ERROR),
+            WebSocketError::InvalidUtf8 => Some(close_code::INVALID_DATA),
+            WebSocketError::TooBig => Some(close_code::MESSAGE_TOO_BIG),
+            _ => None,
+        }
+    }
+}
+
+impl FromError<IoError> for WebSocketError {
+    fn from_error(err: IoError) -> WebSocketError {
+        WebSocketError::Io(err)
+    }
+}
+
+/// Which end of the connection a `WebSocket` is; a client masks what it sends, and a server
+/// insists on receiving masked frames.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+/// A WebSocket connection whose handshake is done.
+pub struct WebSocket<S> {
+    stream: S,
+    role: Role,
+
+    /// The subprotocol agreed in the handshake, if any.
+    pub protocol: Option<String>,
+
+    /// The longest message which will be received, fragments and all; a longer one fails with
+    /// `TooBig`.
+    pub max_message_len: usize,
+
+    /// If set, messages written longer than this are sent in fragments of this size.
+    pub fragment_size: Option<usize>,
+
+    /// The opcode and payload so far of a fragmented message being received.
+    fragments: Option<(Opcode, Vec<u8>)>,
+
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl<S: Reader + Writer> WebSocket<S> {
+    /// A WebSocket over a connection whose handshake has already been made.
+    pub fn new(stream: S, role: Role) -> WebSocket<S> {
+        WebSocket {
+            stream: stream,
+            role: role,
+            protocol: None,
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+            fragment_size: None,
+            fragments: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// Read the next message, putting together one which comes in fragments. Pings are answered
+    /// and a close is echoed before they are returned.
+    ///
+    /// If the other end breaks the protocol, the connection is closed with the appropriate status
+    /// code (see `WebSocketError::close_code`) and nothing more can be read.
+    pub fn read_message(&mut self) -> Result<Message, WebSocketError> {
+        if self.close_received {
+            return Err(WebSocketError::Closed);
+        }
+        let result = self.read_message_unchecked();
+        if let Err(ref err) = result {
+            if let Some(code) = err.close_code() {
+                self.close_received = true;
+                if !self.close_sent {
+                    let _ = self.send_close(Some(code), "");
+                }
+            }
+        }
+        result
+    }
+
+    fn read_message_unchecked(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            let frame = try!(Frame::read_from(&mut self.stream, self.max_message_len));
+            if frame.rsv != 0 {
+                return Err(WebSocketError::Protocol("reserved bits set"));
+            }
+            match (self.role, frame.mask.is_some()) {
+                (Role::Server, false) => return Err(WebSocketError::Protocol("unmasked frame")),
+                (Role::Client, true) => return Err(WebSocketError::Protocol("masked frame")),
+                _ => (),
+            }
+            let opcode = frame.opcode;
+            match opcode {
+                Opcode::Ping => {
+                    if !self.close_sent {
+                        try!(self.send(Opcode::Pong, true, &frame.payload[..]));
+                        try!(self.stream.flush());
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                },
+                Opcode::Pong => return Ok(Message::Pong(frame.payload)),
+                Opcode::Close => {
+                    self.close_received = true;
+                    let (code, reason) = try!(parse_close(&frame.payload[..]));
+                    if !self.close_sent {
+                        // Echo the status code, as is usual (RFC 6455 §5.5.1).
+                        try!(self.send_close(code, ""));
+                    }
+                    return Ok(Message::Close(code, reason));
+                },
+                Opcode::Continuation => {
+                    match self.fragments {
+                        Some((_, ref mut data)) => {
+                            if data.len() + frame.payload.len() > self.max_message_len {
+                                return Err(WebSocketError::TooBig);
+                            }
+                            data.push_all(&frame.payload[..]);
+                        },
+                        None => return Err(WebSocketError::Protocol("nothing to continue")),
+                    }
+                    if frame.fin {
+                        let (opcode, data) = self.fragments.take().unwrap();
+                        return data_message(opcode, data);
+                    }
+                },
+                Opcode::Text | Opcode::Binary => {
+                    if self.fragments.is_some() {
+                        return Err(WebSocketError::Protocol("message interrupted by another"));
+                    }
+                    if frame.fin {
+                        return data_message(opcode, frame.payload);
+                    }
+                    self.fragments = Some((opcode, frame.payload));
+                },
+            }
+        }
+    }
+
+    /// Send a message, in fragments if `fragment_size` calls for it. Control messages (ping, pong
+    /// and close) may carry no more than 125 bytes, a close reason no more than 123.
+    ///
+    /// Once a close has been sent, nothing more can be.
+    pub fn write_message(&mut self, message: Message) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+        try!(match message {
+            Message::Text(text) => self.send_data(Opcode::Text, text.as_bytes()),
+            Message::Binary(data) => self.send_data(Opcode::Binary, &data[..]),
+            Message::Ping(data) => self.send(Opcode::Ping, true, &data[..]),
+            Message::Pong(data) => self.send(Opcode::Pong, true, &data[..]),
+            Message::Close(code, reason) => self.send_close(code, &reason[..]),
+        });
+        try!(self.stream.flush());
+        Ok(())
+    }
+
+    /// Start the closing handshake; the other end's answer is the `Message::Close` that
+    /// `read_message` gives after any messages still on their way.
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        self.write_message(Message::Close(Some(code), String::from_str(reason)))
+    }
+
+    fn send_data(&mut self, opcode: Opcode, data: &[u8]) -> IoResult<()> {
+        let size = match self.fragment_size {
+            Some(size) if size > 0 => size,
+            _ => data.len(),
+        };
+        let mut opcode = opcode;
+        let mut rest = data;
+        loop {
+            let amount = min(size, rest.len());
+            let fin = amount == rest.len();
+            try!(self.send(opcode, fin, &rest[..amount]));
+            if fin {
+                return Ok(());
+            }
+            rest = &rest[amount..];
+            opcode = Opcode::Continuation;
+        }
+    }
+
+    fn send_close(&mut self, code: Option<u16>, reason: &str) -> IoResult<()> {
+        let mut payload = Vec::new();
+        if let Some(code) = code {
+            payload.push((code >> 8) as u8);
+            payload.push(code as u8);
+            payload.push_all(reason.as_bytes());
+        }
+        try!(self.send(Opcode::Close, true, &payload[..]));
+        self.close_sent = true;
+        self.stream.flush()
+    }
+
+    /// Write a single frame, masked if this is the client.
+    fn send(&mut self, opcode: Opcode, fin: bool, payload: &[u8]) -> IoResult<()> {
+        if opcode.is_control() && payload.len() > MAX_CONTROL_LEN {
+            return Err(IoError {
+                kind: InvalidInput,
+                desc: "control frame payload longer than 125 bytes",
+                detail: None,
+            });
+        }
+        let mask = match self.role {
+            Role::Client => Some(rand::random()),
+            Role::Server => None,
+        };
+        write_frame(&mut self.stream, fin, 0, opcode, mask, payload)
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+fn data_message(opcode: Opcode, data: Vec<u8>) -> Result<Message, WebSocketError> {
+    match opcode {
+        Opcode::Text => match String::from_utf8(data) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => Err(WebSocketError::InvalidUtf8),
+        },
+        _ => Ok(Message::Binary(data)),
+    }
+}
+
+/// The status code and reason of a close frame's payload.
+fn parse_close(payload: &[u8]) -> Result<(Option<u16>, String), WebSocketError> {
+    if payload.is_empty() {
+        return Ok((None, String::new()));
+    }
+    if payload.len() == 1 {
+        return Err(WebSocketError::Protocol("truncated close code"));
+    }
+    let code = (payload[0] as u16) << 8 | payload[1] as u16;
+    if !close_code::is_valid(code) {
+        return Err(WebSocketError::Protocol("invalid close code"));
+    }
+    match String::from_utf8(payload[2..].to_vec()) {
+        Ok(reason) => Ok((Some(code), reason)),
+        Err(_) => Err(WebSocketError::InvalidUtf8),
+    }
+}
+
+/// The subprotocols offered in a handshake request, in the client's order of preference.
+pub fn offered_protocols(request: &Request) -> Vec<String> {
+    match request.headers.get::<SecWebSocketProtocol>() {
+        Some(protocols) => protocols.split(',')
+                                    .map(|p| String::from_str(p.trim()))
+                                    .filter(|p| !p.is_empty())
+                                    .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// The Sec-WebSocket-Key of a valid handshake request, or the status to refuse it with.
+fn handshake_key(request: &Request) -> Result<String, Status> {
+    if request.method != Get || !request.wants_upgrade("websocket") {
+        return Err(BadRequest);
+    }
+    match request.headers.get::<SecWebSocketVersion>() {
+        Some(ref version) if version.trim() == VERSION => (),
+        _ => return Err(UpgradeRequired),
+    }
+    let key = match request.headers.get::<SecWebSocketKey>() {
+        Some(key) => String::from_str(key.trim()),
+        None => return Err(BadRequest),
+    };
+    match key.from_base64() {
+        Ok(ref bytes) if bytes.len() == 16 => Ok(key),
+        _ => Err(BadRequest),
+    }
+}
+
+/// Complete the server's side of a handshake: check the request, answer it and take over the
+/// connection. `protocol` is the subprotocol chosen, which must be one of `offered_protocols`.
+///
+/// If the request is not a valid handshake, the response is set up to refuse it (`400 Bad
+/// Request`, or `426 Upgrade Required` for an unsupported version) and this fails with
+/// `Handshake`; the handler need only return.
+pub fn accept(request: &Request, response: &mut ResponseWriter, protocol: Option<&str>)
+        -> Result<WebSocket<Upgraded>, WebSocketError> {
+    let key = match handshake_key(request) {
+        Ok(key) => key,
+        Err(status) => {
+            if status == UpgradeRequired {
+                response.headers.set::<SecWebSocketVersion>(String::from_str(VERSION));
+                response.headers.upgrade =
+                    Some(vec!(Protocol::new(String::from_str("websocket"), None)));
+                response.headers.connection =
+                    Some(vec!(Connection::Token(String::from_str("Upgrade"))));
+            }
+            response.status = status.clone();
+            response.headers.content_length = Some(0);
+            return Err(WebSocketError::Handshake(status));
+        },
+    };
+    response.headers.set::<SecWebSocketAccept>(accept_key(&key[..]));
+    if let Some(protocol) = protocol {
+        response.headers.set::<SecWebSocketProtocol>(String::from_str(protocol));
+    }
+    let stream = try!(response.upgrade(Protocol::new(String::from_str("websocket"), None)));
+    let mut socket = WebSocket::new(stream, Role::Server);
+    socket.protocol = protocol.map(|p| String::from_str(p));
+    Ok(socket)
+}
+
+/// Make the client's side of a handshake through `request`, which is sent as a GET with the
+/// handshake headers added, offering `protocols` as subprotocols. The server's choice is in
+/// `WebSocket.protocol`.
+///
+/// If the server does not switch protocols, this fails with `Handshake` and its status.
+pub fn connect<S: Stream>(mut request: RequestWriter<S>, protocols: &[&str])
+        -> Result<WebSocket<BufferedStream<S>>, WebSocketError> {
+    let key_bytes: [u8; 16] = rand::random();
+    let key = key_bytes.to_base64(STANDARD);
+    request.method = Get;
+    request.decompress = false;
+    request.headers.upgrade = Some(vec!(Protocol::new(String::from_str("websocket"), None)));
+    request.headers.connection = Some(vec!(Connection::Token(String::from_str("Upgrade"))));
+    request.headers.set::<SecWebSocketKey>(key.clone());
+    request.headers.set::<SecWebSocketVersion>(String::from_str(VERSION));
+    if !protocols.is_empty() {
+        request.headers.set::<SecWebSocketProtocol>(protocols.connect(", "));
+    }
+
+    let response = match request.read_response() {
+        Ok(response) => response,
+        Err((_, err)) => return Err(WebSocketError::Io(err)),
+    };
+    if response.status.code() != 101 {
+        return Err(WebSocketError::Handshake(response.status.clone()));
+    }
+    let upgraded = match response.headers.upgrade {
+        Some(ref protocols) => protocols.iter().any(|p| p.is("websocket")),
+        None => false,
+    };
+    if !upgraded {
+        return Err(WebSocketError::Protocol("switched to another protocol"));
+    }
+    match response.headers.get::<SecWebSocketAccept>() {
+        Some(ref accept) if accept.trim() == &accept_key(&key[..])[..] => (),
+        _ => return Err(WebSocketError::Protocol("wrong Sec-WebSocket-Accept")),
+    }
+    let protocol = response.headers.get::<SecWebSocketProtocol>()
+                                   .map(|p| String::from_str(p.trim()));
+    if let Some(ref protocol) = protocol {
+        if !protocols.iter().any(|p| p.eq_ignore_ascii_case(&protocol[..])) {
+            return Err(WebSocketError::Protocol("subprotocol not offered"));
+        }
+    }
+    let status = response.status.clone();
+    match response.into_stream() {
+        Ok(stream) => {
+            let mut socket = WebSocket::new(stream, Role::Client);
+            socket.protocol = protocol;
+            Ok(socket)
+        },
+        Err(_) => Err(WebSocketError::Handshake(status)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::iter::repeat;
+    use std::old_io::{MemReader, IoResult};
+    use super::{accept_key, apply_mask, close_code, Frame, Opcode, Message, WebSocket,
+                WebSocketError, Role};
+
+    /// Reads what was given and keeps what is written.
+    struct Pipe {
+        input: MemReader,
+        output: Vec<u8>,
+    }
+
+    impl Reader for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Writer for Pipe {
+        fn write_all(&mut self, buf: &[u8]) -> IoResult<()> {
+            self.output.write_all(buf)
+        }
+    }
+
+    fn websocket(input: &[u8], role: Role) -> WebSocket<Pipe> {
+        WebSocket::new(Pipe { input: MemReader::new(input.to_vec()), output: Vec::new() }, role)
+    }
+
+    /// The frames written, unmasked.
+    fn written(socket: WebSocket<Pipe>) -> Vec<Frame> {
+        let mut reader = MemReader::new(socket.into_inner().output);
+        let mut frames = Vec::new();
+        while let Ok(frame) = Frame::read_from(&mut reader, 0x10000) {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // RFC 6455 §1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_frames() {
+        // The examples of RFC 6455 §5.7.
+        let hello = Frame { fin: true, rsv: 0, opcode: Opcode::Text, mask: None,
+                            payload: b"Hello".to_vec() };
+        let mut out = Vec::new();
+        hello.write_to(&mut out).unwrap();
+        assert_eq!(out, b"\x81\x05Hello".to_vec());
+
+        let masked = b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+        let frame = Frame::read_from(&mut MemReader::new(masked.to_vec()), 125).unwrap();
+        assert_eq!(frame.mask, Some([0x37, 0xfa, 0x21, 0x3d]));
+        assert_eq!(frame.payload, b"Hello".to_vec());
+        let mut out = Vec::new();
+        frame.write_to(&mut out).unwrap();
+        assert_eq!(out, masked.to_vec());
+
+        // Each form of the length.
+        for &len in [0, 125, 126, 0xFFFF, 0x10000].iter() {
+            let frame = Frame { fin: false, rsv: 0, opcode: Opcode::Binary,
+                                mask: Some([1, 2, 3, 4]), payload: repeat(7u8).take(len).collect() };
+            let mut out = Vec::new();
+            frame.write_to(&mut out).unwrap();
+            assert_eq!(Frame::read_from(&mut MemReader::new(out), 0x10000), Ok(frame));
+        }
+
+        let mut data = b"Hello".to_vec();
+        apply_mask([0x37, 0xfa, 0x21, 0x3d], &mut data[..]);
+        assert_eq!(data, b"\x7f\x9f\x4d\x51\x58".to_vec());
+
+        let big = b"\x82\x7e\x01\x00";
+        assert_eq!(Frame::read_from(&mut MemReader::new(big.to_vec()), 255),
+                   Err(WebSocketError::TooBig));
+        let long_ping = b"\x89\x7e\x00\x7e";
+        assert_eq!(Frame::read_from(&mut MemReader::new(long_ping.to_vec()), 255),
+                   Err(WebSocketError::Protocol("fragmented or overlong control frame")));
+    }
+
+    #[test]
+    fn test_fragments_and_ping() {
+        // A ping may come between the fragments of a message.
+        let mut socket = websocket(b"\x01\x03Hel\x89\x05Hello\x80\x02lo", Role::Client);
+        assert_eq!(socket.read_message(), Ok(Message::Ping(b"Hello".to_vec())));
+        assert_eq!(socket.read_message(), Ok(Message::Text(String::from_str("Hello"))));
+        let frames = written(socket);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].opcode, Opcode::Pong);
+        assert!(frames[0].mask.is_some());
+        assert_eq!(frames[0].payload, b"Hello".to_vec());
+
+        let mut socket = websocket(b"", Role::Server);
+        socket.fragment_size = Some(2);
+        socket.write_message(Message::Text(String::from_str("Hello"))).unwrap();
+        assert_eq!(socket.into_inner().output, b"\x01\x02He\x00\x02ll\x80\x01o".to_vec());
+
+        let mut socket = websocket(b"\x01\x03Hel\x81\x02lo", Role::Client);
+        assert_eq!(socket.read_message(),
+                   Err(WebSocketError::Protocol("message interrupted by another")));
+    }
+
+    #[test]
+    fn test_close() {
+        let mut socket = websocket(b"\x88\x05\x03\xe8bye", Role::Client);
+        assert_eq!(socket.read_message(),
+                   Ok(Message::Close(Some(close_code::NORMAL), String::from_str("bye"))));
+        assert_eq!(socket.read_message(), Err(WebSocketError::Closed));
+        assert_eq!(socket.write_message(Message::Binary(vec![1])), Err(WebSocketError::Closed));
+        let frames = written(socket);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].opcode, Opcode::Close);
+        assert_eq!(frames[0].payload, b"\x03\xe8".to_vec());
+
+        // 1005 is never sent.
+        let mut socket = websocket(b"\x88\x02\x03\xed", Role::Client);
+        assert_eq!(socket.read_message(),
+                   Err(WebSocketError::Protocol("invalid close code")));
+    }
+
+    #[test]
+    fn test_errors() {
+        // A server requires masked frames, and closes with the appropriate code when it is sent
+        // something wrong.
+        let mut socket = websocket(b"\x81\x05Hello", Role::Server);
+        assert_eq!(socket.read_message(), Err(WebSocketError::Protocol("unmasked frame")));
+        assert_eq!(socket.read_message(), Err(WebSocketError::Closed));
+        assert_eq!(socket.into_inner().output, b"\x88\x02\x03\xea".to_vec());
+
+        let mut socket = websocket(b"\x81\x02\xc3\x28", Role::Client);
+        assert_eq!(socket.read_message(), Err(WebSocketError::InvalidUtf8));
+        assert_eq!(written(socket)[0].payload, b"\x03\xef".to_vec());
+
+        let mut socket = websocket(b"\x01\x03Hel\x80\x02lo", Role::Client);
+        socket.max_message_len = 4;
+        assert_eq!(socket.read_message(), Err(WebSocketError::TooBig));
+        assert_eq!(written(socket)[0].payload, b"\x03\xf1".to_vec());
+
+        let mut socket = websocket(b"\xc1\x00", Role::Client);
+        assert_eq!(socket.read_message(), Err(WebSocketError::Protocol("reserved bits set")));
+    }
+}